@@ -0,0 +1,41 @@
+//! Decode the same wave data compressed with each codec. Run with
+//! `cargo bench -p fst --features bench`. The wave is the one with the most
+//! data in `samples/rv64ui-p-add.converted.fst`, or in the file given by
+//! `WAVERY_BENCH_FILE`.
+
+use std::path::{Path, PathBuf};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use fst::{codecs::PACKTYPES, fst::Fst};
+
+fn bench_codecs(c: &mut Criterion) {
+    let path = std::env::var_os("WAVERY_BENCH_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("../samples/rv64ui-p-add.converted.fst")
+        });
+    let fst = Fst::load(&path).unwrap();
+
+    let (varid, _) = fst
+        .var_data
+        .iter_enumerated()
+        .max_by_key(|(_, var_data)| {
+            var_data
+                .wave_slices
+                .iter()
+                .map(|slice| slice.end - slice.start)
+                .sum::<u64>()
+        })
+        .expect("No vars");
+
+    let mut group = c.benchmark_group("decode_wave");
+    for packtype in PACKTYPES {
+        let wave = fst.recompress_wave(varid, packtype).unwrap();
+        let name = format!("{} ({} bytes)", packtype as char, wave.compressed_length());
+        group.bench_function(name, |b| b.iter(|| wave.decode().unwrap()));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_codecs);
+criterion_main!(benches);
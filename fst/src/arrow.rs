@@ -0,0 +1,146 @@
+//! Apache Arrow export, so waves can be used with e.g. DataFusion or Polars
+//! without going through CSV.
+
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::Result;
+use arrow_array::{
+    builder::{Float64Builder, StringDictionaryBuilder, UInt64Builder},
+    types::Int32Type,
+    ArrayRef, RecordBatch, UInt64Array,
+};
+use arrow_schema::{DataType, Field, Schema};
+
+use crate::{
+    fst::{Fst, VarId, VarLength},
+    valvec::Value,
+    Waves,
+};
+
+impl Fst {
+    /// Read the given vars into a record batch. Like `export_csv()` there is a
+    /// `time` column with a row for every time at which any of them changes,
+    /// and a column for each var giving its value at that time (null if it
+    /// doesn't have one yet).
+    ///
+    /// Reals are `Float64` and buses of up to 64 bits are `UInt64`. Buses that
+    /// are wider, or that are ever X or Z, are dictionary encoded strings as
+    /// from `Value::bits_string()`.
+    pub fn to_arrow(&self, varids: &[VarId]) -> Result<RecordBatch> {
+        // The value of each var at each row.
+        let mut times = Vec::new();
+        let mut columns: Vec<Vec<Option<Value>>> = vec![Vec::new(); varids.len()];
+        let mut current = HashMap::new();
+        for (time, changes) in self.merged_changes(varids)? {
+            current.extend(changes);
+            times.push(time);
+            for (column, varid) in columns.iter_mut().zip(varids) {
+                column.push(current.get(varid).cloned());
+            }
+        }
+
+        let mut fields = vec![Field::new("time", DataType::UInt64, false)];
+        let mut arrays: Vec<ArrayRef> = vec![Arc::new(UInt64Array::from(times))];
+        for (column, &varid) in columns.iter().zip(varids) {
+            let name = self.variable_info(varid)?.name;
+            let array = self.arrow_column(column, self.var_lengths.length(varid));
+            fields.push(Field::new(name, array.data_type().clone(), true));
+            arrays.push(array);
+        }
+
+        Ok(RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays)?)
+    }
+
+    fn arrow_column(&self, column: &[Option<Value>], var_length: VarLength) -> ArrayRef {
+        match var_length {
+            VarLength::Real => {
+                let mut builder = Float64Builder::with_capacity(column.len());
+                for value in column {
+                    builder.append_option(value.as_ref().and_then(Value::to_f64));
+                }
+                Arc::new(builder.finish())
+            }
+            VarLength::Bits(bits) => {
+                let integers = column
+                    .iter()
+                    .flatten()
+                    .all(|value| value.to_u64(bits).is_some());
+                if integers {
+                    let mut builder = UInt64Builder::with_capacity(column.len());
+                    for value in column {
+                        builder.append_option(value.as_ref().and_then(|v| v.to_u64(bits)));
+                    }
+                    Arc::new(builder.finish())
+                } else {
+                    let mut builder = StringDictionaryBuilder::<Int32Type>::new();
+                    for value in column {
+                        builder.append_option(value.as_ref().map(|v| v.bits_string(bits)));
+                    }
+                    Arc::new(builder.finish())
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use arrow_array::{
+        cast::AsArray,
+        types::{Float64Type, UInt64Type},
+        Array,
+    };
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_to_arrow() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(
+            b"$timescale 1 ns $end
+$scope module top $end
+$var wire 1 ! clk $end
+$var wire 3 \" bus [2:0] $end
+$var real 64 # level $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+bx01 \"
+#10
+1!
+r1.5 #
+#15
+b1z0 \"
+",
+        )
+        .unwrap();
+        let fst = crate::vcd::load(file.path()).unwrap();
+
+        let batch = fst.to_arrow(&[VarId(0), VarId(1), VarId(2)]).unwrap();
+        let schema = batch.schema();
+        let names: Vec<_> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+        assert_eq!(names, ["time", "clk", "bus [2:0]", "level"]);
+
+        let time = batch.column(0).as_primitive::<UInt64Type>();
+        assert_eq!(time.values(), &[0, 10, 15]);
+
+        let clk = batch.column(1).as_primitive::<UInt64Type>();
+        assert_eq!(clk.values(), &[0, 1, 1]);
+
+        let bus = batch.column(2).as_dictionary::<Int32Type>();
+        let bus_values = bus.values().as_string::<i32>();
+        let bus: Vec<_> = bus
+            .keys()
+            .iter()
+            .map(|k| bus_values.value(k.unwrap() as usize))
+            .collect();
+        assert_eq!(bus, ["x01", "x01", "1z0"]);
+
+        let level = batch.column(3).as_primitive::<Float64Type>();
+        assert!(level.is_null(0));
+        assert_eq!(level.value(1), 1.5);
+        assert_eq!(level.value(2), 1.5);
+    }
+}
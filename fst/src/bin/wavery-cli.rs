@@ -0,0 +1,128 @@
+//! Command line access to FST and VCD files, for scripts and CI.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use fst::{
+    fst::{Fst, ScopeId, VarId, VarLength},
+    valvec::Radix,
+};
+
+#[derive(Parser)]
+#[command(name = "wavery-cli", about = "Inspect and convert FST and VCD files")]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print an overview of the file.
+    Info { file: PathBuf },
+    /// Print the scopes and vars.
+    Tree { file: PathBuf },
+    /// Print the value changes of a var, e.g. `top.cpu.pc`.
+    Dump {
+        file: PathBuf,
+        signal: String,
+        /// How to show bit values: bin, oct, hex, dec or sdec.
+        #[arg(long, default_value_t = Radix::Bin)]
+        radix: Radix,
+    },
+    /// Print a hash of the hierarchy and value changes, ignoring when and by
+    /// what the file was written.
+    Hash { file: PathBuf },
+    /// Convert the file to VCD.
+    Vcd {
+        file: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+
+    match Args::parse().command {
+        Command::Info { file } => {
+            let fst = load(&file)?;
+            println!("{}", fst.summary());
+        }
+        Command::Tree { file } => {
+            let fst = load(&file)?;
+            let mut out = io::stdout().lock();
+            // There can be more than one root.
+            for (id, node) in fst.hierarchy.iter().enumerate() {
+                if node.parent().0 == id {
+                    write_tree(&mut out, &fst, ScopeId(id), 0)?;
+                }
+            }
+        }
+        Command::Dump {
+            file,
+            signal,
+            radix,
+        } => {
+            let fst = load(&file)?;
+            let varid = fst
+                .var_by_path(&signal)
+                .with_context(|| format!("No var called {signal}"))?;
+            let wave = fst.read_wave(varid)?;
+            let var_length = fst.var_lengths.length(varid);
+            let mut out = io::stdout().lock();
+            for (time, value) in wave {
+                let value = match var_length {
+                    VarLength::Bits(bits) => value.format_radix(bits, radix),
+                    VarLength::Real => value.format_real(),
+                };
+                writeln!(out, "{time} {value}")?;
+            }
+        }
+        Command::Hash { file } => {
+            let fst = load(&file)?;
+            println!("{:016x}", fst.content_hash()?);
+        }
+        Command::Vcd { file, output } => {
+            let fst = load(&file)?;
+            let varids: Vec<VarId> = (0..fst.var_lengths.lengths.len()).map(VarId).collect();
+            let out = File::create(&output)
+                .with_context(|| format!("Couldn't create {}", output.display()))?;
+            let mut out = BufWriter::new(out);
+            fst.write_vcd(&mut out, &varids)?;
+            out.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Load an FST file, or a VCD file if it has a `.vcd` extension.
+fn load(filename: &Path) -> Result<Fst> {
+    let is_vcd = filename
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("vcd"));
+    if is_vcd {
+        fst::vcd::load(filename)
+    } else {
+        Fst::load(filename)
+    }
+    .with_context(|| format!("Couldn't load {}", filename.display()))
+}
+
+fn write_tree(out: &mut impl Write, fst: &Fst, scope: ScopeId, depth: usize) -> Result<()> {
+    let indent = "  ".repeat(depth);
+    let node = fst.hierarchy.get(scope).context("Invalid scope ID")?;
+    writeln!(out, "{indent}{}", node.value.name)?;
+    for var in &node.value.vars {
+        let alias = if var.is_alias { " (alias)" } else { "" };
+        writeln!(out, "{indent}  {}{alias}", var.name)?;
+    }
+    for (child, _) in fst.hierarchy.children(scope) {
+        write_tree(out, fst, child, depth + 1)?;
+    }
+    Ok(())
+}
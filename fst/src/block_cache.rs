@@ -0,0 +1,177 @@
+//! Keeping the wave data of Value Change blocks in memory, and reading it in
+//! ahead of time.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::BufReader,
+    ops::Range,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+
+use crate::fst::{read_range, BlockId};
+
+/// The wave data (the `BlockRegion::Waves` region, still compressed) of
+/// recently used Value Change blocks, so reading waves from them doesn't
+/// have to go to the file. When it holds more than `max_bytes` the least
+/// recently used blocks are dropped. It is empty unless something fills it,
+/// e.g. a `BlockPrefetcher`.
+#[derive(Debug, Default)]
+pub struct BlockCache {
+    /// Least recently used first.
+    blocks: VecDeque<(BlockId, Arc<Vec<u8>>)>,
+    /// The total length of `blocks`.
+    bytes: u64,
+    max_bytes: u64,
+}
+
+impl BlockCache {
+    /// Get the wave data of a block if it is cached, marking it as the most
+    /// recently used.
+    pub fn get(&mut self, block: BlockId) -> Option<Arc<Vec<u8>>> {
+        let index = self.blocks.iter().position(|(id, _)| *id == block)?;
+        let entry = self.blocks.remove(index)?;
+        let data = entry.1.clone();
+        self.blocks.push_back(entry);
+        Some(data)
+    }
+
+    /// Add the wave data of a block as the most recently used, dropping
+    /// others if needed. Blocks bigger than `max_bytes` aren't added.
+    pub fn insert(&mut self, block: BlockId, data: Vec<u8>) {
+        if let Some(index) = self.blocks.iter().position(|(id, _)| *id == block) {
+            let (_, old) = self.blocks.remove(index).unwrap();
+            self.bytes -= old.len() as u64;
+        }
+        if data.len() as u64 > self.max_bytes {
+            return;
+        }
+        self.bytes += data.len() as u64;
+        self.blocks.push_back((block, Arc::new(data)));
+        self.evict();
+    }
+
+    /// Whether `bytes` more would fit without dropping anything.
+    pub fn has_room_for(&self, bytes: u64) -> bool {
+        self.bytes + bytes <= self.max_bytes
+    }
+
+    /// Set the most bytes to hold, dropping blocks if it is already over.
+    pub fn set_max_bytes(&mut self, max_bytes: u64) {
+        self.max_bytes = max_bytes;
+        self.evict();
+    }
+
+    /// The total length of the cached wave data.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.bytes = 0;
+    }
+
+    fn evict(&mut self) {
+        while self.bytes > self.max_bytes {
+            let Some((_, data)) = self.blocks.pop_front() else {
+                break;
+            };
+            self.bytes -= data.len() as u64;
+        }
+    }
+}
+
+/// Reads the wave data of each Value Change block of a file into its
+/// `BlockCache` in time order, one block per call, so that scrolling through
+/// the waves finds them already read. It has its own file handle so it can be
+/// used from another thread. From `Fst::block_prefetcher()`.
+#[derive(Debug)]
+pub struct BlockPrefetcher {
+    pub(crate) filename: PathBuf,
+    pub(crate) reader: Option<BufReader<File>>,
+    pub(crate) cache: Arc<Mutex<BlockCache>>,
+    /// The blocks that haven't been read yet and where their wave data is,
+    /// latest first.
+    pub(crate) blocks: Vec<(BlockId, Range<u64>)>,
+}
+
+impl BlockPrefetcher {
+    /// Read the next block into the cache, unless it is already there.
+    /// Returns false once every block has been read, or if the next one
+    /// doesn't fit in the cache without dropping blocks that were read
+    /// before, since those are what reading it was for.
+    pub fn prefetch_next(&mut self) -> Result<bool> {
+        let Some((block, range)) = self.blocks.last().cloned() else {
+            return Ok(false);
+        };
+        {
+            let mut cache = self.cache.lock().unwrap();
+            if cache.get(block).is_some() {
+                self.blocks.pop();
+                return Ok(true);
+            }
+            if !cache.has_room_for(range.end - range.start) {
+                return Ok(false);
+            }
+        }
+
+        if self.reader.is_none() {
+            let file = File::open(&self.filename).with_context(|| {
+                format!("Couldn't reopen {} to prefetch", self.filename.display())
+            })?;
+            self.reader = Some(BufReader::new(file));
+        }
+        let data = read_range(self.reader.as_mut().unwrap(), range)?;
+        self.cache.lock().unwrap().insert(block, data);
+        self.blocks.pop();
+        Ok(true)
+    }
+
+    /// The number of blocks left to read.
+    pub fn remaining(&self) -> usize {
+        self.blocks.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_block_cache() {
+        let block = BlockId::from;
+        let mut cache = BlockCache::default();
+        cache.insert(block(0), vec![0; 10]);
+        assert_eq!(cache.get(block(0)), None);
+
+        cache.set_max_bytes(25);
+        cache.insert(block(0), vec![0; 10]);
+        cache.insert(block(1), vec![1; 10]);
+        assert_eq!(cache.bytes(), 20);
+        assert!(cache.has_room_for(5));
+        assert!(!cache.has_room_for(6));
+
+        // Using block 0 makes block 1 the least recently used.
+        assert_eq!(cache.get(block(0)).unwrap()[0], 0);
+        cache.insert(block(2), vec![2; 10]);
+        assert_eq!(cache.get(block(1)), None);
+        assert!(cache.get(block(0)).is_some());
+        assert_eq!(cache.bytes(), 20);
+
+        // Too big to fit at all.
+        cache.insert(block(3), vec![3; 30]);
+        assert_eq!(cache.get(block(3)), None);
+        assert_eq!(cache.bytes(), 20);
+
+        cache.set_max_bytes(10);
+        assert_eq!(cache.get(block(2)), None);
+        assert!(cache.get(block(0)).is_some());
+
+        cache.clear();
+        assert_eq!(cache.bytes(), 0);
+    }
+}
@@ -0,0 +1,157 @@
+//! Support for benchmarking the wave codecs against each other on real data,
+//! e.g. to decide whether a writer should use a different one by default. A
+//! var's wave data is decompressed and recompressed with a given codec, and
+//! can then be decoded with that codec's decompressor as `read_wave()` would.
+//!
+//! This is only for benchmarks and tests, so it needs the `bench` feature.
+
+use std::io::Write;
+
+use anyhow::{Context, Result};
+use flate2::{write::ZlibEncoder, Compression};
+
+use crate::{
+    fst::{decode_wave_slice, decompress_wave_data, to_usize, BlockRegion, Fst, VarId, VarLength},
+    valvec::ValAndTimeVec,
+    varint::{encode_varint, VarintReader},
+};
+
+/// The `waves_packtype` of each codec: FastLZ, LZ4 and ZLib.
+pub const PACKTYPES: [u8; 3] = [b'F', b'4', b'Z'];
+
+/// A var's wave data, recompressed with one codec. See
+/// `Fst::recompress_wave()`.
+pub struct RecompressedWave {
+    var_length: VarLength,
+    real_is_little_endian: bool,
+    packtype: u8,
+    /// The change times of each Value Change block that the var changes in,
+    /// and the var's recompressed data in it.
+    blocks: Vec<(Vec<u64>, Vec<u8>)>,
+}
+
+impl RecompressedWave {
+    /// The total size of the var's data in all the blocks.
+    pub fn compressed_length(&self) -> usize {
+        self.blocks.iter().map(|(_, data)| data.len()).sum()
+    }
+
+    /// Decode the changes as `read_wave()` does, using the decompressor for
+    /// the codec. The initial value isn't included.
+    pub fn decode(&self) -> Result<ValAndTimeVec> {
+        let mut wave = ValAndTimeVec::new();
+        for (times, data) in &self.blocks {
+            decode_wave_slice(
+                data,
+                self.packtype,
+                times,
+                self.var_length,
+                false,
+                self.real_is_little_endian,
+                &mut wave,
+            )?;
+        }
+        Ok(wave)
+    }
+}
+
+impl Fst {
+    /// Decompress a var's wave data in every Value Change block, then
+    /// compress it again with the codec for `packtype` (one of `PACKTYPES`).
+    /// Unlike a writer, this always compresses it, even if that makes it
+    /// bigger.
+    pub fn recompress_wave(&self, varid: VarId, packtype: u8) -> Result<RecompressedWave> {
+        self.check_blocks_parsed()?;
+        let var_data = self.var_data.get(varid).context("Invalid var ID")?;
+        let mut blocks = Vec::new();
+        for ((block_id, block), wave_slice) in self
+            .value_change_blocks
+            .iter_enumerated()
+            .zip(&var_data.wave_slices)
+        {
+            if wave_slice.is_empty() {
+                continue;
+            }
+            let waves = self.raw_block_bytes(block_id, BlockRegion::Waves)?;
+            let mut data = waves
+                .get(wave_slice.start as usize..wave_slice.end as usize)
+                .context("Wave data is outside the block")?;
+            let uncompressed_length =
+                to_usize(data.read_varint()?, "Uncompressed wave data length")?;
+            let uncompressed_data = match uncompressed_length {
+                0 => data.to_vec(),
+                _ => decompress_wave_data(data, uncompressed_length, block.info.waves_packtype)?,
+            };
+
+            let mut recompressed = vec![0; 10];
+            let varint_length = encode_varint(&mut recompressed, uncompressed_data.len() as u64);
+            recompressed.truncate(varint_length);
+            recompressed.extend(compress_wave_data(&uncompressed_data, packtype)?);
+
+            let times = self.time_index().times(block_id).to_vec();
+            blocks.push((times, recompressed));
+        }
+        Ok(RecompressedWave {
+            var_length: self.var_lengths.length(varid),
+            real_is_little_endian: self.header.real_is_little_endian(),
+            packtype,
+            blocks,
+        })
+    }
+}
+
+/// Compress wave data with the codec for `packtype`, the opposite of
+/// `decompress_wave_data()`.
+fn compress_wave_data(data: &[u8], packtype: u8) -> Result<Vec<u8>> {
+    Ok(match packtype {
+        b'F' => {
+            // FastLZ needs the output to be at least 5% bigger than the input
+            // and at least 66 bytes.
+            let mut output = vec![0; data.len() + data.len() / 16 + 66];
+            let length = fastlz::compress(data, &mut output)
+                .ok()
+                .context("FastLZ compression")?
+                .len();
+            output.truncate(length);
+            output
+        }
+        b'4' => lz4_flex::block::compress(data),
+        _ => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()?
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::Fixture;
+
+    #[test]
+    fn test_recompress_wave() {
+        let changes: Vec<(u64, String)> = (1..200)
+            .map(|i| (i * 5, format!("{:08b}", i % 7)))
+            .collect();
+        let changes: Vec<(u64, &str)> = changes.iter().map(|(t, v)| (*t, v.as_str())).collect();
+        let fixture = Fixture {
+            compress_waves: true,
+            ..Fixture::new()
+                .var("clk", 1, "0", &[(10, "1"), (20, "0")])
+                .var("bus", 8, "xxxxxxxx", &changes)
+        };
+        let fst = Fst::load(fixture.write().path()).unwrap();
+
+        for varid in [VarId(0), VarId(1)] {
+            let wave = fst.read_wave(varid).unwrap();
+            for packtype in PACKTYPES {
+                let recompressed = fst.recompress_wave(varid, packtype).unwrap();
+                assert_eq!(recompressed.decode().unwrap(), wave[1..], "{packtype}");
+            }
+        }
+
+        let lz4 = fst.recompress_wave(VarId(1), b'4').unwrap();
+        assert!(lz4.compressed_length() < changes.len() * 2);
+    }
+}
@@ -0,0 +1,150 @@
+//! A hash of the logical content of a file, for checking that a simulator
+//! produces the same dump every time it is run.
+
+use std::hash::Hasher;
+
+use anyhow::Result;
+
+use crate::fst::{Fst, VarLength};
+
+/// 64-bit FNV-1a. Unlike `DefaultHasher` its output is fixed, so hashes can
+/// be stored and compared between runs and Rust versions.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Fnv1a {
+    /// Write a length-prefixed string so that adjacent strings can't run
+    /// into each other.
+    fn write_str(&mut self, s: &str) {
+        self.write_u64((s.len() as u64).to_le());
+        self.write(s.as_bytes());
+    }
+}
+
+impl Fst {
+    /// Hash the hierarchy and the value changes of every var. Two files with
+    /// the same logical content hash the same, even if they were written by
+    /// different programs, at different times, or with different block
+    /// sizes or compression. Only the timescale, time zero and real byte
+    /// order are used from the header.
+    ///
+    /// All integers are hashed little endian, so the hash is the same on
+    /// every platform.
+    pub fn content_hash(&self) -> Result<u64> {
+        let mut hasher = Fnv1a::default();
+        hasher.write_i8(self.header.timescale);
+        hasher.write_i64(self.header.timezero.to_le());
+        hasher.write_u8(self.header.real_is_little_endian() as u8);
+
+        for (id, node) in self.hierarchy.iter().enumerate() {
+            let scope = &node.value;
+            hasher.write_u64((id as u64).to_le());
+            hasher.write_u64((node.parent().0 as u64).to_le());
+            hasher.write_u8(scope.type_);
+            hasher.write_str(&scope.name);
+            hasher.write_str(&scope.component);
+            hasher.write_u64((scope.attrs.len() as u64).to_le());
+            for attr in &scope.attrs {
+                hasher.write_u8(attr.type_);
+                hasher.write_u8(attr.subtype);
+                hasher.write_str(&attr.name);
+                hasher.write_u64(attr.arg.to_le());
+            }
+            hasher.write_u64((scope.vars.len() as u64).to_le());
+            for var in &scope.vars {
+                hasher.write_u8(var.type_);
+                hasher.write_u8(var.direction);
+                hasher.write_str(&var.name);
+                hasher.write_u64(var.length.to_le());
+                hasher.write_u64((var.id.0 as u64).to_le());
+                hasher.write_u8(var.is_alias as u8);
+            }
+        }
+
+        self.for_each_wave(|varid, wave| {
+            hasher.write_u64((varid.0 as u64).to_le());
+            hasher.write_u64((wave.len() as u64).to_le());
+            let var_length = self.var_lengths.length(varid);
+            for (time, value) in wave {
+                hasher.write_u64(time.to_le());
+                match var_length {
+                    // Hash each bit so padding can't make a difference.
+                    VarLength::Bits(bits) => {
+                        for i in 0..bits as usize {
+                            hasher.write_u8(value.bit(i));
+                        }
+                    }
+                    VarLength::Real => {
+                        let real = value.to_f64().unwrap_or(f64::NAN);
+                        hasher.write_u64(real.to_bits().to_le());
+                    }
+                }
+            }
+        })?;
+        Ok(hasher.finish())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::{write_blocks, Fixture};
+
+    #[test]
+    fn test_content_hash() {
+        let fixture = Fixture::new()
+            .var("clk", 1, "0", &[(10, "1"), (20, "0")])
+            .var("data", 3, "x01", &[(15, "110")]);
+        let hash = Fst::load(fixture.write().path())
+            .unwrap()
+            .content_hash()
+            .unwrap();
+
+        // A different writer, memory use and compression don't matter.
+        let mut same = Fixture {
+            memory_required: 1234,
+            compress_waves: true,
+            ..fixture
+        };
+        let mut blocks = same.blocks();
+        // The block type and length, then the writer is 65 bytes into the
+        // header.
+        blocks[0][9 + 65] = b'X';
+        let fst = Fst::load(write_blocks(&blocks).path()).unwrap();
+        assert_eq!(fst.header.writer_string(), "Xixture");
+        assert_eq!(fst.content_hash().unwrap(), hash);
+
+        // But values, times and names do.
+        same.vars[1].changes[0].1 = "111".to_string();
+        let fst = Fst::load(same.write().path()).unwrap();
+        assert_ne!(fst.content_hash().unwrap(), hash);
+        same.vars[1].changes[0] = (16, "110".to_string());
+        let fst = Fst::load(same.write().path()).unwrap();
+        assert_ne!(fst.content_hash().unwrap(), hash);
+        same.vars[1].changes[0].0 = 15;
+        same.vars[1].name = "datb".to_string();
+        let fst = Fst::load(same.write().path()).unwrap();
+        assert_ne!(fst.content_hash().unwrap(), hash);
+        same.vars[1].name = "data".to_string();
+        let fst = Fst::load(same.write().path()).unwrap();
+        assert_eq!(fst.content_hash().unwrap(), hash);
+    }
+}
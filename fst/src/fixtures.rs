@@ -0,0 +1,534 @@
+//! Small synthetic FST files for tests.
+//!
+//! `Fixture` describes a single scope containing some bit vars, and can
+//! produce each block separately so that tests can leave blocks out, reorder
+//! them or replace them with broken versions before writing the file.
+//!
+//! Values are given as VCD-style strings, MSB first, e.g. `"01xz"`. The bits
+//! array, waves and time table are stored uncompressed (unless
+//! `compress_waves` is set); the hierarchy is LZ4 compressed since that's the
+//! most common encoding in real files.
+
+use std::io::{self, Cursor, Seek, SeekFrom, Write};
+
+use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+use flate2::{write::ZlibEncoder, Compression};
+use tempfile::NamedTempFile;
+
+use crate::{
+    fst::{
+        BlockType, FST_AT_MISC, FST_MT_ENUMTABLE, FST_MT_PATHNAME, FST_MT_SOURCESTEM,
+        FST_ST_GEN_ATTRBEGIN, FST_ST_GEN_ATTREND, FST_ST_VCD_SCOPE, FST_ST_VCD_UPSCOPE,
+        REAL_ENDIANNESS_BIG, REAL_ENDIANNESS_LITTLE,
+    },
+    varint::{encode_svarint, encode_varint},
+};
+
+/// `FST_VT_VCD_WIRE`.
+const VAR_TYPE_WIRE: u8 = 16;
+/// `FST_VT_VCD_REAL`.
+const VAR_TYPE_REAL: u8 = 3;
+
+pub struct FixtureVar {
+    pub name: String,
+    /// Zero for reals.
+    pub length: u32,
+    pub real: bool,
+    /// The characters of the value, or the 8 bytes of a real.
+    pub initial: Vec<u8>,
+    /// The characters of each value, or for reals the number, e.g. `"2.5"`.
+    pub changes: Vec<(u64, String)>,
+    /// The name of an enum and its members' names and values, which is
+    /// written as an enum table just before the var.
+    pub enum_table: Option<(String, Vec<(String, String)>)>,
+    /// The file and line it is declared at, which is written as a source stem
+    /// just before the var.
+    pub source: Option<(String, u32)>,
+}
+
+pub struct Fixture {
+    pub start_time: u64,
+    pub end_time: u64,
+    pub timezero: i64,
+    /// The memory required field of the Value Change block.
+    pub memory_required: u64,
+    /// `REAL_ENDIANNESS_LITTLE` or `REAL_ENDIANNESS_BIG`. Reals are written
+    /// in this byte order.
+    pub real_endianness: u64,
+    /// ZLib compress the wave data of every var, even if it doesn't get
+    /// smaller.
+    pub compress_waves: bool,
+    /// ZLib compress the time table, even if it doesn't get smaller.
+    pub compress_times: bool,
+    pub scope: String,
+    pub vars: Vec<FixtureVar>,
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        Self {
+            start_time: 0,
+            end_time: 100,
+            timezero: 0,
+            memory_required: 0,
+            real_endianness: REAL_ENDIANNESS_LITTLE,
+            compress_waves: false,
+            compress_times: false,
+            scope: "top".to_string(),
+            vars: Vec::new(),
+        }
+    }
+
+    /// Add a var. `initial` and every value in `changes` must have `length` characters.
+    pub fn var(mut self, name: &str, length: u32, initial: &str, changes: &[(u64, &str)]) -> Self {
+        assert_eq!(initial.len(), length as usize);
+        assert!(changes.iter().all(|(_, v)| v.len() == length as usize));
+        self.vars.push(FixtureVar {
+            name: name.to_string(),
+            length,
+            real: false,
+            initial: initial.as_bytes().to_vec(),
+            changes: changes.iter().map(|(t, v)| (*t, v.to_string())).collect(),
+            enum_table: None,
+            source: None,
+        });
+        self
+    }
+
+    /// Make the last var an enum with the given member names and values.
+    pub fn with_enum(mut self, name: &str, members: &[(&str, &str)]) -> Self {
+        let members = members
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        self.vars.last_mut().unwrap().enum_table = Some((name.to_string(), members));
+        self
+    }
+
+    /// Give the last var a source file and line.
+    pub fn with_source(mut self, path: &str, line: u32) -> Self {
+        self.vars.last_mut().unwrap().source = Some((path.to_string(), line));
+        self
+    }
+
+    /// Add a real var.
+    pub fn real(mut self, name: &str, initial: f64, changes: &[(u64, f64)]) -> Self {
+        let initial = real_bytes(initial, self.real_endianness);
+        self.vars.push(FixtureVar {
+            name: name.to_string(),
+            length: 0,
+            real: true,
+            initial: initial.to_vec(),
+            changes: changes.iter().map(|(t, v)| (*t, v.to_string())).collect(),
+            enum_table: None,
+            source: None,
+        });
+        self
+    }
+
+    /// The blocks in the order that gtkwave writes them.
+    pub fn blocks(&self) -> Vec<Vec<u8>> {
+        vec![
+            self.header_block(),
+            self.value_change_block(),
+            geometry_block(&self.geometry_lengths()),
+            self.hierarchy_block(),
+        ]
+    }
+
+    /// Write the standard blocks to a temporary file.
+    pub fn write(&self) -> NamedTempFile {
+        write_blocks(&self.blocks())
+    }
+
+    pub fn header_block(&self) -> Vec<u8> {
+        let num_vars = self.vars.len() as u64;
+        let mut body = Vec::new();
+        body.write_u64::<BigEndian>(self.start_time).unwrap();
+        body.write_u64::<BigEndian>(self.end_time).unwrap();
+        // This is the bit pattern of e in the writer's byte order.
+        body.write_u64::<LittleEndian>(self.real_endianness)
+            .unwrap();
+        // Writer memory use.
+        body.write_u64::<BigEndian>(0).unwrap();
+        // Scopes, hierarchy vars, vars, value change blocks.
+        body.write_u64::<BigEndian>(1).unwrap();
+        body.write_u64::<BigEndian>(num_vars).unwrap();
+        body.write_u64::<BigEndian>(num_vars).unwrap();
+        body.write_u64::<BigEndian>(1).unwrap();
+        // Timescale (1ns).
+        body.write_i8(-9).unwrap();
+        let mut writer = [0; 128];
+        writer[..7].copy_from_slice(b"fixture");
+        body.extend_from_slice(&writer);
+        // Date and reserved.
+        body.extend_from_slice(&[0; 26]);
+        body.extend_from_slice(&[0; 93]);
+        // File type (Verilog) and time zero.
+        body.write_u8(0).unwrap();
+        body.write_i64::<BigEndian>(self.timezero).unwrap();
+        block(BlockType::FST_BL_HDR, &body)
+    }
+
+    pub fn geometry_lengths(&self) -> Vec<u64> {
+        self.vars
+            .iter()
+            .map(|v| match v.length {
+                _ if v.real => 0,
+                0 => 0xFFFFFFFF,
+                n => n as u64,
+            })
+            .collect()
+    }
+
+    pub fn hierarchy_block(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.push(FST_ST_VCD_SCOPE);
+        // FST_ST_VCD_MODULE
+        data.push(0);
+        write_string(&mut data, &self.scope);
+        write_string(&mut data, "");
+        // Each path is declared once, numbered from 1, as gtkwave does.
+        let mut paths: Vec<&str> = Vec::new();
+        for (index, var) in self.vars.iter().enumerate() {
+            if let Some((path, line)) = &var.source {
+                let path_index = match paths.iter().position(|p| p == path) {
+                    Some(i) => i + 1,
+                    None => {
+                        paths.push(path);
+                        write_attr(
+                            &mut data,
+                            FST_MT_PATHNAME,
+                            path.as_bytes(),
+                            paths.len() as u64,
+                        );
+                        data.push(FST_ST_GEN_ATTREND);
+                        paths.len()
+                    }
+                };
+                // The name is the path index as a varint.
+                let mut name = Vec::new();
+                write_varint(&mut name, path_index as u64);
+                write_attr(&mut data, FST_MT_SOURCESTEM, &name, *line as u64);
+                data.push(FST_ST_GEN_ATTREND);
+            }
+            if let Some((name, members)) = &var.enum_table {
+                // Define the table and then refer to it, as gtkwave does.
+                // Spaces are escaped.
+                let escape = |s: &str| s.replace(' ', "\\x20");
+                let mut table = format!("{} {}", escape(name), members.len());
+                for (name, _) in members {
+                    table += &format!(" {}", escape(name));
+                }
+                for (_, value) in members {
+                    table += &format!(" {value}");
+                }
+                let handle = index as u64 + 1;
+                write_attr(&mut data, FST_MT_ENUMTABLE, table.as_bytes(), handle);
+                data.push(FST_ST_GEN_ATTREND);
+                write_attr(&mut data, FST_MT_ENUMTABLE, b"", handle);
+            }
+            data.push(if var.real {
+                VAR_TYPE_REAL
+            } else {
+                VAR_TYPE_WIRE
+            });
+            // FST_VD_IMPLICIT
+            data.push(0);
+            write_string(&mut data, &var.name);
+            // Reals have their length in bytes.
+            write_varint(&mut data, if var.real { 8 } else { var.length as u64 });
+            // Not an alias.
+            write_varint(&mut data, 0);
+            if var.enum_table.is_some() {
+                data.push(FST_ST_GEN_ATTREND);
+            }
+        }
+        data.push(FST_ST_VCD_UPSCOPE);
+
+        let mut body = Vec::new();
+        body.write_u64::<BigEndian>(data.len() as u64).unwrap();
+        body.extend_from_slice(&lz4_flex::compress(&data));
+        block(BlockType::FST_BL_HIER_LZ4, &body)
+    }
+
+    /// The change times in the time table: every time at which any var changes.
+    pub fn times(&self) -> Vec<u64> {
+        let mut times: Vec<u64> = self
+            .vars
+            .iter()
+            .flat_map(|v| v.changes.iter().map(|(t, _)| *t))
+            .collect();
+        times.sort_unstable();
+        times.dedup();
+        times
+    }
+
+    pub fn value_change_block(&self) -> Vec<u8> {
+        let times = self.times();
+
+        let mut body = Vec::new();
+        body.write_u64::<BigEndian>(self.start_time).unwrap();
+        body.write_u64::<BigEndian>(self.end_time).unwrap();
+        body.write_u64::<BigEndian>(self.memory_required).unwrap();
+
+        // Bits array (initial values). Equal lengths mean it isn't compressed.
+        let bits: Vec<u8> = self.vars.iter().flat_map(|v| v.initial.clone()).collect();
+        write_varint(&mut body, bits.len() as u64);
+        write_varint(&mut body, bits.len() as u64);
+        write_varint(&mut body, self.vars.len() as u64);
+        body.extend_from_slice(&bits);
+
+        // Waves.
+        write_varint(&mut body, self.vars.len() as u64);
+        body.push(b'Z');
+        let mut waves = Vec::new();
+        let mut offsets = Vec::new();
+        for var in &self.vars {
+            if var.changes.is_empty() {
+                offsets.push(None);
+                continue;
+            }
+            offsets.push(Some(waves.len() as u64));
+            let mut data = Vec::new();
+            let mut prev_index = 0;
+            for (time, value) in &var.changes {
+                let index = times.binary_search(time).unwrap() as u64;
+                if var.real {
+                    // Always raw bytes, which is flagged like ASCII bits.
+                    let value: f64 = value.parse().unwrap();
+                    write_varint(&mut data, (index - prev_index) << 1 | 1);
+                    data.extend_from_slice(&real_bytes(value, self.real_endianness));
+                } else {
+                    write_wave_value(&mut data, index - prev_index, value);
+                }
+                prev_index = index;
+            }
+            if self.compress_waves {
+                write_varint(&mut waves, data.len() as u64);
+                let mut encoder = ZlibEncoder::new(&mut waves, Compression::default());
+                encoder.write_all(&data).unwrap();
+                encoder.finish().unwrap();
+            } else {
+                // Zero means the data isn't compressed.
+                write_varint(&mut waves, 0);
+                waves.extend_from_slice(&data);
+            }
+        }
+        body.extend_from_slice(&waves);
+
+        // Position table. Offsets are relative to the pack type byte.
+        let mut positions = Vec::new();
+        let mut prev_offset = 0;
+        let mut zero_run = 0;
+        for offset in offsets {
+            match offset {
+                None => zero_run += 1,
+                Some(offset) => {
+                    if zero_run > 0 {
+                        write_varint(&mut positions, zero_run << 1);
+                        zero_run = 0;
+                    }
+                    let offset = offset + 1;
+                    write_svarint(&mut positions, ((offset - prev_offset) << 1 | 1) as i64);
+                    prev_offset = offset;
+                }
+            }
+        }
+        if zero_run > 0 {
+            write_varint(&mut positions, zero_run << 1);
+        }
+        body.extend_from_slice(&positions);
+        body.write_u64::<BigEndian>(positions.len() as u64).unwrap();
+
+        // Time table. Equal lengths mean it isn't compressed, unless
+        // `compress_times` is set and it happens not to get any smaller.
+        let mut time_table = Vec::new();
+        let mut prev_time = 0;
+        for &time in &times {
+            write_varint(&mut time_table, time - prev_time);
+            prev_time = time;
+        }
+        let stored = if self.compress_times {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&time_table).unwrap();
+            encoder.finish().unwrap()
+        } else {
+            time_table.clone()
+        };
+        body.extend_from_slice(&stored);
+        // Uncompressed and compressed lengths.
+        body.write_u64::<BigEndian>(time_table.len() as u64)
+            .unwrap();
+        body.write_u64::<BigEndian>(stored.len() as u64).unwrap();
+        body.write_u64::<BigEndian>(times.len() as u64).unwrap();
+
+        block(BlockType::FST_BL_VCDATA_DYN_ALIAS2, &body)
+    }
+}
+
+/// An uncompressed geometry block.
+pub fn geometry_block(lengths: &[u64]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for &length in lengths {
+        write_varint(&mut data, length);
+    }
+    let mut out = Cursor::new(Vec::new());
+    write_block(&mut out, BlockType::FST_BL_GEOM, |w| {
+        w.write_u64::<BigEndian>(data.len() as u64)?;
+        w.write_u64::<BigEndian>(lengths.len() as u64)?;
+        w.write_all(&data)
+    })
+    .unwrap();
+    out.into_inner()
+}
+
+/// A blackout block. Each entry is whether dumping was turned on, and when.
+pub fn blackout_block(blackouts: &[(bool, u64)]) -> Vec<u8> {
+    let mut body = Vec::new();
+    write_varint(&mut body, blackouts.len() as u64);
+    let mut prev_time = 0;
+    for &(on, time) in blackouts {
+        body.push(on as u8);
+        write_varint(&mut body, time - prev_time);
+        prev_time = time;
+    }
+    block(BlockType::FST_BL_BLACKOUT, &body)
+}
+
+/// A block with its type and length.
+pub fn block(block_type: BlockType, body: &[u8]) -> Vec<u8> {
+    let mut out = Cursor::new(Vec::new());
+    write_block(&mut out, block_type, |w| w.write_all(body)).unwrap();
+    out.into_inner()
+}
+
+/// Write a block's type, then its length, then the body written by `body`.
+/// The length isn't known until the body has been written, so a placeholder
+/// is written and then patched. The length includes its own 8 bytes, which
+/// is what `Fst::load()` checks its position against at the end of each
+/// block.
+pub fn write_block<W: Write + Seek>(
+    out: &mut W,
+    block_type: BlockType,
+    body: impl FnOnce(&mut W) -> io::Result<()>,
+) -> io::Result<()> {
+    out.write_u8(block_type as u8)?;
+    let length_position = out.stream_position()?;
+    out.write_u64::<BigEndian>(0)?;
+    body(out)?;
+    let end = out.stream_position()?;
+    out.seek(SeekFrom::Start(length_position))?;
+    out.write_u64::<BigEndian>(end - length_position)?;
+    out.seek(SeekFrom::Start(end))?;
+    Ok(())
+}
+
+pub fn write_blocks(blocks: &[Vec<u8>]) -> NamedTempFile {
+    let mut file = NamedTempFile::new().unwrap();
+    for block in blocks {
+        file.write_all(block).unwrap();
+    }
+    file.flush().unwrap();
+    file
+}
+
+/// A file with two Value Change blocks, `10..49` and `50..100`, and two
+/// vars: `clk` (1 bit) changes only in the early block, at 20, and `data` (2
+/// bits) only in the late one, at 60. If `in_time_order` is false the late
+/// block is written first, so it is `BlockId(0)`.
+pub fn two_block_file(in_time_order: bool) -> NamedTempFile {
+    let early = Fixture {
+        start_time: 10,
+        end_time: 49,
+        ..Fixture::new()
+    }
+    .var("clk", 1, "0", &[(20, "1")])
+    .var("data", 2, "00", &[]);
+    let late = Fixture {
+        start_time: 50,
+        end_time: 100,
+        ..Fixture::new()
+    }
+    .var("clk", 1, "1", &[])
+    .var("data", 2, "00", &[(60, "11")]);
+    let mut value_change_blocks = [early.value_change_block(), late.value_change_block()];
+    if !in_time_order {
+        value_change_blocks.reverse();
+    }
+    let [first, second] = value_change_blocks;
+    write_blocks(&[
+        early.header_block(),
+        first,
+        second,
+        early.hierarchy_block(),
+        geometry_block(&[1, 2]),
+    ])
+}
+
+/// The bytes of a real in the given byte order (`REAL_ENDIANNESS_*`).
+fn real_bytes(value: f64, real_endianness: u64) -> [u8; 8] {
+    if real_endianness == REAL_ENDIANNESS_BIG {
+        value.to_be_bytes()
+    } else {
+        value.to_le_bytes()
+    }
+}
+
+fn write_wave_value(out: &mut Vec<u8>, time_index_delta: u64, value: &str) {
+    let value = value.as_bytes();
+    if value.len() == 1 {
+        match value[0] {
+            b'0' | b'1' => {
+                write_varint(out, time_index_delta << 2 | ((value[0] - b'0') as u64) << 1);
+            }
+            c => {
+                let code = b"xzhuwl-?"
+                    .iter()
+                    .position(|&x| x == c.to_ascii_lowercase())
+                    .unwrap() as u64;
+                write_varint(out, time_index_delta << 4 | code << 1 | 1);
+            }
+        }
+    } else if value.iter().all(|c| matches!(c, b'0' | b'1')) {
+        // Packed bits, MSB first.
+        write_varint(out, time_index_delta << 1);
+        let mut packed = vec![0u8; value.len().div_ceil(8)];
+        for (j, &c) in value.iter().enumerate() {
+            if c == b'1' {
+                packed[j / 8] |= 1 << (7 - (j % 8));
+            }
+        }
+        out.extend_from_slice(&packed);
+    } else {
+        write_varint(out, time_index_delta << 1 | 1);
+        out.extend_from_slice(value);
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(s.as_bytes());
+    out.push(0);
+}
+
+/// Write an `FST_AT_MISC` attribute, without the end tag.
+fn write_attr(out: &mut Vec<u8>, subtype: u8, name: &[u8], arg: u64) {
+    out.push(FST_ST_GEN_ATTRBEGIN);
+    out.push(FST_AT_MISC);
+    out.push(subtype);
+    out.extend_from_slice(name);
+    out.push(0);
+    write_varint(out, arg);
+}
+
+fn write_varint(out: &mut Vec<u8>, value: u64) {
+    let mut buffer = [0; 10];
+    let length = encode_varint(&mut buffer, value);
+    out.extend_from_slice(&buffer[..length]);
+}
+
+fn write_svarint(out: &mut Vec<u8>, value: i64) {
+    let mut buffer = [0; 10];
+    let length = encode_svarint(&mut buffer, value);
+    out.extend_from_slice(&buffer[..length]);
+}
@@ -1,16 +1,21 @@
 use crate::{
-    valvec::{ValAndTimeVec, ValVec, Value},
-    varint::{decode_svarint, decode_varint, varint_length, VarintReader},
+    block_cache::{BlockCache, BlockPrefetcher},
+    time_index::TimeIndex,
+    valvec::{bit_code, DetectPeriod, LogicBytes, PackedWave, ValAndTimeVec, ValVec, Value},
+    varint::{decode_svarint, decode_varint, VarintReader},
+    Hierarchy, VariableInfo, Waves,
 };
 use std::{
+    borrow::Cow,
     collections::{HashMap, HashSet},
     fs::File,
-    io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom},
+    io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write},
     ops::Range,
     path::{Path, PathBuf},
+    sync::{Arc, Mutex},
 };
 
-use log::info;
+use log::{info, warn};
 
 use anyhow::{bail, Context, Result};
 use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
@@ -18,7 +23,9 @@ use derive_more::{From, Into};
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 
-use flate2::read::ZlibDecoder;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use memmap2::Mmap;
+use tempfile::{NamedTempFile, TempPath};
 use tinyvec::tiny_vec;
 use typed_index_collections::TiVec;
 
@@ -31,9 +38,11 @@ pub struct VarId(pub usize);
 #[derive(From, Into, Debug, Default, Copy, Clone, Hash, PartialEq, Eq)]
 pub struct ScopeId(pub usize);
 
+/// The type byte at the start of each block. The names are the same as in
+/// gtkwave's `fstapi.h`.
 #[allow(non_camel_case_types)]
 #[derive(FromPrimitive, Copy, Clone, Debug, Eq, PartialEq, Hash)]
-enum BlockType {
+pub enum BlockType {
     FST_BL_HDR = 0,
     FST_BL_VCDATA = 1,
     FST_BL_BLACKOUT = 2,
@@ -47,14 +56,45 @@ enum BlockType {
     FST_BL_SKIP = 255,
 }
 
-static REAL_ENDIANNESS_LITTLE: u64 = 0x4005BF0A8B145769;
-static REAL_ENDIANNESS_BIG: u64 = 0x6957148B0ABF0540;
+/// `Header::real_endianness` is the bit pattern of e (2.71828...) as written
+/// by the machine that wrote the file. These are the little and big endian
+/// versions.
+pub static REAL_ENDIANNESS_LITTLE: u64 = 0x4005BF0A8B145769;
+pub static REAL_ENDIANNESS_BIG: u64 = 0x6957148B0ABF0540;
 
+// Tags in the hierarchy block that aren't var types (`FST_VT_*`).
 // TODO: Use enum
-const FST_ST_GEN_ATTRBEGIN: u8 = 252;
-const FST_ST_GEN_ATTREND: u8 = 253;
-const FST_ST_VCD_SCOPE: u8 = 254;
-const FST_ST_VCD_UPSCOPE: u8 = 255;
+pub const FST_ST_GEN_ATTRBEGIN: u8 = 252;
+pub const FST_ST_GEN_ATTREND: u8 = 253;
+pub const FST_ST_VCD_SCOPE: u8 = 254;
+pub const FST_ST_VCD_UPSCOPE: u8 = 255;
+
+// Attribute types (`FST_AT_*`), the `type_` of a `HierarchyAttr`.
+pub const FST_AT_MISC: u8 = 0;
+pub const FST_AT_ARRAY: u8 = 1;
+pub const FST_AT_ENUM: u8 = 2;
+pub const FST_AT_PACK: u8 = 3;
+
+// Subtypes of `FST_AT_MISC` attributes (`FST_MT_*`).
+pub const FST_MT_COMMENT: u8 = 0;
+pub const FST_MT_ENVVAR: u8 = 1;
+pub const FST_MT_SUPVAR: u8 = 2;
+pub const FST_MT_PATHNAME: u8 = 3;
+pub const FST_MT_SOURCESTEM: u8 = 4;
+pub const FST_MT_SOURCEISTEM: u8 = 5;
+pub const FST_MT_VALUELIST: u8 = 6;
+/// An enum table. If the name is empty this refers to the table whose handle
+/// is the attribute's `arg`, and applies to the next var. Otherwise it
+/// defines a table: see `EnumDef::parse()`. Its handle is the `arg`.
+pub const FST_MT_ENUMTABLE: u8 = 7;
+pub const FST_MT_UNKNOWN: u8 = 8;
+
+// Subtypes of `FST_AT_PACK` attributes (`FST_PT_*`), for SystemVerilog
+// structs and unions.
+pub const FST_PT_NONE: u8 = 0;
+pub const FST_PT_UNPACKED: u8 = 1;
+pub const FST_PT_PACKED: u8 = 2;
+pub const FST_PT_TAGGED_PACKED: u8 = 3;
 
 #[derive(Clone, Debug)]
 pub struct Header {
@@ -81,6 +121,13 @@ fn array_to_string<const T: usize>(x: &[u8; T]) -> String {
 }
 
 impl Header {
+    /// Whether reals are stored little endian, i.e. the file was written on
+    /// a little endian machine. `load()` checks that `real_endianness` is one
+    /// of the two valid patterns.
+    pub fn real_is_little_endian(&self) -> bool {
+        self.real_endianness != REAL_ENDIANNESS_BIG
+    }
+
     pub fn writer_string(&self) -> String {
         array_to_string(&self.writer)
     }
@@ -89,7 +136,44 @@ impl Header {
     }
 }
 
-#[derive(Debug)]
+/// Overview of a file, from `Fst::summary()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Summary {
+    pub writer: String,
+    pub date: String,
+    pub timescale: i8,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub timezero: i64,
+    pub num_scopes: usize,
+    /// Vars in the hierarchy, including aliases.
+    pub num_hierarchy_vars: usize,
+    /// Distinct vars, i.e. not including aliases.
+    pub num_vars: usize,
+    pub num_value_change_blocks: usize,
+    /// From `Fst::peak_block_memory()`.
+    pub peak_block_memory: u64,
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Writer: {}", self.writer)?;
+        writeln!(f, "Date: {}", self.date)?;
+        writeln!(f, "Timescale: 1e{} s", self.timescale)?;
+        writeln!(f, "Time: {}..{}", self.start_time, self.end_time)?;
+        writeln!(f, "Time zero: {}", self.timezero)?;
+        writeln!(f, "Scopes: {}", self.num_scopes)?;
+        writeln!(
+            f,
+            "Vars: {} ({} including aliases)",
+            self.num_vars, self.num_hierarchy_vars
+        )?;
+        writeln!(f, "Value change blocks: {}", self.num_value_change_blocks)?;
+        write!(f, "Peak block memory: {} bytes", self.peak_block_memory)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BlackoutType {
     DumpOn,
     DumpOff,
@@ -97,7 +181,7 @@ pub enum BlackoutType {
 
 /// The metadata about the value change block including pointers to the locations
 /// in the file of the actual data so we can get there again quickly.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct ValueChangeBlockInfo {
     pub start_time: u64,
     pub end_time: u64,
@@ -121,12 +205,51 @@ pub struct ValueChangeBlockInfo {
     pub time_count: u64,
 }
 
+/// A part of a Value Change block, for `Fst::raw_block_bytes()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockRegion {
+    /// The initial values.
+    Bits,
+    /// The wave data of every var, after the pack type.
+    Waves,
+    /// The position table, i.e. where each var's wave data is.
+    Positions,
+    /// The change times.
+    Times,
+}
+
+impl ValueChangeBlockInfo {
+    /// Where `region` is in the file. It may be compressed.
+    pub fn region_range(&self, region: BlockRegion) -> Range<u64> {
+        let (offset, length) = match region {
+            BlockRegion::Bits => (self.bits_data_offset, self.bits_compressed_length),
+            BlockRegion::Waves => (
+                self.waves_data_offset,
+                self.position_data_offset - self.waves_data_offset,
+            ),
+            BlockRegion::Positions => (self.position_data_offset, self.position_length),
+            BlockRegion::Times => (self.time_data_offset, self.time_compressed_length),
+        };
+        offset..offset + length
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
 pub enum VarLength {
     Bits(u32),
     Real,
 }
 
+impl std::fmt::Display for VarLength {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VarLength::Bits(1) => write!(f, "1 bit"),
+            VarLength::Bits(bits) => write!(f, "{bits} bits"),
+            VarLength::Real => write!(f, "real"),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct VarLengths {
     /// Geometry block is fully read into memory. There are two sentinel values.
@@ -139,6 +262,18 @@ pub struct VarLengths {
 }
 
 impl VarLengths {
+    pub(crate) fn push(&mut self, length: VarLength) {
+        let varid = VarId(self.lengths.len());
+        match length {
+            VarLength::Real => self.lengths.push(VAR_LENGTH_REAL),
+            VarLength::Bits(bits) if bits >= VAR_LENGTH_REAL as u32 => {
+                self.lengths.push(VAR_LENGTH_LONG);
+                self.lengths_long.insert(varid, bits);
+            }
+            VarLength::Bits(bits) => self.lengths.push(bits as u8),
+        }
+    }
+
     pub fn length(&self, varid: VarId) -> VarLength {
         match self.lengths[varid] {
             VAR_LENGTH_REAL => VarLength::Real,
@@ -152,8 +287,6 @@ impl VarLengths {
 pub struct ValueChangeBlockData {
     /// The medata for the value change block.
     pub info: ValueChangeBlockInfo,
-    /// The value change times (since the start of time).
-    pub times: Vec<u64>,
 }
 
 #[derive(Default, Debug)]
@@ -165,11 +298,104 @@ pub struct VarData {
     pub wave_slices: TiVec<BlockId, Range<u64>>,
 }
 
+/// What `read_wave_with_options()` does for a var that has no initial value.
+///
+/// gtkwave's `fstapi.c` writes an initial value for every var in every
+/// Value Change block, so this only happens for zero-length vars (which have
+/// no state) and for files with no Value Change blocks at all, e.g. when the
+/// writer was closed or crashed before it flushed its first block. In those
+/// cases the wave would otherwise start at its first change instead of the
+/// start time, which isn't valid for e.g. VCD export.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum MissingInitialValue {
+    /// Leave it out, so the wave starts at its first change (if any).
+    #[default]
+    Skip,
+    /// Insert an all-X value (NaN for reals) at the start time.
+    X,
+}
+
+/// Options for `Fst::read_wave_with_options()`.
+#[derive(Debug, Default, Clone)]
+pub struct ReadOptions {
+    pub missing_initial_value: MissingInitialValue,
+    /// Drop changes to the value it already has, e.g. at the start of each
+    /// Value Change block. The first and last samples are always kept so the
+    /// time range of the wave is the same.
+    pub coalesce: bool,
+    /// Read 1-bit values that gtkwave can write but that can't be
+    /// represented (only `?` currently) as X and log a warning, instead of
+    /// failing to read the whole wave.
+    pub lenient: bool,
+}
+
+/// Options for `Fst::load_with_options()`.
+#[derive(Debug, Clone)]
+pub struct LoadOptions {
+    /// Read the initial values of every var when the file is loaded. This
+    /// needs the bits array of every Value Change block to be decoded, which
+    /// is slow for files with lots of vars. If it is disabled the initial
+    /// value of a var is read from the file each time its wave is read.
+    pub read_initial_values: bool,
+    /// Memory map the file instead of reading it through file handles, so
+    /// waves are decoded straight from the mapping without copying. The file
+    /// must not be truncated or overwritten while it is loaded; appending to
+    /// it is fine.
+    pub mmap: bool,
+    /// Parse the Value Change blocks when the file is loaded. If it is
+    /// disabled only the metadata (the header, hierarchy, geometry and
+    /// blackouts) is read, and the blocks are parsed each time a wave is read
+    /// until `Fst::load_blocks()` is called. Initial values are read from the
+    /// file too, as if `read_initial_values` was disabled. Until then the
+    /// accessors for the blocks' metadata (e.g. `Fst::summary()`,
+    /// `Fst::block_for_time()` and `Fst::time_index()`) only see the parsed
+    /// blocks, i.e. none, and the functions that read the blocks' data
+    /// directly (e.g. `Fst::for_each_wave()` and `Fst::block_prefetcher()`)
+    /// return an error.
+    pub read_blocks: bool,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self {
+            read_initial_values: true,
+            mmap: false,
+            read_blocks: true,
+        }
+    }
+}
+
+/// From `wave_stats()`. These only cover the given time range.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct WaveStats {
+    /// How many times the value changes.
+    pub transitions: usize,
+    /// The smallest and largest values held. Buses are compared as unsigned
+    /// integers (or signed ones if `signed` was given), so this is None for
+    /// buses wider than 64 bits (128 if signed) and values with X or Z are
+    /// ignored. NaN reals are ignored too.
+    pub min: Option<Value>,
+    pub max: Option<Value>,
+    /// The fraction of the time that a 1-bit var is 1.
+    pub high_fraction: Option<f64>,
+    /// See `DetectPeriod`.
+    pub period: Option<u64>,
+}
+
+/// From `Fst::merged_changes()`. Each row is a time and the vars that
+/// changed then, with their new values.
+pub type MergedChanges = Vec<(u64, Vec<(VarId, Value)>)>;
+
 #[derive(Debug)]
 pub struct Fst {
-    /// File path that this file was loaded from, for convenience.
+    /// File path that this file was loaded from, for convenience. For a
+    /// `.fst.gz` file this is the decompressed temporary copy.
     pub filename: PathBuf,
 
+    /// The temporary copy of a gzipped file, which is deleted when this is
+    /// dropped.
+    decompressed: Option<TempPath>,
+
     /// Header block fully read into memory.
     pub header: Header,
 
@@ -182,49 +408,403 @@ pub struct Fst {
     /// The metadata for each Value Change block, and the times of the value changes.
     pub value_change_blocks: TiVec<BlockId, ValueChangeBlockData>,
 
+    /// The change times of each Value Change block.
+    time_index: TimeIndex,
+
     /// For each var, the initial value and wave offset in all the blocks.
     pub var_data: TiVec<VarId, VarData>,
 
     /// Blackout block is fully read into memory. This is optional.
     pub blackouts: Vec<(BlackoutType, u64)>,
 
-    /// The file reader; used when actually reading the waves.
-    reader: BufReader<File>,
+    /// Where the waves are read from.
+    waves: WaveSource,
+
+    /// Waves loaded by `Waves::load_waves()`. Not used for in-memory waves.
+    loaded_waves: HashMap<VarId, ValAndTimeVec>,
+
+    /// False if `LoadOptions::read_initial_values` was disabled, in which case
+    /// `VarData::initial_values` is empty.
+    initial_values_read: bool,
+
+    /// Offset in the file just after the last complete block that was parsed.
+    /// Blocks appended after this can be read with `reload_appended()`.
+    parsed_length: u64,
+
+    /// The offset (just after the block length) and length of each Value
+    /// Change block that hasn't been parsed yet because
+    /// `LoadOptions::read_blocks` was disabled. See `load_blocks()`.
+    unparsed_blocks: Vec<(u64, u64)>,
+
+    /// The wave data of recently read blocks. It is shared with
+    /// `BlockPrefetcher`s. Empty unless `set_block_cache_size()` is called.
+    block_cache: Arc<Mutex<BlockCache>>,
+}
+
+/// The first two bytes of a gzip file.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Decompress a gzip file to a temporary file.
+fn gunzip_to_temp_file(reader: impl Read) -> Result<TempPath> {
+    load_span!("gunzip");
+    let mut file = NamedTempFile::new().context("Couldn't create a file to decompress to")?;
+    io::copy(&mut GzDecoder::new(reader), &mut file).context("Couldn't decompress gzip file")?;
+    file.flush()?;
+    Ok(file.into_temp_path())
+}
+
+/// Where `read_wave()` gets the waves from.
+#[derive(Debug)]
+enum WaveSource {
+    /// Decoded from the FST file on demand.
+    File(ReaderPool),
+    /// Decoded from a memory map of the FST file on demand. It is in an `Arc`
+    /// so worker threads can share it without borrowing the `Fst`.
+    Mmap(Arc<Mmap>),
+    /// Already decoded, e.g. because they were parsed from a VCD file.
+    Memory(TiVec<VarId, ValAndTimeVec>),
+}
+
+/// Open handles to the FST file, so that concurrent `read_wave()` calls don't
+/// have to open the file every time.
+#[derive(Debug)]
+struct ReaderPool {
+    readers: Mutex<Vec<BufReader<File>>>,
+    /// The most readers to keep open. Any more than this are closed when
+    /// they are given back.
+    max_readers: usize,
+}
+
+impl ReaderPool {
+    fn new(reader: BufReader<File>) -> Self {
+        Self {
+            readers: Mutex::new(vec![reader]),
+            // Enough for one reader per thread if the reads are done in a
+            // thread pool (which uses this many threads by default).
+            max_readers: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+
+    /// Get a reader from the pool, or open a new one if they are all in use.
+    fn take(&self, filename: &Path) -> Result<BufReader<File>> {
+        match self.readers.lock().unwrap().pop() {
+            Some(reader) => Ok(reader),
+            None => Ok(BufReader::new(File::open(filename).with_context(|| {
+                format!("Couldn't reopen {} to read waves", filename.display())
+            })?)),
+        }
+    }
+
+    fn give_back(&self, reader: BufReader<File>) {
+        let mut readers = self.readers.lock().unwrap();
+        if readers.len() < self.max_readers {
+            readers.push(reader);
+        }
+    }
+
+    /// Call `f` with a reader from the pool. Every read seeks first so the
+    /// reader can be reused even if `f` fails part way through.
+    fn with_reader<T>(
+        &self,
+        filename: &Path,
+        f: impl FnOnce(&mut BufReader<File>) -> Result<T>,
+    ) -> Result<T> {
+        let mut reader = self.take(filename)?;
+        let result = f(&mut reader);
+        self.give_back(reader);
+        result
+    }
+}
+
+/// Memory map an FST file for `WaveSource::Mmap`.
+fn map_file(filename: &Path) -> Result<Mmap> {
+    let file = File::open(filename)?;
+    // SAFETY: The mapping is only read, and only the parts that `load()` or
+    // `reload_appended()` have already parsed, so appending to the file is
+    // fine. Truncating or overwriting it while it is mapped is not, which is
+    // documented on `LoadOptions::mmap`.
+    Ok(unsafe { Mmap::map(&file)? })
+}
+
+/// Read the bytes in `range` of a file, for `BlockPrefetcher`.
+pub(crate) fn read_range(reader: &mut BufReader<File>, range: Range<u64>) -> Result<Vec<u8>> {
+    Ok(FileData::Reader(reader).bytes(range)?.into_owned())
+}
+
+/// The FST file that waves are decoded from, either through a file handle
+/// from the pool or from a memory map.
+enum FileData<'a> {
+    Reader(&'a mut BufReader<File>),
+    Mmap(&'a [u8]),
+}
+
+impl FileData<'_> {
+    /// Get the bytes in `range` of the file. They are only copied if they
+    /// have to be read through a file handle.
+    fn bytes(&mut self, range: Range<u64>) -> Result<Cow<'_, [u8]>> {
+        let length = to_usize(
+            range
+                .end
+                .checked_sub(range.start)
+                .context("Invalid range")?,
+            "Data length",
+        )?;
+        match self {
+            FileData::Reader(reader) => {
+                reader.seek(SeekFrom::Start(range.start))?;
+                Ok(Cow::Owned(reader.read_vec(length)?))
+            }
+            FileData::Mmap(data) => {
+                let start = to_usize(range.start, "Data offset")?;
+                start
+                    .checked_add(length)
+                    .and_then(|end| data.get(start..end))
+                    .map(Cow::Borrowed)
+                    .with_context(|| format!("{range:?} is past the end of the file"))
+            }
+        }
+    }
+
+    /// Parse the Value Change block at `offset` (just after its length),
+    /// adding each var's wave slice to `var_data`.
+    fn read_value_change_block(
+        &mut self,
+        block: BlockId,
+        offset: u64,
+        block_length: u64,
+        num_vars: u64,
+        var_data: &mut TiVec<VarId, VarData>,
+    ) -> Result<(ValueChangeBlockData, Vec<u64>)> {
+        match self {
+            FileData::Reader(reader) => {
+                reader.seek(SeekFrom::Start(offset))?;
+                Fst::read_value_change_block(*reader, block, block_length, num_vars, var_data)
+            }
+            FileData::Mmap(data) => {
+                let mut cursor = Cursor::new(*data);
+                cursor.set_position(offset);
+                Fst::read_value_change_block(&mut cursor, block, block_length, num_vars, var_data)
+            }
+        }
+    }
 }
 
-const VAR_LENGTH_REAL: u8 = 0xFE;
-const VAR_LENGTH_LONG: u8 = 0xFF;
+/// Var types (`FST_VT_*`) that hold reals: real, real_parameter, realtime
+/// and shortreal.
+const REAL_VAR_TYPES: [u8; 4] = [3, 4, 20, 29];
+
+/// Var types (`FST_VT_*`) that are signed integers: integer, int, shortint,
+/// longint and byte.
+pub(crate) const SIGNED_VAR_TYPES: [u8; 5] = [1, 24, 25, 26, 27];
+
+/// Sentinel in `VarLengths::lengths` for reals.
+pub const VAR_LENGTH_REAL: u8 = 0xFE;
+/// Sentinel in `VarLengths::lengths` for vars that are too long to fit in a
+/// byte. The actual length is in `VarLengths::lengths_long`.
+pub const VAR_LENGTH_LONG: u8 = 0xFF;
 
 #[derive(Debug, Default)]
 pub struct HierarchyScope {
     /// This does not come from the file - it is just an incremental ID
     /// starting from 0, assigned in depth-first order. Use for convenience.
     pub type_: u8,
+    /// The name, with any invalid UTF-8 replaced. See `name_bytes()`.
     pub name: String,
+    /// The name as it is in the file if it isn't valid UTF-8.
+    pub raw_name: Option<Box<[u8]>>,
     pub component: String,
     pub vars: Vec<HierarchyVar>,
     pub attrs: Vec<HierarchyAttr>,
+    /// Where it is declared, if the writer recorded it. See `SourceStem`.
+    pub source: Option<SourceStem>,
 }
 
 #[derive(Debug, Default)]
 pub struct HierarchyVar {
     pub type_: u8,
     pub direction: u8,
+    /// The name, with any invalid UTF-8 replaced. See `name_bytes()`.
     pub name: String,
+    /// The name as it is in the file if it isn't valid UTF-8.
+    pub raw_name: Option<Box<[u8]>>,
     pub length: u64,
     pub id: VarId,
     pub is_alias: bool,
+    /// The names of the var's values if it is an enum.
+    pub enum_def: Option<Arc<EnumDef>>,
+    /// Where it is declared, if the writer recorded it.
+    pub source: Option<SourceStem>,
+}
+
+/// A source file and line, from an `FST_MT_SOURCESTEM` or
+/// `FST_MT_SOURCEISTEM` attribute (the declaration or the instantiation) just
+/// before a scope or var. The attribute refers to the file by the `arg` of an
+/// earlier `FST_MT_PATHNAME` attribute whose name is the path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceStem {
+    pub path: Arc<str>,
+    pub line: u32,
 }
 
+impl HierarchyScope {
+    /// The name with control characters escaped, for display. Use `name`
+    /// for matching.
+    pub fn display_name(&self) -> Cow<'_, str> {
+        escape_control_chars(&self.name)
+    }
+
+    /// The name exactly as it is in the file, e.g. for writing it out again.
+    pub fn name_bytes(&self) -> &[u8] {
+        self.raw_name.as_deref().unwrap_or(self.name.as_bytes())
+    }
+}
+
+impl HierarchyVar {
+    /// The name with control characters escaped, for display. Use `name`
+    /// for matching.
+    pub fn display_name(&self) -> Cow<'_, str> {
+        escape_control_chars(&self.name)
+    }
+
+    /// The name exactly as it is in the file, e.g. for writing it out again.
+    pub fn name_bytes(&self) -> &[u8] {
+        self.raw_name.as_deref().unwrap_or(self.name.as_bytes())
+    }
+
+    /// Whether it holds a real rather than bits. Its `length` is in bytes if
+    /// so.
+    pub fn is_real(&self) -> bool {
+        REAL_VAR_TYPES.contains(&self.type_)
+    }
+
+    /// Whether it is declared as a signed integer, so its top bit is the
+    /// sign.
+    pub fn is_signed(&self) -> bool {
+        SIGNED_VAR_TYPES.contains(&self.type_)
+    }
+}
+
+/// Some writers put control characters (even CR/LF) in generated names.
+/// Names are stored as read (apart from invalid UTF-8, which is replaced)
+/// so this escapes them like Rust string literals, e.g. `\r`.
+fn escape_control_chars(name: &str) -> Cow<'_, str> {
+    if !name.chars().any(char::is_control) {
+        return Cow::Borrowed(name);
+    }
+    let mut escaped = String::with_capacity(name.len() + 4);
+    for c in name.chars() {
+        if c.is_control() {
+            escaped.extend(c.escape_default());
+        } else {
+            escaped.push(c);
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// An attribute in the hierarchy, e.g. a comment or an enum table. These are
+/// stored in the scope they are declared in.
 #[derive(Debug, Default)]
 pub struct HierarchyAttr {
+    /// One of the `FST_AT_*` constants.
     pub type_: u8,
+    /// One of the `FST_MT_*` or `FST_PT_*` constants, depending on `type_`.
     pub subtype: u8,
     pub name: String,
     pub arg: u64,
+    /// For `FST_MT_SOURCESTEM` and `FST_MT_SOURCEISTEM` attributes the name
+    /// is a varint, the index of the path, and `arg` is the line. This is
+    /// that varint. Zero for other attributes.
     pub arg_from_name: u64,
 }
 
+/// A SystemVerilog enum: the name of each value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnumDef {
+    pub name: String,
+    pub members: Vec<(String, Value)>,
+}
+
+impl EnumDef {
+    /// Parse the name of an `FST_MT_ENUMTABLE` attribute that defines a
+    /// table. It is space separated: the enum's name, the number of members,
+    /// then the name of each member and then the value of each (MSB first,
+    /// e.g. `0110`). Each of these is escaped like a C string, with `\xHH`
+    /// for spaces and anything unprintable.
+    pub fn parse(s: &str) -> Result<EnumDef> {
+        let mut tokens = s.split(' ').filter(|token| !token.is_empty());
+        let name = unescape(tokens.next().context("Enum table has no name")?)?;
+        let count: usize = tokens
+            .next()
+            .context("Enum table has no member count")?
+            .parse()
+            .context("Invalid enum table member count")?;
+        let tokens: Vec<&str> = tokens.collect();
+        if tokens.len() != count * 2 {
+            bail!(
+                "Enum table {name:?} should have {count} names and values but has {} tokens",
+                tokens.len()
+            );
+        }
+        let (names, values) = tokens.split_at(count);
+        let members = names
+            .iter()
+            .zip(values)
+            .map(|(name, value)| Ok((unescape(name)?, Value::from_bits_str(&unescape(value)?)?)))
+            .collect::<Result<_>>()?;
+        Ok(EnumDef { name, members })
+    }
+
+    /// Get the name of the member with the given `bits` bit value, if there
+    /// is one.
+    pub fn member_name(&self, value: &Value, bits: u32) -> Option<&str> {
+        self.members
+            .iter()
+            .find(|(_, v)| v.eq_bits(value, bits))
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+/// Undo the C-style escaping that gtkwave uses for enum tables.
+fn unescape(s: &str) -> Result<String> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            out.push(b);
+            continue;
+        }
+        out.push(match bytes.next().context("Escape at end of string")? {
+            b'a' => 0x07,
+            b'b' => 0x08,
+            b'f' => 0x0C,
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b't' => b'\t',
+            b'v' => 0x0B,
+            b'x' | b'X' => {
+                let hex = [
+                    bytes.next().unwrap_or_default(),
+                    bytes.next().unwrap_or_default(),
+                ];
+                std::str::from_utf8(&hex)
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .with_context(|| format!("Invalid hex escape in {s:?}"))?
+            }
+            c => c,
+        });
+    }
+    Ok(String::from_utf8_lossy(&out).to_string())
+}
+
+/// Convert a length, count or index from the file to `usize`. This fails on
+/// 32-bit platforms (e.g. WASM) if the value is too large, instead of silently
+/// truncating it.
+pub(crate) fn to_usize(value: u64, what: &str) -> Result<usize> {
+    usize::try_from(value).with_context(|| format!("{what} {value} is too large for this platform"))
+}
+
 trait ReadArray {
     fn read_array<const T: usize>(&mut self) -> std::io::Result<[u8; T]>;
 
@@ -263,34 +843,102 @@ where
     }
 }
 
+/// The longest scope, var or attribute name that is accepted, not including
+/// the null terminator. This is gtkwave's limit for attribute names
+/// (`FST_ID_NAM_ATTR_SIZ`), which is the largest it has; enum tables in
+/// particular can be long. The writer doesn't limit names at all, but this
+/// stops a corrupt file using unbounded memory.
+pub const MAX_NAME_LENGTH: u64 = 65536 + 4096;
+
 trait ReadString {
-    fn read_null_terminated_string(&mut self, max_size: u64) -> std::io::Result<String>;
+    /// Read a string up to a null byte, which is consumed. It is an error if
+    /// there isn't one in the first `max_length + 1` bytes.
+    fn read_null_terminated_string(&mut self, max_length: u64) -> std::io::Result<String>;
+
+    /// Like `read_null_terminated_string()` but without converting the bytes
+    /// to UTF-8, for names that aren't text.
+    fn read_null_terminated_bytes(&mut self, max_length: u64) -> std::io::Result<Vec<u8>>;
+
+    /// Like `read_null_terminated_string()` but if the string isn't valid
+    /// UTF-8 the bytes are returned too, since replacing the invalid parts
+    /// loses them.
+    fn read_null_terminated_string_exact(
+        &mut self,
+        max_length: u64,
+    ) -> std::io::Result<(String, Option<Box<[u8]>>)>;
 }
 
 impl<R> ReadString for R
 where
     R: BufRead,
 {
-    fn read_null_terminated_string(&mut self, max_size: u64) -> std::io::Result<String> {
-        let mut buf = Vec::new();
-        self.take(max_size).read_until(0, &mut buf)?;
-        // It includes the 0 byte.
-        buf.pop();
+    fn read_null_terminated_string(&mut self, max_length: u64) -> std::io::Result<String> {
+        let buf = self.read_null_terminated_bytes(max_length)?;
         Ok(String::from_utf8_lossy(&buf).to_string())
     }
+
+    fn read_null_terminated_string_exact(
+        &mut self,
+        max_length: u64,
+    ) -> std::io::Result<(String, Option<Box<[u8]>>)> {
+        let buf = self.read_null_terminated_bytes(max_length)?;
+        Ok(match String::from_utf8(buf) {
+            Ok(string) => (string, None),
+            Err(err) => {
+                let bytes = err.into_bytes();
+                (
+                    String::from_utf8_lossy(&bytes).to_string(),
+                    Some(bytes.into_boxed_slice()),
+                )
+            }
+        })
+    }
+
+    fn read_null_terminated_bytes(&mut self, max_length: u64) -> std::io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.take(max_length + 1).read_until(0, &mut buf)?;
+        // It includes the 0 byte, unless the string is too long or the data
+        // ended. Carrying on would read the rest of the string as whatever
+        // comes next.
+        if buf.pop() != Some(0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("String is not null terminated within {max_length} bytes"),
+            ));
+        }
+        Ok(buf)
+    }
 }
 
 impl Fst {
     pub fn load(filename: &Path) -> Result<Self> {
+        Self::load_with_options(filename, &LoadOptions::default())
+    }
+
+    /// Like `load()` but with control over what is read up front.
+    ///
+    /// Gzipped files (e.g. `.fst.gz`) are detected from their magic number
+    /// and decompressed to a temporary file first, since reading needs to
+    /// seek.
+    pub fn load_with_options(filename: &Path, options: &LoadOptions) -> Result<Self> {
+        load_span!("fst", filename = filename.display().to_string());
         let f = File::open(filename)?;
 
         let mut reader = BufReader::new(f);
 
+        if reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+            let decompressed = gunzip_to_temp_file(reader)?;
+            let mut fst = Self::load_with_options(&decompressed, options)?;
+            fst.decompressed = Some(decompressed);
+            return Ok(fst);
+        }
+
         let mut expected_block_types: HashSet<BlockType> = Default::default();
         expected_block_types.insert(BlockType::FST_BL_HDR);
 
         let mut header = None;
         let mut value_change_blocks = TiVec::new();
+        let mut time_index = TimeIndex::default();
         let mut var_data = TiVec::new();
 
         let mut hierarchy = None;
@@ -298,6 +946,10 @@ impl Fst {
 
         let mut var_lengths = None;
 
+        let mut parsed_length = 0;
+
+        let mut unparsed_blocks = Vec::new();
+
         // Read blocks.
         while let Ok(block_type) = reader.read_u8() {
             let block_type = match BlockType::from_u8(block_type) {
@@ -307,7 +959,32 @@ impl Fst {
                 }
             };
 
+            if block_type == BlockType::FST_BL_SKIP {
+                // Writers tag blocks as SKIP until they are finished, so this
+                // block is incomplete and there's nothing valid after it.
+                info!("Found SKIP block; the file is still being written.");
+                parsed_length = reader.stream_position()? - 1;
+                break;
+            }
+
             if !expected_block_types.contains(&block_type) {
+                if header.is_none() {
+                    bail!(
+                        "Not an FST file; the first block is {block_type:?} instead of the header"
+                    );
+                }
+                let duplicate = match block_type {
+                    BlockType::FST_BL_HDR => true,
+                    BlockType::FST_BL_GEOM => var_lengths.is_some(),
+                    BlockType::FST_BL_BLACKOUT => blackouts.is_some(),
+                    BlockType::FST_BL_HIER
+                    | BlockType::FST_BL_HIER_LZ4
+                    | BlockType::FST_BL_HIER_LZ4DUO => hierarchy.is_some(),
+                    _ => false,
+                };
+                if duplicate {
+                    bail!("Duplicate {block_type:?} block; there should only be one");
+                }
                 bail!(
                     "Unexpected block type {:?}; expected one of {:?}",
                     &block_type,
@@ -328,6 +1005,7 @@ impl Fst {
                         bail!("Invalid header block length {block_length} (should be 321)");
                     }
 
+                    load_span!("header");
                     let h = Self::read_header(&mut reader)?;
                     // One byte is not much a magic number so we use `e` too.
                     if h.real_endianness != REAL_ENDIANNESS_LITTLE
@@ -343,6 +1021,8 @@ impl Fst {
 
                     header = Some(h);
 
+                    // The other blocks can be in any order, though there
+                    // can only be one geometry, hierarchy and blackout block.
                     expected_block_types.remove(&BlockType::FST_BL_HDR);
                     expected_block_types.insert(BlockType::FST_BL_VCDATA);
                     expected_block_types.insert(BlockType::FST_BL_BLACKOUT);
@@ -357,11 +1037,13 @@ impl Fst {
                     bail!("This file uses an old format (FST_BL_VCDATA) which is not currently supported.");
                 }
                 BlockType::FST_BL_BLACKOUT => {
+                    load_span!("blackout");
                     blackouts = Some(Self::read_blackout_block(&mut reader)?);
                     // There should only be one blackout block.
                     expected_block_types.remove(&BlockType::FST_BL_BLACKOUT);
                 }
                 BlockType::FST_BL_GEOM => {
+                    load_span!("geometry");
                     var_lengths = Some(Self::read_geometry_block(&mut reader, block_length)?);
                     // There should only be one geometry block.
                     expected_block_types.remove(&BlockType::FST_BL_GEOM);
@@ -372,6 +1054,7 @@ impl Fst {
                 BlockType::FST_BL_HIER
                 | BlockType::FST_BL_HIER_LZ4
                 | BlockType::FST_BL_HIER_LZ4DUO => {
+                    load_span!("hierarchy");
                     let num_scopes_hint = header
                         .as_ref()
                         .expect("Internal logic error; header not read before hierarchy.")
@@ -387,9 +1070,17 @@ impl Fst {
                     expected_block_types.remove(&BlockType::FST_BL_HIER_LZ4);
                     expected_block_types.remove(&BlockType::FST_BL_HIER_LZ4DUO);
                 }
+                BlockType::FST_BL_VCDATA_DYN_ALIAS2 if !options.read_blocks => {
+                    unparsed_blocks.push((reader.stream_position()?, block_length));
+                    reader.seek(SeekFrom::Start(
+                        block_length_position + block_length_including_length,
+                    ))?;
+                }
                 BlockType::FST_BL_VCDATA_DYN_ALIAS2 => {
-                    let data = Self::read_value_change_block(
+                    load_span!("value_change_block", index = value_change_blocks.len());
+                    let (data, times) = Self::read_value_change_block(
                         &mut reader,
+                        BlockId::from(value_change_blocks.len()),
                         block_length,
                         // `expected_block_types` ensures this should not happen.
                         header
@@ -399,13 +1090,14 @@ impl Fst {
                         &mut var_data,
                     )?;
 
+                    time_index.push(data.info.start_time, times);
                     value_change_blocks.push(data);
                 }
                 BlockType::FST_BL_ZWRAPPER => {
                     bail!("This file is a GZip compressed FST file (FST_BL_ZWRAPPER) which is not currently supported. You should just compressed it separately to get `.fst.gz`.");
                 }
                 BlockType::FST_BL_SKIP => {
-                    bail!("File contains 'skip' block indicating it has not been finished writing. Reading partially complete files is not currently supported.");
+                    bail!("Internal logic error; SKIP block should have been handled already.");
                 }
             }
 
@@ -420,6 +1112,7 @@ impl Fst {
                     pos,
                 );
             }
+            parsed_length = pos;
         }
 
         let header = match header {
@@ -432,176 +1125,1173 @@ impl Fst {
         let hierarchy = match hierarchy {
             Some(h) => h,
             None => {
-                bail!("Missing hierarchy block");
+                // gtkwave writes it when the file is closed.
+                bail!("Missing hierarchy block; the file may still be being written");
             }
         };
 
+        // Some minimal writers leave out the geometry block, but the lengths
+        // are in the hierarchy too.
         let var_lengths = match var_lengths {
             Some(v) => v,
             None => {
-                bail!("Missing geometry block");
+                info!("Missing geometry block; using the var lengths from the hierarchy");
+                Self::var_lengths_from_hierarchy(&hierarchy, var_data.len())?
             }
         };
 
+        // The geometry block determines the var lengths, but it must cover
+        // every var. Some writers pad it with extra trailing entries (e.g. for
+        // aliases); those are harmless since no var refers to them.
+        let num_geometry_vars = var_lengths.lengths.len();
+        if num_geometry_vars < var_data.len() {
+            bail!(
+                "Geometry block has {} entries but the header says there are {} vars",
+                num_geometry_vars,
+                var_data.len()
+            );
+        }
+        if num_geometry_vars > var_data.len() {
+            info!(
+                "Geometry block has {} extra trailing entries; ignoring them",
+                num_geometry_vars - var_data.len()
+            );
+        }
+
         let blackouts = blackouts.unwrap_or_default();
 
         // Read the initial values (the bit array) of each block here. We have
         // to do it at the end because we need `var_lengths` (the geometry block).
 
-        for vc in value_change_blocks.iter() {
-            reader.seek(SeekFrom::Start(vc.info.bits_data_offset))?;
-            Self::read_bits_array(
-                &mut reader,
-                vc.info.bits_compressed_length,
-                vc.info.bits_uncompressed_length,
-                vc.info.bits_count,
-                &var_lengths,
-                &mut var_data,
-            )?;
+        if options.read_initial_values {
+            load_span!("initial_values");
+            for (index, vc) in value_change_blocks.iter().enumerate() {
+                load_span!("bits_array", index = index);
+                reader.seek(SeekFrom::Start(vc.info.bits_data_offset))?;
+                Self::read_bits_array(
+                    &mut reader,
+                    vc.info.bits_compressed_length,
+                    vc.info.bits_uncompressed_length,
+                    vc.info.bits_count,
+                    &var_lengths,
+                    header.real_is_little_endian(),
+                    &mut var_data,
+                )?;
+            }
         }
 
         Ok(Self {
             filename: filename.to_owned(),
+            decompressed: None,
             header,
             value_change_blocks,
+            time_index,
             var_lengths,
             blackouts,
             hierarchy,
             var_data,
-            reader,
+            waves: if options.mmap {
+                WaveSource::Mmap(Arc::new(map_file(filename)?))
+            } else {
+                WaveSource::File(ReaderPool::new(reader))
+            },
+            loaded_waves: HashMap::new(),
+            initial_values_read: options.read_initial_values && options.read_blocks,
+            parsed_length,
+            unparsed_blocks,
+            block_cache: Default::default(),
         })
     }
 
-    /// This takes a mutable reference to self because it reads from the file.
-    pub fn read_wave(&mut self, varid: VarId) -> Result<ValAndTimeVec> {
-        // 1. Loop through the blocks.
-        // 2. Get the wave offset.
-        // 3. Decode the values to Value
+    /// Estimate how much memory loading a file and reading all of its waves
+    /// would need, in bytes, without loading it. Only the start of each block
+    /// is read. This is the sum of the writer's estimate for each Value
+    /// Change block and the uncompressed lengths of the hierarchy and
+    /// geometry blocks.
+    pub fn estimate_memory(filename: &Path) -> Result<u64> {
+        let mut reader = BufReader::new(File::open(filename)?);
 
-        info!("Reading waves for {:?}", varid);
+        let mut estimate: u64 = 0;
+        let mut first_block = true;
 
-        let mut wave = ValAndTimeVec::new();
+        while let Ok(block_type) = reader.read_u8() {
+            let block_type = BlockType::from_u8(block_type)
+                .with_context(|| format!("Unknown block type {block_type}"))?;
+            if first_block && block_type != BlockType::FST_BL_HDR {
+                bail!("Not an FST file; the first block is {block_type:?}");
+            }
+            first_block = false;
 
-        let var_data = self.var_data.get(varid).context("Invalid var ID")?;
-        let var_length = self.var_lengths.length(varid);
+            if block_type == BlockType::FST_BL_SKIP {
+                // The file is still being written.
+                break;
+            }
 
-        // Add the initial value. TODO: Should this error if there is no initial value?
-        if let Some(first) = var_data.initial_values.first() {
-            info!("Initial value: {:?}", first);
-            wave.push((0, first.clone()));
+            let block_length_position = reader.stream_position()?;
+            let block_length_including_length = reader.read_u64::<BigEndian>()?;
+
+            let size = match block_type {
+                BlockType::FST_BL_VCDATA
+                | BlockType::FST_BL_VCDATA_DYN_ALIAS
+                | BlockType::FST_BL_VCDATA_DYN_ALIAS2 => {
+                    // Skip the start and end times to get `memory_required`.
+                    reader.seek(SeekFrom::Current(16))?;
+                    reader.read_u64::<BigEndian>()?
+                }
+                // These all start with their uncompressed length.
+                BlockType::FST_BL_HIER
+                | BlockType::FST_BL_HIER_LZ4
+                | BlockType::FST_BL_HIER_LZ4DUO
+                | BlockType::FST_BL_GEOM => reader.read_u64::<BigEndian>()?,
+                _ => block_length_including_length,
+            };
+            // The sizes are straight from the file, which may be corrupt.
+            estimate = estimate.saturating_add(size);
+
+            let block_end = block_length_position
+                .checked_add(block_length_including_length)
+                .with_context(|| {
+                    format!(
+                        "{block_type:?} block at {block_length_position} has invalid length {block_length_including_length}"
+                    )
+                })?;
+            reader.seek(SeekFrom::Start(block_end))?;
         }
 
-        for (block, wave_slice) in self
-            .value_change_blocks
-            .iter()
-            .zip(var_data.wave_slices.iter())
-        {
-            info!("Reading Value Change Block...");
+        Ok(estimate)
+    }
 
-            if wave_slice.is_empty() {
-                info!("No changes in this block.");
-                continue;
-            }
+    /// Make an `Fst` from waves that have already been decoded, e.g. from
+    /// another format. There are no Value Change blocks.
+    pub(crate) fn from_waves(
+        filename: &Path,
+        header: Header,
+        hierarchy: espalier::Tree<ScopeId, HierarchyScope>,
+        var_lengths: VarLengths,
+        waves: TiVec<VarId, ValAndTimeVec>,
+        blackouts: Vec<(BlackoutType, u64)>,
+    ) -> Self {
+        let mut var_data = TiVec::new();
+        var_data.resize_with(waves.len(), Default::default);
+        Self {
+            filename: filename.to_owned(),
+            decompressed: None,
+            header,
+            hierarchy,
+            var_lengths,
+            value_change_blocks: TiVec::new(),
+            time_index: TimeIndex::default(),
+            var_data,
+            blackouts,
+            waves: WaveSource::Memory(waves),
+            loaded_waves: HashMap::new(),
+            initial_values_read: true,
+            parsed_length: 0,
+            unparsed_blocks: Vec::new(),
+            block_cache: Default::default(),
+        }
+    }
 
-            // Offset of the wave data.
-            let offset = block.info.waves_data_offset + wave_slice.start;
+    /// Parse the Value Change blocks that weren't parsed by `load()` because
+    /// `LoadOptions::read_blocks` was disabled, so reading waves doesn't have
+    /// to parse them each time. This does nothing if they have all been
+    /// parsed. Initial values are still read from the file when needed.
+    pub fn load_blocks(&mut self) -> Result<()> {
+        if self.unparsed_blocks.is_empty() {
+            return Ok(());
+        }
+        load_span!("value_change_blocks", count = self.unparsed_blocks.len());
+        let mut reader = BufReader::new(File::open(&self.filename).with_context(|| {
+            format!(
+                "Couldn't reopen {} to read its Value Change blocks",
+                self.filename.display()
+            )
+        })?);
+        for (offset, block_length) in std::mem::take(&mut self.unparsed_blocks) {
+            let (data, times) = FileData::Reader(&mut reader).read_value_change_block(
+                BlockId::from(self.value_change_blocks.len()),
+                offset,
+                block_length,
+                self.header.num_vars,
+                &mut self.var_data,
+            )?;
+            self.time_index.push(data.info.start_time, times);
+            self.value_change_blocks.push(data);
+        }
+        Ok(())
+    }
 
-            info!(
-                "Offset of wave data in file: {} + {} = {}",
-                block.info.waves_data_offset, wave_slice.start, offset
-            );
+    /// Read any complete Value Change blocks that have been appended to the
+    /// file since it was loaded (or last reloaded), e.g. by a simulator that is
+    /// still running. Returns the number of blocks added.
+    ///
+    /// This only works for writers that put the hierarchy (and geometry)
+    /// blocks before the Value Change blocks. gtkwave's `fstapi.c` writes
+    /// them when the file is closed, so its files can't be loaded at all
+    /// until then, and for them this is only useful if something else is
+    /// appended after closing.
+    pub fn reload_appended(&mut self) -> Result<usize> {
+        if let WaveSource::Memory(_) = self.waves {
+            bail!("Only FST files can be reloaded");
+        }
 
-            self.reader.seek(SeekFrom::Start(offset))?;
+        // Appended blocks come after the unparsed ones.
+        self.load_blocks()?;
 
-            // Read vc_waves_length. This is the uncompressed length if compressed
-            // or 0 if not compressed. We don't actually use this because we
-            // decompress on the fly.
-            let uncompressed_length_or_zero = self.reader.read_varint()?;
+        // Reopen the file so we don't get stale buffered data.
+        let mut reader = BufReader::new(File::open(&self.filename)?);
+        let file_length = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(self.parsed_length))?;
 
-            // Compressed length.
-            let compressed_length = (wave_slice.end - wave_slice.start) as usize
-                - varint_length(uncompressed_length_or_zero) as usize;
+        let mut added = 0;
 
-            // We have to read all the data into memory in most cases.
-            // This also makes it easier to know when we've read to the end
-            // of the wave.
-            let compressed_data = self.reader.read_vec(compressed_length)?;
+        while let Ok(block_type) = reader.read_u8() {
+            let block_type = BlockType::from_u8(block_type)
+                .with_context(|| format!("Unknown block type {}", block_type))?;
 
-            info!(
-                "Uncompressed length (0=not compressed): {} Pack type: {}",
-                uncompressed_length_or_zero, block.info.waves_packtype as char
-            );
+            if block_type == BlockType::FST_BL_SKIP {
+                break;
+            }
 
-            // The pack type and waves_length determine the compression used.
-            let uncompressed_data = match (
-                uncompressed_length_or_zero as usize,
-                block.info.waves_packtype,
-            ) {
-                (0, _) => compressed_data,
-                (uncompressed_length, b'F') => {
-                    // FastLZ. Have to read the data into memory in this case.
-                    let mut uncompressed_data = vec![0; uncompressed_length];
-                    let output = fastlz::decompress(&compressed_data, &mut uncompressed_data)
-                        .ok()
-                        .context("FastLZ decompression")?;
-                    if output.len() != uncompressed_data.len() {
-                        bail!("Couldn't uncompress wave data using FastLZ");
-                    }
-                    uncompressed_data
-                }
-                (uncompressed_length, b'4') => {
-                    // LZ4
-                    lz4_flex::block::decompress(&compressed_data, uncompressed_length)?
-                }
-                (uncompressed_length, _) => {
-                    // ZLib
-                    let mut uncompressed_data = Vec::with_capacity(uncompressed_length);
-                    flate2::Decompress::new(false).decompress(
-                        &compressed_data,
-                        &mut uncompressed_data,
-                        flate2::FlushDecompress::Finish,
+            let block_length_position = reader.stream_position()?;
+            let block_length_including_length = reader.read_u64::<BigEndian>()?;
+            let block_length = block_length_including_length
+                .checked_sub(8)
+                .context("Invalid block length (must be >= 8).")?;
+            let block_end = block_length_position + block_length_including_length;
+            if block_end > file_length {
+                // Not completely written yet.
+                break;
+            }
+
+            if block_type == BlockType::FST_BL_VCDATA_DYN_ALIAS2 {
+                let (data, times) = Self::read_value_change_block(
+                    &mut reader,
+                    BlockId::from(self.value_change_blocks.len()),
+                    block_length,
+                    self.header.num_vars,
+                    &mut self.var_data,
+                )?;
+
+                if self.initial_values_read {
+                    reader.seek(SeekFrom::Start(data.info.bits_data_offset))?;
+                    Self::read_bits_array(
+                        &mut reader,
+                        data.info.bits_compressed_length,
+                        data.info.bits_uncompressed_length,
+                        data.info.bits_count,
+                        &self.var_lengths,
+                        self.header.real_is_little_endian(),
+                        &mut self.var_data,
                     )?;
-                    uncompressed_data
                 }
-            };
 
-            // Get the actual uncompressed length (it could have been zero).
-            let uncompressed_length = uncompressed_data.len();
+                // The header isn't rewritten until the file is finished, so
+                // keep it consistent with the blocks we have.
+                self.header.end_time = self.header.end_time.max(data.info.end_time);
+                self.header.num_vc_blocks += 1;
+
+                self.time_index.push(data.info.start_time, times);
+                self.value_change_blocks.push(data);
+                added += 1;
+            } else {
+                info!("Ignoring appended {block_type:?} block");
+            }
 
-            let mut cursor = Cursor::new(uncompressed_data);
+            reader.seek(SeekFrom::Start(block_end))?;
+            self.parsed_length = block_end;
+        }
 
-            let mut time_index = 0;
+        // Drop the old readers too in case they have stale buffered data, or
+        // remap the file so the mapping covers the new blocks.
+        self.waves = match self.waves {
+            WaveSource::Mmap(_) => WaveSource::Mmap(Arc::new(map_file(&self.filename)?)),
+            _ => WaveSource::File(ReaderPool::new(reader)),
+        };
 
-            while (cursor.position() as usize) < uncompressed_length {
-                // info!("Reader pos: {}", cursor.position());
-                let (value, time_index_delta) =
-                    value_and_time_index_delta_from_waves_table(&mut cursor, var_length)?;
-                // info!("Read value and time index delta: {:?}, {:?}", value, time_index_delta);
-                time_index += time_index_delta;
-                let time = block.times[time_index as usize];
-                wave.push((time, value));
+        // The new blocks may have changes for the loaded waves.
+        if added > 0 {
+            let varids: Vec<VarId> = self.loaded_waves.keys().copied().collect();
+            for varid in varids {
+                let wave = self.read_wave(varid)?;
+                self.loaded_waves.insert(varid, wave);
             }
         }
 
-        Ok(wave)
+        Ok(added)
     }
 
-    fn read_header(reader: &mut impl BufRead) -> Result<Header> {
-        Ok(Header {
-            start_time: reader.read_u64::<BigEndian>()?,
-            end_time: reader.read_u64::<BigEndian>()?,
-            real_endianness: reader.read_u64::<LittleEndian>()?,
-            writer_memory_use: reader.read_u64::<BigEndian>()?,
-            num_scopes: reader.read_u64::<BigEndian>()?,
-            num_hiearchy_vars: reader.read_u64::<BigEndian>()?,
-            num_vars: reader.read_u64::<BigEndian>()?,
-            num_vc_blocks: reader.read_u64::<BigEndian>()?,
-            timescale: reader.read_i8()?,
-            writer: reader.read_array()?,
-            date: reader.read_array()?,
-            reserved: reader.read_array()?,
-            filetype: reader.read_u8()?,
-            timezero: reader.read_i64::<BigEndian>()?,
+    /// Read the values of a var from the file. This can be called from several
+    /// threads at once; each read uses its own file handle from a pool.
+    pub fn read_wave(&self, varid: VarId) -> Result<ValAndTimeVec> {
+        self.read_wave_with_options(varid, &ReadOptions::default())
+    }
+
+    /// Read the values of a var given its dotted path, as for `var_by_path()`.
+    pub fn read_wave_by_path(&self, path: &str) -> Result<ValAndTimeVec> {
+        let varid = self
+            .var_by_path(path)
+            .with_context(|| format!("No var called {path}"))?;
+        self.read_wave(varid)
+    }
+
+    /// Like `read_wave()` but with control over how edge cases are handled.
+    pub fn read_wave_with_options(
+        &self,
+        varid: VarId,
+        options: &ReadOptions,
+    ) -> Result<ValAndTimeVec> {
+        info!("Reading waves for {:?}", varid);
+
+        let var_data = self.var_data.get(varid).context("Invalid var ID")?;
+        let var_length = self.var_lengths.length(varid);
+
+        let wave = match &self.waves {
+            WaveSource::File(_) | WaveSource::Mmap(_) => self.with_file_data(|file| {
+                self.read_wave_from_file(
+                    file,
+                    varid,
+                    var_data,
+                    var_length,
+                    options,
+                    ValAndTimeVec::new(),
+                )
+            })?,
+            WaveSource::Memory(waves) => {
+                let mut wave = waves[varid].clone();
+                if options.missing_initial_value == MissingInitialValue::X
+                    && wave
+                        .first()
+                        .is_none_or(|(time, _)| *time > self.header.start_time)
+                {
+                    info!("No initial value; using X");
+                    wave.insert(0, (self.header.start_time, x_value(var_length)));
+                }
+                wave
+            }
+        };
+
+        Ok(if options.coalesce {
+            coalesce(wave, var_length)
+        } else {
+            wave
+        })
+    }
+
+    /// Like `read_wave()` but with all the values in one buffer. See
+    /// `PackedWave`.
+    pub fn read_wave_packed(&self, varid: VarId) -> Result<PackedWave> {
+        self.read_wave_into(varid, PackedWave::new())
+    }
+
+    /// Read one bit of a bus (0 is the LSB) as a 1-bit wave. Each value is
+    /// sliced as it is decoded so only one bit per change is kept, which
+    /// saves a lot of memory for wide buses. Changes of other bits are left
+    /// out.
+    pub fn read_wave_bit(&self, varid: VarId, bit: u32) -> Result<ValAndTimeVec> {
+        self.var_data.get(varid).context("Invalid var ID")?;
+        match self.var_lengths.length(varid) {
+            VarLength::Bits(bits) if bit < bits => {}
+            VarLength::Bits(bits) => bail!("Bit {bit} is out of range for a {bits} bit var"),
+            VarLength::Real => bail!("Can't read a bit of a real"),
+        }
+        Ok(self
+            .read_wave_into(
+                varid,
+                BitWave {
+                    bit,
+                    wave: Vec::new(),
+                },
+            )?
+            .wave)
+    }
+
+    /// Count how many times a var's value changes after its initial value.
+    /// The values are decoded but not kept, so this uses little memory.
+    pub fn change_count(&self, varid: VarId) -> Result<usize> {
+        Ok(self.read_wave_into(varid, ChangeCounter::default())?.count)
+    }
+
+    /// Get the value of a var after its last change in a Value Change block,
+    /// i.e. the value it carries into the next block. None if it doesn't
+    /// change in the block. Only the var's data in that block is decoded, so
+    /// an exporter that goes through the blocks in order can use this to
+    /// stitch them together without reading from the start each time.
+    pub fn block_final_value(&self, varid: VarId, block: BlockId) -> Result<Option<Value>> {
+        self.check_blocks_parsed()?;
+        let var_data = self.var_data.get(varid).context("Invalid var ID")?;
+        let wave_slice = var_data
+            .wave_slices
+            .get(block)
+            .with_context(|| format!("Invalid block ID {block:?}"))?;
+        let var_length = self.var_lengths.length(varid);
+
+        let mut last = LastValue::default();
+        self.with_file_data(|file| {
+            self.read_wave_slice(file, block, wave_slice, var_length, false, &mut last)
+        })?;
+        Ok(last.0)
+    }
+
+    /// The value of each of `varids` at each of `times`, i.e. after any
+    /// changes at that time: `samples[i][j]` is `varids[j]` at `times[i]`.
+    /// Vars with no value yet are X (NaN for reals). The times don't have to
+    /// be sorted, but only the blocks that they are in are decoded, so this
+    /// is much cheaper than reading whole waves to sample a short span.
+    pub fn sample(&self, varids: &[VarId], times: &[u64]) -> Result<Vec<Vec<Value>>> {
+        for &varid in varids {
+            self.var_data.get(varid).context("Invalid var ID")?;
+        }
+        // The times in time order.
+        let mut order: Vec<usize> = (0..times.len()).collect();
+        order.sort_by_key(|&index| times[index]);
+
+        let mut samples = vec![Vec::with_capacity(varids.len()); times.len()];
+        for &varid in varids {
+            let var_length = self.var_lengths.length(varid);
+            let values = match &self.waves {
+                WaveSource::File(_) | WaveSource::Mmap(_) if self.unparsed_blocks.is_empty() => {
+                    self.with_file_data(|file| self.sample_from_file(file, varid, times, &order))?
+                }
+                _ => {
+                    let wave = self.read_wave(varid)?;
+                    times
+                        .iter()
+                        .map(|&time| value_at(&wave, time).cloned())
+                        .collect()
+                }
+            };
+            for (sample, value) in samples.iter_mut().zip(values) {
+                sample.push(value.unwrap_or_else(|| x_value(var_length)));
+            }
+        }
+        Ok(samples)
+    }
+
+    /// The value of a var at each of `times` (in the order they were given),
+    /// or None before it has one. `order` is the indices of `times` in time
+    /// order. Blocks after the last time aren't decoded, and nor are blocks
+    /// without any of the times if each block's initial values were read,
+    /// since the next block's initial value is the value it ends with.
+    fn sample_from_file(
+        &self,
+        file: &mut FileData,
+        varid: VarId,
+        times: &[u64],
+        order: &[usize],
+    ) -> Result<Vec<Option<Value>>> {
+        let var_data = &self.var_data[varid];
+        let var_length = self.var_lengths.length(varid);
+        let block_initial_values = self.initial_values_read
+            && var_data.initial_values.len() == self.value_change_blocks.len();
+
+        let mut values = vec![None; times.len()];
+        let mut value = self
+            .initial_sample(file, varid, var_data, var_length, &ReadOptions::default())?
+            .map(|(_, value)| value);
+        // The times that haven't been sampled yet.
+        let mut remaining = order;
+        for (block, wave_slice) in var_data.wave_slices.iter_enumerated() {
+            if remaining.is_empty() {
+                break;
+            }
+            // The times before the next block starts.
+            let count = match self
+                .value_change_blocks
+                .get(BlockId::from(usize::from(block) + 1))
+            {
+                Some(next) => {
+                    remaining.partition_point(|&index| times[index] < next.info.start_time)
+                }
+                None => remaining.len(),
+            };
+            if block_initial_values {
+                if count == 0 {
+                    continue;
+                }
+                value = var_data.initial_values.get(usize::from(block)).cloned();
+            }
+
+            let mut changes = Vec::new();
+            self.read_wave_slice(file, block, wave_slice, var_length, false, &mut changes)?;
+            let mut changes = changes.into_iter().peekable();
+            for &index in &remaining[..count] {
+                while let Some((_, change)) = changes.next_if(|(time, _)| *time <= times[index]) {
+                    value = Some(change);
+                }
+                values[index] = value.clone();
+            }
+            if let Some((_, last)) = changes.last() {
+                value = Some(last);
+            }
+            remaining = &remaining[count..];
+        }
+        // Times after the last block, or if there are no blocks.
+        for &index in remaining {
+            values[index] = value.clone();
+        }
+        Ok(values)
+    }
+
+    /// Read a wave with the default options, adding it to `wave`.
+    fn read_wave_into<W: Extend<(u64, Value)>>(&self, varid: VarId, mut wave: W) -> Result<W> {
+        let var_data = self.var_data.get(varid).context("Invalid var ID")?;
+        let var_length = self.var_lengths.length(varid);
+        let options = ReadOptions::default();
+
+        match &self.waves {
+            WaveSource::File(_) | WaveSource::Mmap(_) => self.with_file_data(|file| {
+                self.read_wave_from_file(file, varid, var_data, var_length, &options, wave)
+            }),
+            WaveSource::Memory(waves) => {
+                wave.extend(waves[varid].iter().cloned());
+                Ok(wave)
+            }
+        }
+    }
+
+    /// Get the value of a var at the start of the file without reading its
+    /// wave. None if it has no initial value (see `MissingInitialValue`). If
+    /// `LoadOptions::read_initial_values` was disabled this reads it from the
+    /// file.
+    pub fn initial_value(&self, varid: VarId) -> Result<Option<Value>> {
+        let var_data = self.var_data.get(varid).context("Invalid var ID")?;
+
+        let var_length = self.var_lengths.length(varid);
+        match &self.waves {
+            WaveSource::File(_) | WaveSource::Mmap(_) if self.initial_values_read => {
+                Ok(var_data.initial_values.first().cloned())
+            }
+            WaveSource::File(_) | WaveSource::Mmap(_) => {
+                self.with_file_data(|file| self.read_initial_value(file, varid, var_length))
+            }
+            WaveSource::Memory(waves) => Ok(waves[varid]
+                .first()
+                .filter(|(time, _)| *time <= self.header.start_time)
+                .map(|(_, value)| value.clone())),
+        }
+    }
+
+    /// Read the wave of a var from the file, appending it to `wave`.
+    fn read_wave_from_file<W: Extend<(u64, Value)>>(
+        &self,
+        file: &mut FileData,
+        varid: VarId,
+        var_data: &VarData,
+        var_length: VarLength,
+        options: &ReadOptions,
+        mut wave: W,
+    ) -> Result<W> {
+        if !self.unparsed_blocks.is_empty() {
+            return self.read_wave_from_unparsed_blocks(file, varid, var_length, options, wave);
+        }
+
+        // 1. Loop through the blocks.
+        // 2. Get the wave offset.
+        // 3. Decode the values to Value
+
+        wave.extend(self.initial_sample(file, varid, var_data, var_length, options)?);
+
+        for (block_id, wave_slice) in var_data.wave_slices.iter_enumerated() {
+            self.read_wave_slice(
+                file,
+                block_id,
+                wave_slice,
+                var_length,
+                options.lenient,
+                &mut wave,
+            )?;
+        }
+
+        Ok(wave)
+    }
+
+    /// Like `read_wave_from_file()` but for when `LoadOptions::read_blocks`
+    /// was disabled. Each block is parsed to find the var's changes in it,
+    /// and nothing is kept, so `load_blocks()` first is faster if several
+    /// waves are going to be read.
+    fn read_wave_from_unparsed_blocks<W: Extend<(u64, Value)>>(
+        &self,
+        file: &mut FileData,
+        varid: VarId,
+        var_length: VarLength,
+        options: &ReadOptions,
+        mut wave: W,
+    ) -> Result<W> {
+        // The position table has the wave slices of every var so they all
+        // need somewhere to go.
+        let mut var_data = TiVec::new();
+        var_data.resize_with(self.var_data.len(), VarData::default);
+
+        for (index, &(offset, block_length)) in self.unparsed_blocks.iter().enumerate() {
+            let (block, times) = file
+                .read_value_change_block(
+                    BlockId::from(index),
+                    offset,
+                    block_length,
+                    self.header.num_vars,
+                    &mut var_data,
+                )
+                .with_context(|| format!("Couldn't parse Value Change block {index}"))?;
+            if index == 0 {
+                let initial_value =
+                    self.read_initial_value_from_block(file, &block.info, varid, var_length)?;
+                wave.extend(self.to_initial_sample(initial_value, var_length, options));
+            }
+
+            let wave_slice = var_data[varid].wave_slices.last().cloned().unwrap_or(0..0);
+            if wave_slice.is_empty() {
+                continue;
+            }
+            let data = file.bytes(
+                block.info.waves_data_offset + wave_slice.start
+                    ..block.info.waves_data_offset + wave_slice.end,
+            )?;
+            decode_wave_slice(
+                &data,
+                block.info.waves_packtype,
+                &times,
+                var_length,
+                options.lenient,
+                self.header.real_is_little_endian(),
+                &mut wave,
+            )?;
+        }
+
+        Ok(wave)
+    }
+
+    /// Get the first sample of a wave, from the initial value in the first
+    /// Value Change block.
+    fn initial_sample(
+        &self,
+        file: &mut FileData,
+        varid: VarId,
+        var_data: &VarData,
+        var_length: VarLength,
+        options: &ReadOptions,
+    ) -> Result<Option<(u64, Value)>> {
+        let initial_value = if self.initial_values_read {
+            var_data.initial_values.first().cloned()
+        } else {
+            self.read_initial_value(file, varid, var_length)?
+        };
+        Ok(self.to_initial_sample(initial_value, var_length, options))
+    }
+
+    /// The first sample of a wave given its initial value, if it has one.
+    fn to_initial_sample(
+        &self,
+        initial_value: Option<Value>,
+        var_length: VarLength,
+        options: &ReadOptions,
+    ) -> Option<(u64, Value)> {
+        match initial_value {
+            Some(first) => {
+                info!("Initial value: {:?}", first);
+                Some((0, first))
+            }
+            None => match options.missing_initial_value {
+                MissingInitialValue::Skip => {
+                    info!("No initial value; skipping");
+                    None
+                }
+                MissingInitialValue::X => {
+                    info!("No initial value; using X");
+                    Some((self.header.start_time, x_value(var_length)))
+                }
+            },
+        }
+    }
+
+    /// Decode the changes of a var in one Value Change block and append them
+    /// to `wave`.
+    fn read_wave_slice(
+        &self,
+        file: &mut FileData,
+        block_id: BlockId,
+        wave_slice: &Range<u64>,
+        var_length: VarLength,
+        lenient: bool,
+        wave: &mut impl Extend<(u64, Value)>,
+    ) -> Result<()> {
+        info!("Reading Value Change Block...");
+
+        if wave_slice.is_empty() {
+            info!("No changes in this block.");
+            return Ok(());
+        }
+
+        let block = &self.value_change_blocks[block_id];
+        // Offset of the wave data.
+        let offset = block.info.waves_data_offset + wave_slice.start;
+
+        info!(
+            "Offset of wave data in file: {} + {} = {}",
+            block.info.waves_data_offset, wave_slice.start, offset
+        );
+
+        let cached = self.block_cache.lock().unwrap().get(block_id);
+        let data = match &cached {
+            Some(waves) => Cow::Borrowed(
+                waves
+                    .get(
+                        to_usize(wave_slice.start, "Wave data offset")?
+                            ..to_usize(wave_slice.end, "Wave data offset")?,
+                    )
+                    .context("Wave data is past the end of the block")?,
+            ),
+            None => file.bytes(offset..block.info.waves_data_offset + wave_slice.end)?,
+        };
+        decode_wave_slice(
+            &data,
+            block.info.waves_packtype,
+            self.time_index.times(block_id),
+            var_length,
+            lenient,
+            self.header.real_is_little_endian(),
+            wave,
+        )
+    }
+
+    /// Call `f` with the wave of every var, in var ID order. This reads the
+    /// file a block at a time (and each block in file order) instead of a var
+    /// at a time, so it is much faster than calling `read_wave()` for every
+    /// var. However all the waves are held in memory until the end.
+    pub fn for_each_wave(&self, mut f: impl FnMut(VarId, &ValAndTimeVec)) -> Result<()> {
+        let waves = match &self.waves {
+            WaveSource::File(_) | WaveSource::Mmap(_) => {
+                self.with_file_data(|file| self.read_all_waves(file))?
+            }
+            WaveSource::Memory(waves) => {
+                for (varid, wave) in waves.iter_enumerated() {
+                    f(varid, wave);
+                }
+                return Ok(());
+            }
+        };
+
+        for (varid, wave) in waves.iter_enumerated() {
+            f(varid, wave);
+        }
+        Ok(())
+    }
+
+    fn read_all_waves(&self, file: &mut FileData) -> Result<TiVec<VarId, ValAndTimeVec>> {
+        self.check_blocks_parsed()?;
+        let options = ReadOptions::default();
+
+        let mut waves = TiVec::with_capacity(self.var_data.len());
+        for (varid, var_data) in self.var_data.iter_enumerated() {
+            let var_length = self.var_lengths.length(varid);
+            let initial = self.initial_sample(file, varid, var_data, var_length, &options)?;
+            waves.push(initial.into_iter().collect::<ValAndTimeVec>());
+        }
+
+        for blockid in self.value_change_blocks.keys() {
+            let slice = |varid: VarId| self.var_data[varid].wave_slices.get(blockid);
+
+            // Read the waves in the order they are in the file.
+            let mut varids: Vec<VarId> = (0..self.var_data.len())
+                .map(VarId)
+                .filter(|&varid| slice(varid).is_some_and(|slice| !slice.is_empty()))
+                .collect();
+            varids.sort_by_key(|&varid| slice(varid).map(|slice| slice.start));
+
+            for varid in varids {
+                if let Some(wave_slice) = slice(varid) {
+                    self.read_wave_slice(
+                        file,
+                        blockid,
+                        wave_slice,
+                        self.var_lengths.length(varid),
+                        false,
+                        &mut waves[varid],
+                    )?;
+                }
+            }
+        }
+        Ok(waves)
+    }
+
+    /// Like `read_wave()` but with `timezero` from the header added to the
+    /// times, so they are absolute simulation times. These can be negative.
+    pub fn read_wave_absolute(&self, varid: VarId) -> Result<Vec<(i64, Value)>> {
+        let timezero = self.header.timezero;
+        self.read_wave(varid)?
+            .into_iter()
+            .map(|(time, value)| {
+                let time = i64::try_from(time)
+                    .ok()
+                    .and_then(|time| time.checked_add(timezero))
+                    .with_context(|| format!("Time {time} + timezero {timezero} overflows"))?;
+                Ok((time, value))
+            })
+            .collect()
+    }
+
+    /// Get the value that a var holds for the largest total time within `range`,
+    /// and the fraction of `range` for which it holds it. Values are weighted
+    /// by how long they are held, not how many times they occur.
+    pub fn wave_mode(&self, varid: VarId, range: Range<u64>) -> Result<(Value, f64)> {
+        if range.is_empty() {
+            bail!("Empty time range {range:?}");
+        }
+        let wave = self.read_wave(varid)?;
+        wave_mode(&wave, range).context("Var has no value in the time range")
+    }
+
+    /// Get statistics about a var within `range`. See `wave_stats()`. Vars
+    /// that are declared signed are treated as signed.
+    pub fn wave_stats(&self, varid: VarId, range: Range<u64>) -> Result<WaveStats> {
+        let wave = self.read_wave(varid)?;
+        Ok(wave_stats(
+            &wave,
+            self.var_lengths.length(varid),
+            self.variable_info(varid)?.is_signed(),
+            range,
+        ))
+    }
+
+    /// Read the given vars and merge their changes by time. There is a row for
+    /// every time at which any of them changes, with the vars that changed
+    /// then in the order they were given.
+    pub fn merged_changes(&self, varids: &[VarId]) -> Result<MergedChanges> {
+        let mut changes = Vec::new();
+        for &varid in varids {
+            changes.extend(
+                self.read_wave(varid)?
+                    .into_iter()
+                    .map(|(time, value)| (time, varid, value)),
+            );
+        }
+        // This is stable so changes at the same time stay in order.
+        changes.sort_by_key(|(time, _, _)| *time);
+
+        let mut rows: MergedChanges = Vec::new();
+        for (time, varid, value) in changes {
+            match rows.last_mut() {
+                Some((row_time, row)) if *row_time == time => row.push((varid, value)),
+                _ => rows.push((time, vec![(varid, value)])),
+            }
+        }
+        Ok(rows)
+    }
+
+    /// Write the given vars as CSV. There is a row for every time at which
+    /// any of them changes, and a column for each var giving its value at
+    /// that time (empty if it doesn't have one yet).
+    pub fn export_csv(&self, mut out: impl Write, varids: &[VarId]) -> Result<()> {
+        let mut header = vec!["time".to_string()];
+        for &varid in varids {
+            header.push(csv_field(&self.variable_info(varid)?.name));
+        }
+        writeln!(out, "{}", header.join(","))?;
+
+        // The formatted value of each var at the current row.
+        let mut current = HashMap::new();
+        for (time, changes) in self.merged_changes(varids)? {
+            for (varid, value) in changes {
+                let value = value.format(self.var_lengths.length(varid));
+                current.insert(varid, value);
+            }
+            let mut row = time.to_string();
+            for varid in varids {
+                row.push(',');
+                if let Some(value) = current.get(varid) {
+                    row.push_str(value);
+                }
+            }
+            writeln!(out, "{row}")?;
+        }
+        Ok(())
+    }
+
+    /// Get an overview of the file. The counts are from what was actually
+    /// read rather than the header, which may be out of date if the writer
+    /// didn't finish.
+    pub fn summary(&self) -> Summary {
+        Summary {
+            writer: self.header.writer_string(),
+            date: self.header.date_string(),
+            timescale: self.header.timescale,
+            start_time: self.header.start_time,
+            end_time: self.header.end_time,
+            timezero: self.header.timezero,
+            num_scopes: self.hierarchy.len(),
+            num_hierarchy_vars: self
+                .hierarchy
+                .iter()
+                .map(|node| node.value.vars.len())
+                .sum(),
+            num_vars: self.var_lengths.lengths.len(),
+            num_value_change_blocks: self.value_change_blocks.len(),
+            peak_block_memory: self.peak_block_memory(),
+        }
+    }
+
+    /// Fail if `LoadOptions::read_blocks` was disabled and `load_blocks()`
+    /// hasn't been called yet, for functions that read the Value Change
+    /// blocks' data through their parsed metadata. Without this they would
+    /// act as if the file had no blocks.
+    pub(crate) fn check_blocks_parsed(&self) -> Result<()> {
+        if !self.unparsed_blocks.is_empty() {
+            bail!(
+                "The Value Change blocks haven't been parsed because \
+                 LoadOptions::read_blocks was disabled; call load_blocks() first"
+            );
+        }
+        Ok(())
+    }
+
+    /// The most memory that the writer estimated is needed to decode any
+    /// one Value Change block, in bytes. 0 if there are no blocks.
+    pub fn peak_block_memory(&self) -> u64 {
+        self.value_change_blocks
+            .iter()
+            .map(|block| block.info.memory_required)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The time range of each Value Change block, in file (`BlockId`) order.
+    pub fn block_time_ranges(&self) -> Vec<Range<u64>> {
+        self.value_change_blocks
+            .iter()
+            .map(|block| block.info.start_time..block.info.end_time)
+            .collect()
+    }
+
+    /// Read a region of a Value Change block from the file as it is stored,
+    /// i.e. still compressed. This is for debugging files that fail to
+    /// decode.
+    pub fn raw_block_bytes(&self, block: BlockId, region: BlockRegion) -> Result<Vec<u8>> {
+        self.check_blocks_parsed()?;
+        let range = self
+            .value_change_blocks
+            .get(block)
+            .with_context(|| format!("Invalid block ID {block:?}"))?
+            .info
+            .region_range(region);
+        self.with_file_data(|file| Ok(file.bytes(range)?.into_owned()))
+    }
+
+    /// Call `f` with the FST file the waves are decoded from, i.e. a reader
+    /// from the pool or the memory map.
+    fn with_file_data<T>(&self, f: impl FnOnce(&mut FileData) -> Result<T>) -> Result<T> {
+        match &self.waves {
+            WaveSource::File(pool) => {
+                pool.with_reader(&self.filename, |reader| f(&mut FileData::Reader(reader)))
+            }
+            WaveSource::Mmap(mmap) => f(&mut FileData::Mmap(mmap)),
+            WaveSource::Memory(_) => bail!("Only FST files have Value Change blocks"),
+        }
+    }
+
+    /// The Value Change block that contains `time`. See
+    /// `TimeIndex::block_for_time()`.
+    pub fn block_for_time(&self, time: u64) -> Option<BlockId> {
+        self.time_index.block_for_time(time)
+    }
+
+    /// Set the most bytes of wave data to keep in memory in the block cache,
+    /// dropping the least recently used blocks if it is already over. It is
+    /// only filled by `block_prefetcher()`. 0 (the default) disables it.
+    pub fn set_block_cache_size(&self, max_bytes: u64) {
+        self.block_cache.lock().unwrap().set_max_bytes(max_bytes);
+    }
+
+    /// Get a `BlockPrefetcher` for reading the wave data of every Value
+    /// Change block into the block cache in time order, e.g. in a background
+    /// thread while the waves aren't being read.
+    pub fn block_prefetcher(&self) -> Result<BlockPrefetcher> {
+        if let WaveSource::Memory(_) = self.waves {
+            bail!("Only FST files have Value Change blocks");
+        }
+        self.check_blocks_parsed()?;
+        let mut blocks: Vec<(BlockId, Range<u64>)> = self
+            .value_change_blocks
+            .iter_enumerated()
+            .map(|(id, block)| (id, block.info.region_range(BlockRegion::Waves)))
+            .collect();
+        // They are read from the end.
+        blocks.sort_by_key(|(id, _)| {
+            std::cmp::Reverse(self.value_change_blocks[*id].info.start_time)
+        });
+        Ok(BlockPrefetcher {
+            filename: self.filename.clone(),
+            reader: None,
+            cache: self.block_cache.clone(),
+            blocks,
+        })
+    }
+
+    /// Whether a var changes in a Value Change block, i.e. after its initial
+    /// value there. This doesn't decode anything so it is much cheaper than
+    /// reading the wave, e.g. to find which vars are active in a time range.
+    /// False for invalid IDs.
+    pub fn has_changes_in_block(&self, varid: VarId, block: BlockId) -> bool {
+        self.var_data
+            .get(varid)
+            .and_then(|var_data| var_data.wave_slices.get(block))
+            .is_some_and(|wave_slice| !wave_slice.is_empty())
+    }
+
+    /// The change times of the Value Change blocks, for navigating the waves
+    /// in time. Empty for files that aren't FST.
+    pub fn time_index(&self) -> &TimeIndex {
+        &self.time_index
+    }
+
+    /// The number of vars (including aliases) declared directly in a scope.
+    pub fn scope_var_count(&self, scope: ScopeId) -> usize {
+        self.hierarchy
+            .get(scope)
+            .map_or(0, |node| node.value.vars.len())
+    }
+
+    /// Like `scope_var_count()` but including all the scopes inside it.
+    /// Scopes are stored depth-first so this is just a sum over a slice.
+    pub fn scope_var_count_recursive(&self, scope: ScopeId) -> usize {
+        self.scope_var_count(scope)
+            + self
+                .hierarchy
+                .descendents(scope)
+                .iter()
+                .map(|node| node.value.vars.len())
+                .sum::<usize>()
+    }
+
+    /// The var that `varid` refers to. Aliases in the hierarchy
+    /// (`HierarchyVar::is_alias`) are declared with the `VarId` of the var
+    /// they alias, so this is always `varid`. Vars whose changes a writer
+    /// stored only once because they were identical (dynamic aliases in the
+    /// position table) are still different vars.
+    pub fn canonical_var(&self, varid: VarId) -> VarId {
+        varid
+    }
+
+    /// Each var once, in hierarchy order, leaving out the aliases. Use this
+    /// rather than the hierarchy to avoid counting aliases twice.
+    pub fn unique_vars(&self) -> impl Iterator<Item = VarId> + '_ {
+        self.hierarchy
+            .iter()
+            .flat_map(|node| node.value.vars.iter())
+            .filter(|var| !var.is_alias)
+            .map(|var| var.id)
+    }
+
+    /// Find a scope from its dotted path, e.g. `top.cpu.alu`. SystemVerilog
+    /// escaped identifiers (`top.\\foo.bar .alu`) may contain dots.
+    pub fn scope_by_path(&self, path: &str) -> Option<ScopeId> {
+        self.scope_by_components(&split_path(path))
+    }
+
+    /// Find a var from its dotted path, e.g. `top.cpu.alu.result`. The bit
+    /// range that some writers append to var names (`result [31:0]`) may
+    /// be omitted.
+    pub fn var_by_path(&self, path: &str) -> Option<VarId> {
+        let components = split_path(path);
+        let (var_name, scope_components) = components.split_last()?;
+        let scope = self.scope_by_components(scope_components)?;
+        self.hierarchy
+            .get(scope)?
+            .value
+            .vars
+            .iter()
+            .find(|var| name_matches(&var.name, var_name))
+            .map(|var| var.id)
+    }
+
+    /// The source file and line that a var is declared at, if the writer
+    /// recorded them. See `SourceStem`.
+    pub fn var_source(&self, varid: VarId) -> Option<(String, u32)> {
+        let source = self
+            .hierarchy
+            .iter()
+            .flat_map(|node| node.value.vars.iter())
+            .find(|var| var.id == varid && !var.is_alias)?
+            .source
+            .as_ref()?;
+        Some((source.path.to_string(), source.line))
+    }
+
+    /// The dotted path of a scope, the inverse of `scope_by_path()`.
+    pub fn scope_path(&self, scope: ScopeId) -> Option<String> {
+        let node = self.hierarchy.get(scope)?;
+        let mut names: Vec<&str> = self
+            .hierarchy
+            .parents(scope)
+            .map(|(_, parent)| parent.value.name.as_str())
+            .collect();
+        names.reverse();
+        names.push(&node.value.name);
+        Some(names.join("."))
+    }
+
+    /// The dotted path of a var declared in `scope`, the inverse of
+    /// `var_by_path()`. Any bit range on the end of its name is left off. For
+    /// an alias this is where the alias is declared.
+    pub fn var_path(&self, scope: ScopeId, var_name: &str) -> Option<String> {
+        Some(format!(
+            "{}.{}",
+            self.scope_path(scope)?,
+            base_name(var_name)
+        ))
+    }
+
+    /// The dotted path of a var's first (non-alias) declaration, e.g. to find
+    /// it again with `var_by_path()` after the file is reloaded and its
+    /// `VarId` may have changed.
+    pub fn var_path_by_id(&self, varid: VarId) -> Option<String> {
+        let (scope, var) = self.hierarchy.iter().enumerate().find_map(|(id, node)| {
+            let var = node
+                .value
+                .vars
+                .iter()
+                .find(|var| var.id == varid && !var.is_alias)?;
+            Some((ScopeId(id), var))
+        })?;
+        self.var_path(scope, &var.name)
+    }
+
+    fn scope_by_components(&self, components: &[&str]) -> Option<ScopeId> {
+        let (first, rest) = components.split_first()?;
+
+        // There can be more than one root.
+        let mut scope = self
+            .hierarchy
+            .iter()
+            .enumerate()
+            .find(|(id, node)| node.parent().0 == *id && name_matches(&node.value.name, first))
+            .map(|(id, _)| ScopeId(id))?;
+
+        for component in rest {
+            scope = self
+                .hierarchy
+                .children(scope)
+                .find(|(_, node)| name_matches(&node.value.name, component))
+                .map(|(id, _)| id)?;
+        }
+        Some(scope)
+    }
+
+    fn read_header(reader: &mut impl BufRead) -> Result<Header> {
+        Ok(Header {
+            start_time: reader.read_u64::<BigEndian>()?,
+            end_time: reader.read_u64::<BigEndian>()?,
+            real_endianness: reader.read_u64::<LittleEndian>()?,
+            writer_memory_use: reader.read_u64::<BigEndian>()?,
+            num_scopes: reader.read_u64::<BigEndian>()?,
+            num_hiearchy_vars: reader.read_u64::<BigEndian>()?,
+            num_vars: reader.read_u64::<BigEndian>()?,
+            num_vc_blocks: reader.read_u64::<BigEndian>()?,
+            timescale: reader.read_i8()?,
+            writer: reader.read_array()?,
+            date: reader.read_array()?,
+            reserved: reader.read_array()?,
+            filetype: reader.read_u8()?,
+            timezero: reader.read_i64::<BigEndian>()?,
         })
     }
 
@@ -658,10 +2348,17 @@ impl Fst {
             }
         };
 
-        let mut tree = espalier::Tree::with_capacity(num_scopes_hint);
+        let mut tree: Hierarchy = espalier::Tree::with_capacity(num_scopes_hint);
 
         let mut first = true;
         let mut next_varid = 0;
+        // Enum tables by handle, and the one that applies to the next var.
+        let mut enum_tables: HashMap<u64, Arc<EnumDef>> = HashMap::new();
+        let mut next_enum = None;
+        // Source file paths by index, and the file and line of the next scope
+        // or var.
+        let mut paths: HashMap<u64, Arc<str>> = HashMap::new();
+        let mut next_source = None;
 
         loop {
             let tag = compressed_reader.read_u8()?;
@@ -673,25 +2370,86 @@ impl Fst {
                 FST_ST_GEN_ATTRBEGIN => {
                     let attr_type = compressed_reader.read_u8()?;
                     let attr_subtype = compressed_reader.read_u8()?;
-                    let attr_name = compressed_reader.read_null_terminated_string(512)?;
+                    let attr_name_bytes =
+                        compressed_reader.read_null_terminated_bytes(MAX_NAME_LENGTH)?;
+                    let attr_name = String::from_utf8_lossy(&attr_name_bytes).to_string();
                     let attr_value = compressed_reader.read_varint()?;
-
-                    // TODO: Record attributes.
+                    let mut arg_from_name = 0;
 
                     info!("Attribute: {attr_name} = {attr_value}");
+
+                    if attr_type == FST_AT_MISC {
+                        match attr_subtype {
+                            FST_MT_PATHNAME => {
+                                paths.insert(attr_value, attr_name.as_str().into());
+                            }
+                            FST_MT_SOURCESTEM | FST_MT_SOURCEISTEM => {
+                                next_source = match attr_name_bytes.as_slice().read_varint() {
+                                    Ok(path_index) => {
+                                        arg_from_name = path_index;
+                                        match (paths.get(&path_index), u32::try_from(attr_value)) {
+                                            (Some(path), Ok(line)) => Some(SourceStem {
+                                                path: path.clone(),
+                                                line,
+                                            }),
+                                            _ => {
+                                                warn!(
+                                                    "Invalid source stem {path_index}:{attr_value}"
+                                                );
+                                                None
+                                            }
+                                        }
+                                    }
+                                    Err(err) => {
+                                        warn!("Invalid source stem path index: {err}");
+                                        None
+                                    }
+                                };
+                            }
+                            _ => {}
+                        }
+                    }
+
+                    if attr_type == FST_AT_MISC && attr_subtype == FST_MT_ENUMTABLE {
+                        if attr_name.is_empty() {
+                            next_enum = enum_tables.get(&attr_value).cloned();
+                            if next_enum.is_none() {
+                                warn!("Unknown enum table {attr_value}");
+                            }
+                        } else {
+                            match EnumDef::parse(&attr_name) {
+                                Ok(enum_def) => {
+                                    enum_tables.insert(attr_value, Arc::new(enum_def));
+                                }
+                                Err(err) => warn!("Invalid enum table {attr_value}: {err:#}"),
+                            }
+                        }
+                    }
+
+                    tree.last_mut().unwrap().value.attrs.push(HierarchyAttr {
+                        type_: attr_type,
+                        subtype: attr_subtype,
+                        name: attr_name,
+                        arg: attr_value,
+                        arg_from_name,
+                    });
                 }
                 FST_ST_GEN_ATTREND => {}
                 FST_ST_VCD_SCOPE => {
                     let scope_type = compressed_reader.read_u8()?;
-                    let scope_name = compressed_reader.read_null_terminated_string(512)?;
-                    let scope_component = compressed_reader.read_null_terminated_string(512)?;
+                    let (scope_name, raw_scope_name) =
+                        compressed_reader.read_null_terminated_string_exact(MAX_NAME_LENGTH)?;
+                    let scope_component =
+                        compressed_reader.read_null_terminated_string(MAX_NAME_LENGTH)?;
 
                     tree.push(HierarchyScope {
                         type_: scope_type,
                         name: scope_name,
+                        raw_name: raw_scope_name,
                         component: scope_component,
                         vars: Vec::new(),
                         attrs: Vec::new(),
+                        source: next_source.take(),
                     });
                 }
                 FST_ST_VCD_UPSCOPE => {
@@ -701,7 +2459,8 @@ impl Fst {
                 }
                 var_type => {
                     let var_direction = compressed_reader.read_u8()?;
-                    let var_name = compressed_reader.read_null_terminated_string(512)?;
+                    let (var_name, raw_var_name) =
+                        compressed_reader.read_null_terminated_string_exact(MAX_NAME_LENGTH)?;
                     let var_length = compressed_reader.read_varint()?;
                     let var_alias = compressed_reader.read_varint()?;
 
@@ -723,15 +2482,41 @@ impl Fst {
                         type_: var_type,
                         direction: var_direction,
                         name: var_name,
+                        raw_name: raw_var_name,
                         length: var_length,
                         id: VarId(id as usize),
                         is_alias: var_alias != 0,
+                        enum_def: next_enum.take(),
+                        source: next_source.take(),
                     });
                 }
             }
         }
 
-        // TODO: Verify we are at the end.
+        // The top-level upscope should be the last thing in the block. If
+        // there's anything after it the hierarchy ended early.
+        let trailing = match block_type {
+            // This reads straight from the file, so check the position.
+            BlockType::FST_BL_HIER => None,
+            _ => Some(compressed_reader.fill_buf()?.len() as u64),
+        };
+        let end = start_pos + block_length;
+        let trailing = match trailing {
+            Some(trailing) => trailing,
+            None => {
+                let position = reader.stream_position()?;
+                if position > end {
+                    bail!(
+                        "Hierarchy runs {} bytes past the end of its block",
+                        position - end
+                    );
+                }
+                end - position
+            }
+        };
+        if trailing != 0 {
+            bail!("Hierarchy has {trailing} bytes after the top-level upscope");
+        }
 
         // Restore the position at the end of the compressed block, otherwise
         // the block reader complains.
@@ -742,14 +2527,28 @@ impl Fst {
 
     fn read_value_change_block(
         reader: &mut (impl BufRead + Seek),
+        block: BlockId,
         block_length: u64,
         num_vars: u64,
         var_data: &mut TiVec<VarId, VarData>,
-    ) -> Result<ValueChangeBlockData> {
+    ) -> Result<(ValueChangeBlockData, Vec<u64>)> {
         // File is at `vc_start_time`.
 
+        // The tables are found from lengths at the end of the block, which
+        // are only checked to not overlap each other or the start of the
+        // block. Any way that they don't fit is the same error.
+        let layout_error = || {
+            format!(
+                "Corrupt Value Change block layout in block {}",
+                usize::from(block)
+            )
+        };
+
         // Record the offset of the end of the block.
-        let block_end = reader.stream_position()? + block_length;
+        let block_end = reader
+            .stream_position()?
+            .checked_add(block_length)
+            .with_context(layout_error)?;
 
         let start_time = reader.read_u64::<BigEndian>()?;
         let end_time = reader.read_u64::<BigEndian>()?;
@@ -772,16 +2571,19 @@ impl Fst {
         reader.seek(SeekFrom::Start(
             block_end
                 .checked_sub(24)
-                .context("Value Change time_uncompressed_length offset")?,
+                .filter(|&offset| offset >= waves_data_offset)
+                .with_context(layout_error)?,
         ))?;
 
         let time_uncompressed_length = reader.read_u64::<BigEndian>()?;
         let time_compressed_length = reader.read_u64::<BigEndian>()?;
         let time_count = reader.read_u64::<BigEndian>()?;
 
-        let position_length_offset = block_end
-            .checked_sub(time_compressed_length + 32)
-            .context("Value Change position_length_offset")?;
+        let position_length_offset = time_compressed_length
+            .checked_add(32)
+            .and_then(|length| block_end.checked_sub(length))
+            .filter(|&offset| offset >= waves_data_offset)
+            .with_context(layout_error)?;
         let time_data_offset = position_length_offset + 8;
 
         reader.seek(SeekFrom::Start(position_length_offset))?;
@@ -790,14 +2592,13 @@ impl Fst {
 
         let position_data_offset = position_length_offset
             .checked_sub(position_length)
-            .context("Value Change position_data_offset")?;
+            .filter(|&offset| offset >= waves_data_offset)
+            .with_context(layout_error)?;
 
         reader.seek(SeekFrom::Start(position_data_offset))?;
 
         // Read the waves offsets and lengths and add them to var_data.
-        let waves_data_length = position_data_offset
-            .checked_sub(waves_data_offset)
-            .context("Invalid Value Change block")?;
+        let waves_data_length = position_data_offset - waves_data_offset;
 
         Self::read_wave_slices(reader, num_vars, var_data, waves_data_length)?;
 
@@ -814,7 +2615,7 @@ impl Fst {
         // Seek to the next block.
         reader.seek(SeekFrom::Start(block_end))?;
 
-        Ok(ValueChangeBlockData {
+        let data = ValueChangeBlockData {
             info: ValueChangeBlockInfo {
                 start_time,
                 end_time,
@@ -833,15 +2634,47 @@ impl Fst {
                 time_compressed_length,
                 time_count,
             },
-            times,
-        })
+        };
+        Ok((data, times))
     }
 
-    fn read_geometry_block(
-        reader: &mut (impl BufRead + Seek),
-        block_length: u64,
-    ) -> Result<VarLengths> {
-        let uncompressed_length = reader.read_u64::<BigEndian>()?;
+    /// Get the var lengths from the hierarchy instead of the geometry block.
+    fn var_lengths_from_hierarchy(hierarchy: &Hierarchy, num_vars: usize) -> Result<VarLengths> {
+        let mut lengths: TiVec<VarId, Option<VarLength>> = vec![None; num_vars].into();
+        for var in hierarchy.iter().flat_map(|node| &node.value.vars) {
+            if var.is_alias {
+                continue;
+            }
+            // Reals have length 8 in the hierarchy (bytes, not bits).
+            let length = if var.is_real() {
+                VarLength::Real
+            } else {
+                VarLength::Bits(
+                    u32::try_from(var.length)
+                        .with_context(|| format!("Var {:?} is too long", var.name))?,
+                )
+            };
+            *lengths
+                .get_mut(var.id)
+                .with_context(|| format!("Var {:?} has an invalid ID", var.name))? = Some(length);
+        }
+
+        let mut var_lengths = VarLengths {
+            lengths: TiVec::with_capacity(num_vars),
+            lengths_long: HashMap::new(),
+        };
+        for (varid, length) in lengths.into_iter_enumerated() {
+            var_lengths
+                .push(length.with_context(|| format!("Var {} isn't in the hierarchy", varid.0))?);
+        }
+        Ok(var_lengths)
+    }
+
+    fn read_geometry_block(
+        reader: &mut (impl BufRead + Seek),
+        block_length: u64,
+    ) -> Result<VarLengths> {
+        let uncompressed_length = reader.read_u64::<BigEndian>()?;
         let count = reader.read_u64::<BigEndian>()?;
 
         let compressed_length = block_length
@@ -850,16 +2683,32 @@ impl Fst {
 
         let start_pos = reader.stream_position()?;
 
-        let mut bufreader;
+        // Every length takes at least one byte.
+        if count > uncompressed_length {
+            bail!("Geometry block has {count} entries but is only {uncompressed_length} bytes");
+        }
 
-        // If the compressed length is the same as the
-        // uncompressed length then it isn't compressed.
-        let mut compressed_reader: &mut dyn Read = if uncompressed_length == compressed_length {
-            reader
+        // If the compressed length is the same as the uncompressed length
+        // then it isn't compressed. Otherwise it should be ZLib. If it isn't
+        // it is unlikely to decompress to exactly the right length, which is
+        // checked because the lengths would be nonsense otherwise.
+        let data = if uncompressed_length == compressed_length {
+            reader.read_vec(to_usize(uncompressed_length, "Geometry length")?)?
         } else {
-            bufreader = BufReader::new(ZlibDecoder::new(&mut *reader));
-            &mut bufreader
+            let mut data = Vec::new();
+            ZlibDecoder::new((&mut *reader).take(compressed_length))
+                .take(uncompressed_length)
+                .read_to_end(&mut data)
+                .context("Geometry decompression failed")?;
+            if data.len() as u64 != uncompressed_length {
+                bail!(
+                    "Geometry decompression failed: got {} bytes instead of {uncompressed_length}",
+                    data.len()
+                );
+            }
+            data
         };
+        let mut data = data.as_slice();
 
         let mut var_lengths = VarLengths {
             lengths: TiVec::with_capacity(count as usize),
@@ -867,7 +2716,7 @@ impl Fst {
         };
 
         for varid in 0..count {
-            let length = compressed_reader.read_varint()?;
+            let length = data.read_varint().context("Invalid geometry block")?;
             if length == 0 {
                 // It's a real (always 8 bytes).
                 var_lengths.lengths.push(VAR_LENGTH_REAL);
@@ -886,6 +2735,12 @@ impl Fst {
                 var_lengths.lengths.push(length as u8);
             }
         }
+        if !data.is_empty() {
+            bail!(
+                "Geometry block has {} bytes left over after its {count} entries",
+                data.len()
+            );
+        }
 
         // Restore the position at the end of the compressed block, otherwise
         // the block reader complains.
@@ -921,6 +2776,7 @@ impl Fst {
         uncompressed_length: u64,
         count: u64,
         var_lengths: &VarLengths,
+        real_is_little_endian: bool,
         var_data: &mut TiVec<VarId, VarData>,
     ) -> Result<()> {
         let mut bufreader;
@@ -934,17 +2790,90 @@ impl Fst {
             &mut bufreader
         };
 
+        if count > var_data.len() as u64 {
+            bail!(
+                "Bits array has {} entries but there are only {} vars",
+                count,
+                var_data.len()
+            );
+        }
+
         for varid in 0..count as usize {
             let varid = VarId(varid);
             let length = var_lengths.length(varid);
 
-            let value = value_from_ascii(&mut reader, length)?;
+            let value = value_from_ascii(&mut reader, length, real_is_little_endian)?;
 
             var_data[varid].initial_values.push(value);
         }
         Ok(())
     }
 
+    /// Read the initial value of one var from the bits array of the first
+    /// Value Change block, for when they weren't all read by `load()`.
+    fn read_initial_value(
+        &self,
+        file: &mut FileData,
+        varid: VarId,
+        var_length: VarLength,
+    ) -> Result<Option<Value>> {
+        if let Some(block) = self.value_change_blocks.first() {
+            return self.read_initial_value_from_block(file, &block.info, varid, var_length);
+        }
+        // The first block may not have been parsed.
+        let Some(&(offset, block_length)) = self.unparsed_blocks.first() else {
+            return Ok(None);
+        };
+        let mut var_data = TiVec::new();
+        var_data.resize_with(self.var_data.len(), VarData::default);
+        let (block, _) = file.read_value_change_block(
+            BlockId::from(0),
+            offset,
+            block_length,
+            self.header.num_vars,
+            &mut var_data,
+        )?;
+        self.read_initial_value_from_block(file, &block.info, varid, var_length)
+    }
+
+    /// Read the initial value of one var from the bits array of `block`.
+    fn read_initial_value_from_block(
+        &self,
+        file: &mut FileData,
+        block: &ValueChangeBlockInfo,
+        varid: VarId,
+        var_length: VarLength,
+    ) -> Result<Option<Value>> {
+        if varid.0 as u64 >= block.bits_count {
+            return Ok(None);
+        }
+
+        // Every value is stored as one character per bit (8 bytes for reals).
+        let skip: u64 = (0..varid.0)
+            .map(|i| match self.var_lengths.length(VarId(i)) {
+                VarLength::Bits(bits) => bits as u64,
+                VarLength::Real => 8,
+            })
+            .sum();
+
+        // The bits array can be large so it is streamed instead of using
+        // `FileData::bytes()`.
+        let little_endian = self.header.real_is_little_endian();
+        let value = match file {
+            FileData::Reader(reader) => {
+                initial_value_from_bits(*reader, block, skip, var_length, little_endian)?
+            }
+            FileData::Mmap(data) => initial_value_from_bits(
+                &mut Cursor::new(*data),
+                block,
+                skip,
+                var_length,
+                little_endian,
+            )?,
+        };
+        Ok(Some(value))
+    }
+
     fn read_wave_slices(
         reader: &mut (impl BufRead + Seek),
         num_vars: u64,
@@ -972,26 +2901,16 @@ impl Fst {
         let mut varid = VarId(0);
         while varid.0 < num_vars {
             // Lowest bit indicates varint / svarint.
-            let mut varint_bytes = [0; 10];
-            let mut varint_length = 0;
-            loop {
-                let byte = reader.read_u8()?;
-                varint_bytes[varint_length] = byte;
-                varint_length += 1;
-                if byte & 0x80 == 0 {
-                    break;
-                }
-                if varint_length >= varint_bytes.len() {
-                    bail!("Invalid varint");
-                }
-            }
-
-            let varint_bytes = &varint_bytes[0..varint_length];
+            let mut varint_buffer = [0; 10];
+            let varint_bytes = read_varint_bytes(reader, &mut varint_buffer)?;
 
             if varint_bytes[0] & 0x01 == 0 {
                 // This is a varint encoding a run of zeros, equal to `run_length << 1`.
                 let zero_run_length =
                     decode_varint(&varint_bytes).context("Varint decode error")? >> 1;
+                if zero_run_length > (num_vars - varid.0) as u64 {
+                    bail!("Position table zero run of {zero_run_length} at {varid:?} goes past the last var");
+                }
 
                 for _ in 0..zero_run_length {
                     var_data[varid].wave_slices.push(0..0);
@@ -1011,6 +2930,12 @@ impl Fst {
                 x if x > 0 => {
                     // Delta from previous non-alias.
                     prev_non_alias_offset += x as u64;
+                    // The offset is at least 1 here, so this can't underflow.
+                    if prev_non_alias_offset - 1 > waves_data_length {
+                        bail!(
+                            "Position table offset for {varid:?} is past the end of the waves data"
+                        );
+                    }
                     // -1 because the offest in the file is from vc_waves_packtype.
                     // Use u64::MAX to mean "unresolved".
                     var_data[varid]
@@ -1076,113 +3001,512 @@ impl Fst {
         uncompressed_length: u64,
         count: u64,
     ) -> Result<Vec<u64>> {
-        let mut times = Vec::with_capacity(count as usize);
-
-        let mut time = 0;
+        // Every time takes at least one byte.
+        if count > uncompressed_length {
+            bail!("Time table has {count} entries but is only {uncompressed_length} bytes");
+        }
+        let data = reader.read_vec(to_usize(compressed_length, "Time table length")?)?;
 
-        // If the compressed length is different to the uncompressed length then it's compressed.
+        // If the compressed length is different to the uncompressed length
+        // then it's compressed with ZLib.
         if uncompressed_length != compressed_length {
-            // Compressed with ZLib.
-            let mut decoder = ZlibDecoder::new(reader);
+            return decompress_change_times(&data, uncompressed_length, count);
+        }
 
-            for n in 0..count {
-                time += decoder
-                    .read_varint()
-                    .with_context(|| format!("Reading compressed time table value {n}"))?;
-                times.push(time);
-            }
+        // Otherwise it should be uncompressed. There's no flag for this, and
+        // gtkwave only compresses the table if that makes it smaller, but
+        // other writers might store compressed data that happened to end up
+        // the same length. In that case decoding it as raw varints almost
+        // certainly won't use exactly all the bytes, so try decompressing it.
+        let times = decode_change_times(&data, count).or_else(|err| {
+            let times =
+                decompress_change_times(&data, uncompressed_length, count).map_err(|_| err)?;
+            warn!("Time table is compressed but no smaller than uncompressed");
+            Ok::<_, anyhow::Error>(times)
+        })?;
+        info!("Read change times: {:?}", times);
+        Ok(times)
+    }
+}
+
+/// Read the bytes of a varint or svarint without decoding it, since in the
+/// position table which one it is depends on the lowest bit.
+fn read_varint_bytes<'a>(reader: &mut impl Read, buffer: &'a mut [u8; 10]) -> Result<&'a [u8]> {
+    let mut length = 0;
+    loop {
+        let byte = reader.read_u8()?;
+        buffer[length] = byte;
+        length += 1;
+        if byte & 0x80 == 0 {
+            return Ok(&buffer[..length]);
+        }
+        if length >= buffer.len() {
+            bail!("Invalid varint");
+        }
+    }
+}
+
+/// Decode a time table: `count` varint deltas that must use all of `data`.
+fn decode_change_times(mut data: &[u8], count: u64) -> Result<Vec<u64>> {
+    let mut times = Vec::with_capacity(to_usize(count, "Time table length")?);
+    let mut time = 0u64;
+    for n in 0..count {
+        let delta = data
+            .read_varint()
+            .with_context(|| format!("Reading time table value {n}"))?;
+        time = time
+            .checked_add(delta)
+            .with_context(|| format!("Time table value {n} overflows"))?;
+        times.push(time);
+    }
+    if !data.is_empty() {
+        bail!("Time table has {} bytes after the last time", data.len());
+    }
+    Ok(times)
+}
+
+/// Decompress a ZLib compressed time table and decode it.
+fn decompress_change_times(data: &[u8], uncompressed_length: u64, count: u64) -> Result<Vec<u64>> {
+    let mut uncompressed = Vec::new();
+    ZlibDecoder::new(data)
+        .take(uncompressed_length)
+        .read_to_end(&mut uncompressed)
+        .context("Time table decompression failed")?;
+    if uncompressed.len() as u64 != uncompressed_length {
+        bail!(
+            "Time table decompressed to {} bytes but should be {uncompressed_length}",
+            uncompressed.len()
+        );
+    }
+    decode_change_times(&uncompressed, count)
+}
+
+/// Quote a CSV field if necessary.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Split a dotted hierarchical path into its components. Escaped identifiers
+/// start with a backslash and end at the next space, and can contain dots.
+fn split_path(path: &str) -> Vec<&str> {
+    let mut components = Vec::new();
+    let mut rest = path;
+    loop {
+        let end = if rest.starts_with('\\') {
+            // Find the end of the escaped identifier, then the dot after it.
+            let space = rest.find(' ').unwrap_or(rest.len());
+            components.push(&rest[..space]);
+            rest[space..].find('.').map(|dot| space + dot)
         } else {
-            for _ in 0..count {
-                time += reader.read_varint()?;
-                times.push(time);
+            let dot = rest.find('.');
+            components.push(&rest[..dot.unwrap_or(rest.len())]);
+            dot
+        };
+        match end {
+            Some(dot) => rest = &rest[dot + 1..],
+            None => return components,
+        }
+    }
+}
+
+/// Check if the name of a scope or var matches a path component. Trailing
+/// whitespace (which ends escaped identifiers) and bit ranges (`foo [7:0]`)
+/// are ignored.
+fn name_matches(name: &str, component: &str) -> bool {
+    base_name(name) == base_name(component)
+}
+
+/// A name without any bit range or trailing whitespace.
+fn base_name(name: &str) -> &str {
+    name.split_once(" [")
+        .map_or(name, |(base, _)| base)
+        .trim_end()
+}
+
+/// See `Fst::wave_mode()`. Returns None if no value is held during `range`.
+fn wave_mode(wave: &ValAndTimeVec, range: Range<u64>) -> Option<(Value, f64)> {
+    // Total duration and index of first occurrence (to break ties deterministically).
+    let mut durations: HashMap<&Value, (u64, usize)> = HashMap::new();
+
+    for (index, (time, value)) in wave.iter().enumerate() {
+        let next_time = wave.get(index + 1).map_or(range.end, |(t, _)| *t);
+        let start = (*time).max(range.start);
+        let end = next_time.min(range.end);
+        if start >= end {
+            continue;
+        }
+        durations.entry(value).or_insert((0, index)).0 += end - start;
+    }
+
+    durations
+        .into_iter()
+        .max_by_key(|(_, (duration, index))| (*duration, std::cmp::Reverse(*index)))
+        .map(|(value, (duration, _))| {
+            (
+                value.clone(),
+                duration as f64 / (range.end - range.start) as f64,
+            )
+        })
+}
+
+/// Get statistics about a wave within `range`, e.g. for one that has already
+/// been read. `signed` is whether buses are two's complement signed, for the
+/// min and max.
+pub fn wave_stats(
+    wave: &[(u64, Value)],
+    var_length: VarLength,
+    signed: bool,
+    range: Range<u64>,
+) -> WaveStats {
+    let mut stats = WaveStats::default();
+    if range.is_empty() {
+        return stats;
+    }
+
+    // Used to find the min and max.
+    let key = |value: &Value| -> Option<f64> {
+        match var_length {
+            VarLength::Bits(bits) if signed => value.to_i128(bits).map(|n| n as f64),
+            VarLength::Bits(bits) => value.to_u64(bits).map(|n| n as f64),
+            VarLength::Real => value.to_f64().filter(|x| !x.is_nan()),
+        }
+    };
+    let max_bits = if signed { 128 } else { 64 };
+    let wide = matches!(var_length, VarLength::Bits(bits) if bits > max_bits);
+
+    let mut high_time = 0;
+    for (index, (time, value)) in wave.iter().enumerate() {
+        if index > 0 && range.contains(time) && wave[index - 1].1 != *value {
+            stats.transitions += 1;
+        }
+
+        // The part of the range that this value is held for.
+        let next_time = wave.get(index + 1).map_or(range.end, |(t, _)| *t);
+        let start = (*time).max(range.start);
+        let end = next_time.min(range.end);
+        if start >= end {
+            continue;
+        }
+
+        if value.bit(0) == 1 {
+            high_time += end - start;
+        }
+        if let Some(k) = key(value).filter(|_| !wide) {
+            if stats.min.as_ref().and_then(key).is_none_or(|min| k < min) {
+                stats.min = Some(value.clone());
+            }
+            if stats.max.as_ref().and_then(key).is_none_or(|max| k > max) {
+                stats.max = Some(value.clone());
             }
         }
-        info!("Read change times: {:?}", times);
-        Ok(times)
+    }
+
+    if var_length == VarLength::Bits(1) {
+        stats.high_fraction = Some(high_time as f64 / (range.end - range.start) as f64);
+    }
+
+    let first = wave.partition_point(|(time, _)| *time < range.start);
+    let last = wave.partition_point(|(time, _)| *time < range.end);
+    stats.period = wave[first..last].detect_period();
+
+    stats
+}
+
+/// A value that is entirely X. Reals don't have X so they get NaN instead.
+fn x_value(var_length: VarLength) -> Value {
+    match var_length {
+        VarLength::Bits(bits) => {
+            let bits = bits as usize;
+            let mut bytes = LogicBytes::default();
+            bytes.resize(bits.div_ceil(4), 0b1010_1010);
+            // Clear the unused bits at the end.
+            let used = bits % 4;
+            if used > 0 {
+                if let Some(last) = bytes.last_mut() {
+                    *last &= (1 << (used * 2)) - 1;
+                }
+            }
+            Value::Logic(bytes)
+        }
+        VarLength::Real => Value::Real(f64::NAN),
     }
 }
 
 /// Read a value from packed bits that only contains 0s and 1s.
+///
+/// The bits are packed MSB first, starting at the top bit of the first byte,
+/// so when `bits` isn't a multiple of 8 the padding is in the bottom bits of
+/// the last byte. That is ignored so the value only has `bits` bits and
+/// compares equal to the same value from anywhere else.
 fn value_from_packed_bits(reader: &mut impl BufRead, bits: u32) -> Result<Value> {
-    let bytes_2 = (bits + 7) / 8;
-    let packed_bits = reader.read_tinyvec::<16>(bytes_2 as usize)?;
+    let bits = bits as usize;
+    let packed_bits = reader.read_tinyvec::<16>(bits.div_ceil(8))?;
+
+    let mut bytes = LogicBytes::default();
+    bytes.resize(bits.div_ceil(4), 0);
+    for i in 0..bits {
+        // Index of the bit in the packed data, where 0 is the MSB.
+        let j = bits - 1 - i;
+        let bit = (packed_bits[j / 8] >> (7 - j % 8)) & 1;
+        bytes[i / 4] |= bit << ((i % 4) * 2);
+    }
+    Ok(Value::Logic(bytes))
+}
+
+/// Collects one bit of each value, for `Fst::read_wave_bit()`.
+struct BitWave {
+    bit: u32,
+    wave: ValAndTimeVec,
+}
 
-    let mut val = Value::default();
-    let bytes_4 = (bits + 3) / 4;
+impl Extend<(u64, Value)> for BitWave {
+    fn extend<T: IntoIterator<Item = (u64, Value)>>(&mut self, iter: T) {
+        for (time, value) in iter {
+            let value = value.slice(self.bit..self.bit + 1);
+            if self.wave.last().map(|(_, prev)| prev) != Some(&value) {
+                self.wave.push((time, value));
+            }
+        }
+    }
+}
 
-    val.0.reserve(bytes_4 as usize);
+/// Counts changes of value, for `Fst::change_count()`.
+#[derive(Default)]
+struct ChangeCounter {
+    previous: Option<Value>,
+    count: usize,
+}
 
-    // Interleave the bits with 0s.
-    // https://graphics.stanford.edu/~seander/bithacks.html#Interleave64bitOps
-    // But I'll just do it the obvious way.
-    for b in packed_bits.iter() {
-        val.0.push(
-            ((b & 0b1000) << 2) | ((b & 0b0100) << 2) | ((b & 0b0010) << 1) | ((b & 0b0001) << 0),
-        );
-        val.0.push(
-            ((b & 0b1000_0000) >> 1)
-                | ((b & 0b0100_0000) >> 2)
-                | ((b & 0b0010_0000) >> 3)
-                | ((b & 0b0001_0000) >> 4),
-        );
+impl Extend<(u64, Value)> for ChangeCounter {
+    fn extend<T: IntoIterator<Item = (u64, Value)>>(&mut self, iter: T) {
+        for (_, value) in iter {
+            if self
+                .previous
+                .as_ref()
+                .is_some_and(|previous| *previous != value)
+            {
+                self.count += 1;
+            }
+            self.previous = Some(value);
+        }
+    }
+}
+
+/// The value of a wave at `time`, i.e. after any changes at that time. None
+/// before its first sample.
+fn value_at(wave: &[(u64, Value)], time: u64) -> Option<&Value> {
+    let index = wave.partition_point(|(t, _)| *t <= time);
+    index.checked_sub(1).map(|index| &wave[index].1)
+}
+
+/// Keeps the last value, for `Fst::block_final_value()`.
+#[derive(Default)]
+struct LastValue(Option<Value>);
+
+impl Extend<(u64, Value)> for LastValue {
+    fn extend<T: IntoIterator<Item = (u64, Value)>>(&mut self, iter: T) {
+        if let Some((_, value)) = iter.into_iter().last() {
+            self.0 = Some(value);
+        }
+    }
+}
+
+/// Remove samples that have the same value as the previous one, apart from
+/// the last sample.
+fn coalesce(wave: ValAndTimeVec, var_length: VarLength) -> ValAndTimeVec {
+    let last = wave.len().saturating_sub(1);
+    let mut coalesced: ValAndTimeVec = Vec::with_capacity(wave.len());
+    for (i, (time, value)) in wave.into_iter().enumerate() {
+        let unchanged = coalesced.last().is_some_and(|(_, prev)| match var_length {
+            VarLength::Bits(bits) => prev.eq_bits(&value, bits),
+            VarLength::Real => *prev == value,
+        });
+        if !unchanged || i == last {
+            coalesced.push((time, value));
+        }
     }
-    Ok(val)
+    coalesced
 }
 
-fn value_from_ascii(reader: &mut impl BufRead, var_length: VarLength) -> Result<Value> {
+/// Read a value that is stored as one character per bit, or as the raw 8
+/// bytes for reals. See `Header::real_is_little_endian()`.
+fn value_from_ascii(
+    reader: &mut impl BufRead,
+    var_length: VarLength,
+    real_is_little_endian: bool,
+) -> Result<Value> {
     Ok(match var_length {
         VarLength::Bits(bits) => {
             let bits = bits as usize;
 
             let buffer = reader.read_tinyvec::<64>(bits)?;
 
-            let (has_xz, other) = buffer.iter().fold((false, false), |acc, &c| match c {
-                b'0' | b'1' => acc,
-                b'x' | b'X' | b'z' | b'Z' => (true, acc.1),
-                _ => (acc.0, true),
-            });
+            info!("Reading {} bit value", bits);
+
+            let mut bytes = LogicBytes::default();
+            bytes.resize(bits.div_ceil(4), 0);
 
-            if other {
-                bail!("Value contains a bit that isn't 0, 1, X or Z. This isn't supported.");
+            // The characters are MSB first.
+            for (i, &c) in buffer.iter().rev().enumerate() {
+                let b = match bit_code(c) {
+                    Some(b) => b,
+                    None => bail!("Value contains an unsupported bit value {:?}", c as char),
+                };
+                bytes[i / 4] |= b << ((i % 4) * 2) as u8;
             }
 
-            info!("Reading {} bit value", bits);
-
-            let mut val = Value::default();
+            Value::Logic(bytes)
+        }
+        VarLength::Real => {
+            let mut bytes = [0; 8];
+            reader.read_exact(&mut bytes)?;
+            Value::from_real_bytes(bytes, real_is_little_endian)
+        }
+    })
+}
 
-            let bytes = (bits + 3) / 4;
+/// Read the initial value of a var from the bits array of a Value Change
+/// block. `skip` is the number of bytes of the values before it.
+fn initial_value_from_bits(
+    reader: &mut (impl BufRead + Seek),
+    info: &ValueChangeBlockInfo,
+    skip: u64,
+    var_length: VarLength,
+    real_is_little_endian: bool,
+) -> Result<Value> {
+    reader.seek(SeekFrom::Start(info.bits_data_offset))?;
+    if info.bits_uncompressed_length == info.bits_compressed_length {
+        reader.seek_relative(skip as i64)?;
+        value_from_ascii(reader, var_length, real_is_little_endian)
+    } else {
+        let mut decoder = BufReader::new(ZlibDecoder::new(reader));
+        io::copy(&mut (&mut decoder).take(skip), &mut io::sink())?;
+        value_from_ascii(&mut decoder, var_length, real_is_little_endian)
+    }
+}
 
-            val.0.resize(bytes, 0);
+/// Decode the data of a var in one Value Change block, i.e. `vc_waves_length`
+/// followed by the (possibly compressed) changes, and append them to `wave`.
+/// `packtype` and `times` are the block's.
+pub(crate) fn decode_wave_slice(
+    data: &[u8],
+    packtype: u8,
+    times: &[u64],
+    var_length: VarLength,
+    lenient: bool,
+    real_is_little_endian: bool,
+    wave: &mut impl Extend<(u64, Value)>,
+) -> Result<()> {
+    // Read vc_waves_length. This is the uncompressed length if compressed
+    // or 0 if not compressed. The rest is the compressed data.
+    let mut compressed_data = data;
+    let uncompressed_length_or_zero = compressed_data
+        .read_varint()
+        .context("Invalid wave data length")?;
+
+    info!(
+        "Uncompressed length (0=not compressed): {} Pack type: {}",
+        uncompressed_length_or_zero, packtype as char
+    );
+
+    // Writers only compress a var's data if that makes it smaller, so whether
+    // it is compressed is decided per var by vc_waves_length. The pack type is
+    // the same for the whole block and only says how the compressed vars are
+    // compressed.
+    let uncompressed_length =
+        to_usize(uncompressed_length_or_zero, "Uncompressed wave data length")?;
+    let uncompressed_data = match uncompressed_length {
+        0 => Cow::Borrowed(compressed_data),
+        uncompressed_length => Cow::Owned(decompress_wave_data(
+            compressed_data,
+            uncompressed_length,
+            packtype,
+        )?),
+    };
+
+    // Get the actual uncompressed length (it could have been zero).
+    let uncompressed_length = uncompressed_data.len();
+
+    let mut cursor = Cursor::new(uncompressed_data);
+
+    let mut time_index: u64 = 0;
+
+    while cursor.position() < uncompressed_length as u64 {
+        // info!("Reader pos: {}", cursor.position());
+        let (value, time_index_delta) = value_and_time_index_delta_from_waves_table(
+            &mut cursor,
+            var_length,
+            lenient,
+            real_is_little_endian,
+        )?;
+        // info!("Read value and time index delta: {:?}, {:?}", value, time_index_delta);
+        // A corrupt file can have any delta, e.g. in the 1-bit encoding where
+        // it is most of a varint.
+        time_index = time_index
+            .checked_add(time_index_delta)
+            .filter(|&index| index < times.len() as u64)
+            .with_context(|| {
+                format!(
+                    "Time index {time_index} + {time_index_delta} is past the end of the {} times in the block",
+                    times.len()
+                )
+            })?;
+        let time = times[time_index as usize];
+        wave.extend([(time, value)]);
+    }
 
-            for (i, &c) in buffer.iter().enumerate() {
-                // The only possible characters are:
-                // b'0' = 0x30 = 0b....0000
-                // b'1' = 0x31 = 0b....0001
-                // b'X' = 0x58 = 0b....1000
-                // b'x' = 0x78 = 0b....1000
-                // b'Z' = 0x5A = 0b....1010
-                // b'z' = 0x7A = 0b....1010
+    Ok(())
+}
 
-                // So we can make the value we want (0, 1, 2, 3) through:
-                //
-                //   (c | (c >> 1) | (c >> 2)) & 0x02
-                let b = ((c | (c >> 1) | (c >> 2)) & 0x02) as u8;
-                val.0[i / 4] |= b << ((i % 4) * 2) as u8;
+/// Decompress a var's wave data in a Value Change block with the codec for
+/// `packtype`: `F` is FastLZ, `4` is LZ4 and anything else is ZLib.
+pub(crate) fn decompress_wave_data(
+    compressed_data: &[u8],
+    uncompressed_length: usize,
+    packtype: u8,
+) -> Result<Vec<u8>> {
+    if compressed_data.is_empty() {
+        bail!("Wave data is empty but should uncompress to {uncompressed_length} bytes");
+    }
+    Ok(match packtype {
+        b'F' => {
+            // FastLZ. Have to read the data into memory in this case.
+            let mut uncompressed_data = vec![0; uncompressed_length];
+            let output = fastlz::decompress(compressed_data, &mut uncompressed_data)
+                .ok()
+                .context("FastLZ decompression")?;
+            if output.len() != uncompressed_data.len() {
+                bail!("Couldn't uncompress wave data using FastLZ");
             }
-
-            val
+            uncompressed_data
         }
-        VarLength::Real => {
-            // TODO: Handle endianness.
-            let todo = reader.read_f64::<LittleEndian>()?;
-            todo!()
+        b'4' => lz4_flex::block::decompress(compressed_data, uncompressed_length)?,
+        _ => {
+            // ZLib
+            let mut uncompressed_data = Vec::with_capacity(uncompressed_length);
+            ZlibDecoder::new(compressed_data)
+                .take(uncompressed_length as u64)
+                .read_to_end(&mut uncompressed_data)
+                .context("ZLib decompression")?;
+            if uncompressed_data.len() != uncompressed_length {
+                bail!("Couldn't uncompress wave data using ZLib");
+            }
+            uncompressed_data
         }
     })
 }
 
+/// Read a value and time index delta from a wave. If `lenient` is set, 1-bit
+/// values that can't be represented are read as X.
 fn value_and_time_index_delta_from_waves_table(
     reader: &mut impl BufRead,
     var_length: VarLength,
+    lenient: bool,
+    real_is_little_endian: bool,
 ) -> Result<(Value, u64)> {
     Ok(match var_length {
         VarLength::Bits(1) => {
@@ -1192,17 +3516,21 @@ fn value_and_time_index_delta_from_waves_table(
                 let time_index_delta = varint >> 2;
                 // 0 or 1
                 if varint & 0b10 == 0 {
-                    (Value(tiny_vec!([u8; 16] => 0)), time_index_delta)
+                    (Value::Logic(tiny_vec!([u8; 16] => 0)), time_index_delta)
                 } else {
-                    (Value(tiny_vec!([u8; 16] => 1)), time_index_delta)
+                    (Value::Logic(tiny_vec!([u8; 16] => 1)), time_index_delta)
                 }
             } else {
                 let time_index_delta = varint >> 4;
-                // X, Z, etc.
-                match varint & 0b1110 {
-                    0b0000 => (Value(tiny_vec!([u8; 16] => 2)), time_index_delta), // X
-                    0b0010 => (Value(tiny_vec!([u8; 16] => 3)), time_index_delta), // Z
-                    _ => bail!("Values other than 0, 1, X, Z are not supported."),
+                // X, Z, etc. These are the value characters in this order.
+                let c = b"xzhuwl-?"[((varint >> 1) & 0b111) as usize];
+                match bit_code(c) {
+                    Some(b) => (Value::Logic(tiny_vec!([u8; 16] => b)), time_index_delta),
+                    None if lenient => {
+                        warn!("Unsupported bit value {:?}; using X", c as char);
+                        (Value::Logic(tiny_vec!([u8; 16] => 2)), time_index_delta)
+                    }
+                    None => bail!("Unsupported bit value {:?}", c as char),
                 }
             }
         }
@@ -1216,45 +3544,109 @@ fn value_and_time_index_delta_from_waves_table(
                 value_from_packed_bits(reader, bits)?
             } else {
                 // Encoded as raw ASCII.
-                value_from_ascii(reader, var_length)?
+                value_from_ascii(reader, var_length, real_is_little_endian)?
             };
             (value, time_index_delta)
         }
         VarLength::Real => {
-            todo!()
+            // Reals are written like 8 character bit values whose characters
+            // are the raw bytes of the double. So they are almost always
+            // "ASCII", but if every byte happens to be '0' or '1' they are
+            // packed into a single byte like binary values.
+            let time_index_delta_and_is_binary = reader.read_varint()?;
+            let time_index_delta = time_index_delta_and_is_binary >> 1;
+            let is_binary = (time_index_delta_and_is_binary & 1) == 0;
+
+            let value = if is_binary {
+                let packed = reader.read_u8()?;
+                let bytes = std::array::from_fn(|j| b'0' + ((packed >> (7 - j)) & 1));
+                Value::from_real_bytes(bytes, real_is_little_endian)
+            } else {
+                value_from_ascii(reader, var_length, real_is_little_endian)?
+            };
+            (value, time_index_delta)
         }
     })
 }
 
-// impl Waves for Fst {
-//     fn hierarchy(&self) -> &super::Hierarchy {
-//         todo!()
-//     }
+impl Waves for Fst {
+    fn hierarchy(&self) -> &Hierarchy {
+        &self.hierarchy
+    }
 
-//     fn load_waves(&mut self, variable_ids: std::collections::HashSet<usize>) -> Result<()> {
-//         todo!()
-//     }
+    fn load_waves(&mut self, varids: &HashSet<VarId>) -> Result<()> {
+        if let WaveSource::Memory(_) = self.waves {
+            // They're all loaded already.
+            return Ok(());
+        }
+        self.load_blocks()?;
+        for &varid in varids {
+            if !self.loaded_waves.contains_key(&varid) {
+                let wave = self.read_wave(varid)?;
+                self.loaded_waves.insert(varid, wave);
+            }
+        }
+        Ok(())
+    }
 
-//     fn wave(&self, variable_id: super::VariableId) -> Result<&super::Wave<u8>> {
-//         todo!()
-//     }
+    fn wave(&self, varid: VarId) -> Result<&ValAndTimeVec> {
+        match &self.waves {
+            WaveSource::Memory(waves) => waves.get(varid).context("Invalid var ID"),
+            WaveSource::File(_) | WaveSource::Mmap(_) => self
+                .loaded_waves
+                .get(&varid)
+                .with_context(|| format!("Wave for {varid:?} has not been loaded")),
+        }
+    }
 
-//     fn times(&self) -> &[u64] {
-//         todo!()
-//     }
+    /// This has to gather the times from every block so it isn't free.
+    fn times(&self) -> Vec<u64> {
+        match &self.waves {
+            WaveSource::Memory(waves) => {
+                let mut times: Vec<u64> = waves
+                    .iter()
+                    .flat_map(|wave| wave.iter().map(|(time, _)| *time))
+                    .collect();
+                times.sort_unstable();
+                times.dedup();
+                times
+            }
+            WaveSource::File(_) | WaveSource::Mmap(_) => self.time_index.all_times().collect(),
+        }
+    }
 
-//     fn timebase_order(&self) -> i8 {
-//         todo!()
-//     }
+    fn timebase_order(&self) -> i8 {
+        self.header.timescale
+    }
 
-//     fn variable_info(&self, variable_id: super::VariableId) -> Result<&super::VariableInfo> {
-//         todo!()
-//     }
-// }
+    fn variable_info(&self, varid: VarId) -> Result<VariableInfo> {
+        let var = self
+            .hierarchy
+            .iter()
+            .flat_map(|node| node.value.vars.iter())
+            .find(|var| var.id == varid && !var.is_alias)
+            .context("Invalid var ID")?;
+        Ok(VariableInfo {
+            name: var.name.clone(),
+            direction: var.direction,
+            length: self.var_lengths.length(varid),
+            type_: var.type_,
+        })
+    }
+}
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::fixtures::{
+        blackout_block, block, geometry_block, two_block_file, write_block, write_blocks, Fixture,
+    };
+    use crate::varint::{encode_svarint, encode_varint};
+    use byteorder::WriteBytesExt;
+    use flate2::{
+        write::{GzEncoder, ZlibEncoder},
+        Compression,
+    };
 
     fn logging_setup() {
         env_logger::builder()
@@ -1271,7 +3663,7 @@ mod test {
             "/../samples/hdl-example.fst"
         ));
 
-        let mut fst = Fst::load(file).unwrap();
+        let fst = Fst::load(file).unwrap();
 
         // dbg!(fst.header.num_vars);
         for varid in [7] {
@@ -1280,4 +3672,1816 @@ mod test {
             dbg!(&varid, &wave);
         }
     }
+
+    /// Read waves of every sample file, as a broad smoke test. Decoding every
+    /// var of the large sample takes over a minute in a debug build, so only
+    /// an evenly spread subset of the vars of each file is read.
+    #[test]
+    fn test_reading_all_samples() {
+        const MAX_VARS_PER_FILE: usize = 200;
+
+        let samples = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../samples"));
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(samples)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "fst"))
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty());
+
+        for path in paths {
+            let fst = Fst::load(&path).unwrap_or_else(|err| panic!("{path:?}: {err:?}"));
+            let range = fst.header.start_time..=fst.header.end_time;
+            let step = fst.var_data.len().div_ceil(MAX_VARS_PER_FILE);
+            for varid in (0..fst.var_data.len()).step_by(step).map(VarId) {
+                let wave = fst
+                    .read_wave(varid)
+                    .unwrap_or_else(|err| panic!("{path:?} {varid:?}: {err:?}"));
+                assert!(
+                    wave.windows(2).all(|pair| pair[0].0 <= pair[1].0),
+                    "{path:?} {varid:?}: times aren't monotonic"
+                );
+                // The initial value is at 0 even if the file starts later.
+                for (time, _) in wave.iter().skip(1) {
+                    assert!(
+                        range.contains(time),
+                        "{path:?} {varid:?}: time {time} is outside {range:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_reload_appended_finished_file() {
+        let file = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../samples/hdl-example.fst"
+        ));
+        let mut fst = Fst::load(file).unwrap();
+        assert_eq!(fst.reload_appended().unwrap(), 0);
+        assert_eq!(fst.value_change_blocks.len(), 1);
+        fst.read_wave(VarId(7)).unwrap();
+    }
+
+    #[test]
+    fn test_reload_appended() {
+        let first = Fixture {
+            start_time: 0,
+            end_time: 49,
+            ..Fixture::new()
+        }
+        .var("clk", 1, "0", &[(10, "1")]);
+        let second = Fixture {
+            start_time: 50,
+            end_time: 100,
+            ..Fixture::new()
+        }
+        .var("clk", 1, "1", &[(60, "0")]);
+        // A writer that puts the hierarchy first, so the file can be loaded
+        // while it is still being written.
+        let mut file = write_blocks(&[
+            first.header_block(),
+            first.hierarchy_block(),
+            geometry_block(&[1]),
+            first.value_change_block(),
+        ]);
+        let mut fst = Fst::load(file.path()).unwrap();
+        assert_eq!(fst.value_change_blocks.len(), 1);
+
+        // The next block, and one that is still being written.
+        file.write_all(&second.value_change_block()).unwrap();
+        file.write_all(&block(BlockType::FST_BL_SKIP, &[0; 16]))
+            .unwrap();
+        file.flush().unwrap();
+
+        assert_eq!(fst.reload_appended().unwrap(), 1);
+        assert_eq!(fst.value_change_blocks.len(), 2);
+        assert_eq!(fst.header.end_time, 100);
+        let wave = fst.read_wave(VarId(0)).unwrap();
+        let bits: Vec<(u64, String)> = wave
+            .iter()
+            .map(|(time, value)| (*time, value.bits_string(1)))
+            .collect();
+        assert_eq!(
+            bits,
+            [
+                (0, "0".to_string()),
+                (10, "1".to_string()),
+                (60, "0".to_string())
+            ]
+        );
+        assert_eq!(fst.reload_appended().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_missing_initial_value() {
+        let file = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../samples/hdl-example.fst"
+        ));
+        let mut fst = Fst::load(file).unwrap();
+        let varid = VarId(7);
+        let with_initial = fst.read_wave(varid).unwrap();
+
+        fst.var_data[varid].initial_values.clear();
+
+        let skipped = fst.read_wave(varid).unwrap();
+        assert_eq!(skipped, with_initial[1..]);
+
+        let options = ReadOptions {
+            missing_initial_value: MissingInitialValue::X,
+            ..Default::default()
+        };
+        let x = fst.read_wave_with_options(varid, &options).unwrap();
+        assert_eq!(x[1..], with_initial[1..]);
+        assert_eq!(x[0].0, fst.header.start_time);
+        assert_eq!(x[0].1, x_value(fst.var_lengths.length(varid)));
+    }
+
+    #[test]
+    fn test_fixture() {
+        let file = Fixture::new()
+            .var("clk", 1, "0", &[(10, "1"), (20, "0"), (30, "z")])
+            .var("idle", 1, "1", &[])
+            .var(
+                "weak",
+                1,
+                "h",
+                &[(10, "l"), (20, "u"), (30, "w"), (40, "-")],
+            )
+            .var("bus", 3, "1x0", &[])
+            .write();
+        let fst = Fst::load(file.path()).unwrap();
+        assert_eq!(fst.var_by_path("top.idle"), Some(VarId(1)));
+
+        let values = |wave: ValAndTimeVec| -> Vec<(u64, u8)> {
+            wave.into_iter().map(|(t, v)| (t, v.bytes()[0])).collect()
+        };
+        assert_eq!(
+            values(fst.read_wave(VarId(0)).unwrap()),
+            [(0, 0), (10, 1), (20, 0), (30, 3)]
+        );
+        assert_eq!(values(fst.read_wave(VarId(1)).unwrap()), [(0, 1)]);
+        assert_eq!(
+            values(fst.read_wave(VarId(2)).unwrap()),
+            [(0, 1), (10, 0), (20, 2), (30, 2), (40, 2)]
+        );
+        // LSB first.
+        assert_eq!(values(fst.read_wave(VarId(3)).unwrap()), [(0, 0b01_10_00)]);
+    }
+
+    #[test]
+    fn test_single_changing_var() {
+        // Only one var changes so every other position table entry is a zero
+        // run, and the changing var's slice has to extend to the end of the
+        // waves data.
+        for changing in 0..3 {
+            let mut fixture = Fixture::new();
+            for n in 0..3 {
+                let changes: &[(u64, &str)] = if n == changing {
+                    &[(10, "1"), (20, "0")]
+                } else {
+                    &[]
+                };
+                fixture = fixture.var(&format!("v{n}"), 1, "0", changes);
+            }
+            let file = fixture.write();
+            let fst = Fst::load(file.path()).unwrap();
+
+            let info = &fst.value_change_blocks[BlockId(0)].info;
+            let waves_data_length = info.position_data_offset - info.waves_data_offset;
+            for n in 0..3 {
+                let slice = &fst.var_data[VarId(n)].wave_slices[BlockId(0)];
+                if n == changing {
+                    assert_eq!(*slice, 0..waves_data_length);
+                } else {
+                    assert!(slice.is_empty());
+                }
+            }
+
+            let values: Vec<_> = fst
+                .read_wave(VarId(changing))
+                .unwrap()
+                .into_iter()
+                .map(|(t, v)| (t, v.bytes()[0]))
+                .collect();
+            assert_eq!(values, [(0, 0), (10, 1), (20, 0)]);
+        }
+    }
+
+    #[test]
+    fn test_compressed_waves() {
+        let mut fixture = Fixture::new()
+            .var("clk", 1, "0", &[(10, "1"), (20, "0"), (30, "1")])
+            .var("idle", 1, "1", &[])
+            .var("data", 2, "xx", &[(15, "z1"), (25, "x0")]);
+        let uncompressed = Fst::load(fixture.write().path()).unwrap();
+        fixture.compress_waves = true;
+        let file = fixture.write();
+        let compressed = Fst::load(file.path()).unwrap();
+        assert_eq!(
+            compressed.value_change_blocks[BlockId(0)]
+                .info
+                .waves_packtype,
+            b'Z'
+        );
+        for varid in (0..3).map(VarId) {
+            assert_eq!(
+                compressed.read_wave(varid).unwrap(),
+                uncompressed.read_wave(varid).unwrap()
+            );
+        }
+
+        // A compressed var with no data.
+        let err = decode_wave_slice(
+            &[5],
+            b'Z',
+            &[0],
+            VarLength::Bits(1),
+            false,
+            true,
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Wave data is empty but should uncompress to 5 bytes"
+        );
+    }
+
+    #[test]
+    fn test_time_index_out_of_range() {
+        // Uncompressed 1-bit changes to 1 at index 1 and then 0 at a huge
+        // index. Its delta has the top bits of the varint.
+        let mut data = vec![0, 1 << 2 | 1 << 1];
+        let mut encoded = [0; 10];
+        let length = encode_varint(&mut encoded, u64::MAX << 2);
+        data.extend_from_slice(&encoded[..length]);
+
+        let mut wave = Vec::new();
+        let err = decode_wave_slice(
+            &data,
+            b'Z',
+            &[0, 10, 20],
+            VarLength::Bits(1),
+            false,
+            true,
+            &mut wave,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Time index 1 + {} is past the end of the 3 times in the block",
+                u64::MAX >> 2
+            )
+        );
+        assert_eq!(wave.len(), 1);
+
+        // Past the end by one.
+        let err = decode_wave_slice(
+            &[0, 3 << 2],
+            b'Z',
+            &[0, 10, 20],
+            VarLength::Bits(1),
+            false,
+            true,
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Time index 0 + 3 is past the end of the 3 times in the block"
+        );
+    }
+
+    #[test]
+    fn test_waves_trait() {
+        let file = Fixture::new()
+            .var("clk", 1, "0", &[(10, "1"), (20, "0")])
+            .var("data", 1, "x", &[(15, "z")])
+            .write();
+        let mut fst = Fst::load(file.path()).unwrap();
+
+        assert!(fst.wave(VarId(1)).is_err());
+        fst.load_waves(&HashSet::from([VarId(1)])).unwrap();
+        assert_eq!(fst.wave(VarId(1)).unwrap().len(), 2);
+        assert!(fst.wave(VarId(0)).is_err());
+
+        assert_eq!(fst.times(), [10, 15, 20]);
+        assert_eq!(fst.timebase_order(), -9);
+        assert_eq!(fst.hierarchy().len(), 1);
+        assert_eq!(
+            fst.variable_info(VarId(1)).unwrap(),
+            VariableInfo {
+                name: "data".to_string(),
+                direction: 0,
+                length: VarLength::Bits(1),
+                type_: 16,
+            }
+        );
+        assert!(fst.variable_info(VarId(2)).is_err());
+    }
+
+    #[test]
+    fn test_read_wave_absolute() {
+        let mut fixture = Fixture::new().var("clk", 1, "0", &[(10, "1"), (20, "0")]);
+        fixture.timezero = -50;
+        let file = fixture.write();
+        let fst = Fst::load(file.path()).unwrap();
+
+        let times: Vec<i64> = fst
+            .read_wave_absolute(VarId(0))
+            .unwrap()
+            .into_iter()
+            .map(|(time, _)| time)
+            .collect();
+        assert_eq!(times, [-50, -40, -30]);
+    }
+
+    #[test]
+    fn test_var_length_display() {
+        assert_eq!(VarLength::Bits(8).to_string(), "8 bits");
+        assert_eq!(VarLength::Bits(1).to_string(), "1 bit");
+        assert_eq!(VarLength::Bits(0).to_string(), "0 bits");
+        assert_eq!(VarLength::Real.to_string(), "real");
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut fixture = Fixture::new()
+            .var("clk", 1, "0", &[(10, "1"), (20, "0")])
+            .var("data", 8, "xxxxxxxx", &[]);
+        fixture.memory_required = 1234;
+        let file = fixture.write();
+        let fst = Fst::load(file.path()).unwrap();
+        assert_eq!(
+            fst.summary(),
+            Summary {
+                writer: "fixture".to_string(),
+                date: String::new(),
+                timescale: -9,
+                start_time: 0,
+                end_time: 100,
+                timezero: 0,
+                num_scopes: 1,
+                num_hierarchy_vars: 2,
+                num_vars: 2,
+                num_value_change_blocks: 1,
+                peak_block_memory: 1234,
+            }
+        );
+    }
+
+    #[test]
+    fn test_display_name() {
+        let var = HierarchyVar {
+            name: "gen\r\n\0[0]".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(var.display_name(), "gen\\r\\n\\u{0}[0]");
+        assert!(matches!(
+            escape_control_chars("top.\\a.b "),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_concurrent_reads() {
+        let file = Fixture::new()
+            .var("clk", 1, "0", &[(10, "1"), (20, "0")])
+            .var("data", 2, "xx", &[(15, "z1")])
+            .write();
+        let fst = Fst::load(file.path()).unwrap();
+        let expected = fst.read_wave(VarId(1)).unwrap();
+
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..10 {
+                        assert_eq!(fst.read_wave(VarId(1)).unwrap(), expected);
+                    }
+                });
+            }
+        });
+
+        let WaveSource::File(pool) = &fst.waves else {
+            panic!("Expected file waves");
+        };
+        assert!(pool.readers.lock().unwrap().len() <= pool.max_readers);
+    }
+
+    #[test]
+    fn test_mmap() {
+        let file = Fixture::new()
+            .var("clk", 1, "0", &[(10, "1"), (20, "0")])
+            .var("data", 2, "xx", &[(15, "z1")])
+            .var("idle", 1, "z", &[])
+            .write();
+        let buffered = Fst::load(file.path()).unwrap();
+        for read_initial_values in [true, false] {
+            let mapped = Fst::load_with_options(
+                file.path(),
+                &LoadOptions {
+                    read_initial_values,
+                    mmap: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            assert!(matches!(mapped.waves, WaveSource::Mmap(_)));
+
+            for varid in (0..3).map(VarId) {
+                assert_eq!(
+                    mapped.read_wave(varid).unwrap(),
+                    buffered.read_wave(varid).unwrap()
+                );
+                assert_eq!(
+                    mapped.initial_value(varid).unwrap(),
+                    buffered.initial_value(varid).unwrap()
+                );
+            }
+
+            let mut waves = Vec::new();
+            mapped
+                .for_each_wave(|varid, wave| waves.push((varid, wave.clone())))
+                .unwrap();
+            assert_eq!(waves.len(), 3);
+            assert_eq!(waves[1].1, buffered.read_wave(VarId(1)).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_read_wave_packed() {
+        let file = Fixture::new()
+            .var("clk", 1, "0", &[(10, "1"), (20, "0")])
+            .var("wide", 70, &"x".repeat(70), &[(15, &"z".repeat(70))])
+            .write();
+        let fst = Fst::load(file.path()).unwrap();
+        for varid in (0..2).map(VarId) {
+            let packed = fst.read_wave_packed(varid).unwrap();
+            assert_eq!(packed.unpack(), fst.read_wave(varid).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_merged_changes() {
+        let file = Fixture::new()
+            .var("clk", 1, "0", &[(10, "1"), (20, "0")])
+            .var("data", 2, "xx", &[(10, "z1"), (15, "1z")])
+            .write();
+        let fst = Fst::load(file.path()).unwrap();
+
+        let rows: Vec<(u64, Vec<VarId>)> = fst
+            .merged_changes(&[VarId(1), VarId(0)])
+            .unwrap()
+            .into_iter()
+            .map(|(time, changes)| (time, changes.into_iter().map(|(varid, _)| varid).collect()))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                (0, vec![VarId(1), VarId(0)]),
+                (10, vec![VarId(1), VarId(0)]),
+                (15, vec![VarId(1)]),
+                (20, vec![VarId(0)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lazy_initial_values() {
+        let file = Fixture::new()
+            .var("clk", 1, "1", &[(10, "0")])
+            .var("data", 3, "x1z", &[(15, "zz1")])
+            .var("idle", 2, "zx", &[])
+            .write();
+        let eager = Fst::load(file.path()).unwrap();
+        let lazy = Fst::load_with_options(
+            file.path(),
+            &LoadOptions {
+                read_initial_values: false,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert!(lazy.var_data.iter().all(|v| v.initial_values.is_empty()));
+        for varid in 0..3 {
+            assert_eq!(
+                lazy.read_wave(VarId(varid)).unwrap(),
+                eager.read_wave(VarId(varid)).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_unparsed_blocks() {
+        let file = Fixture::new()
+            .var("clk", 1, "1", &[(10, "0"), (20, "1")])
+            .var("data", 3, "x1z", &[(15, "zz1")])
+            .var("idle", 2, "zx", &[])
+            .write();
+        let eager = Fst::load(file.path()).unwrap();
+        for mmap in [false, true] {
+            let mut lazy = Fst::load_with_options(
+                file.path(),
+                &LoadOptions {
+                    read_blocks: false,
+                    mmap,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            assert!(lazy.value_change_blocks.is_empty());
+            assert_eq!(lazy.unparsed_blocks.len(), 1);
+            // These need the blocks to be parsed first.
+            assert!(lazy.for_each_wave(|_, _| {}).is_err());
+            assert!(lazy.block_prefetcher().is_err());
+            let err = lazy.block_final_value(VarId(0), BlockId(0)).unwrap_err();
+            assert!(err.to_string().contains("load_blocks()"), "{err}");
+
+            for varid in (0..3).map(VarId) {
+                assert_eq!(
+                    lazy.read_wave(varid).unwrap(),
+                    eager.read_wave(varid).unwrap()
+                );
+                assert_eq!(
+                    lazy.initial_value(varid).unwrap(),
+                    eager.initial_value(varid).unwrap()
+                );
+            }
+            assert_eq!(
+                lazy.change_count(VarId(0)).unwrap(),
+                eager.change_count(VarId(0)).unwrap()
+            );
+
+            lazy.load_blocks().unwrap();
+            assert!(lazy.unparsed_blocks.is_empty());
+            assert_eq!(lazy.block_time_ranges(), eager.block_time_ranges());
+            for varid in (0..3).map(VarId) {
+                assert_eq!(
+                    lazy.read_wave(varid).unwrap(),
+                    eager.read_wave(varid).unwrap()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_initial_value() {
+        let file = Fixture::new()
+            .var("clk", 1, "1", &[(10, "0")])
+            .var("data", 3, "x1z", &[(15, "zz1")])
+            .write();
+        let eager = Fst::load(file.path()).unwrap();
+        let lazy = Fst::load_with_options(
+            file.path(),
+            &LoadOptions {
+                read_initial_values: false,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        for fst in [&eager, &lazy] {
+            let clk = fst.initial_value(VarId(0)).unwrap().unwrap();
+            assert_eq!(clk.bits_string(1), "1");
+            let data = fst.initial_value(VarId(1)).unwrap().unwrap();
+            assert_eq!(data.bits_string(3), "x1z");
+            assert!(fst.initial_value(VarId(2)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_scope_var_count() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(
+            b"$scope module top $end
+$var wire 1 ! a $end
+$scope module sub $end
+$var wire 1 ! a_alias $end
+$var wire 1 \" b $end
+$scope module leaf $end
+$upscope $end
+$upscope $end
+$var wire 1 # c $end
+$upscope $end
+$enddefinitions $end
+",
+        )
+        .unwrap();
+        let fst = crate::vcd::load(file.path()).unwrap();
+
+        let top = fst.scope_by_path("top").unwrap();
+        let sub = fst.scope_by_path("top.sub").unwrap();
+        let leaf = fst.scope_by_path("top.sub.leaf").unwrap();
+        assert_eq!(fst.scope_var_count(top), 2);
+        assert_eq!(fst.scope_var_count_recursive(top), 4);
+        assert_eq!(fst.scope_var_count(sub), 2);
+        assert_eq!(fst.scope_var_count_recursive(sub), 2);
+        assert_eq!(fst.scope_var_count_recursive(leaf), 0);
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let file = Fixture::new()
+            .var(
+                "data",
+                2,
+                "x1",
+                &[(10, "x1"), (15, "1z"), (20, "1z"), (30, "1z")],
+            )
+            .write();
+        let fst = Fst::load(file.path()).unwrap();
+        let options = ReadOptions {
+            coalesce: true,
+            ..Default::default()
+        };
+
+        let times =
+            |wave: ValAndTimeVec| -> Vec<u64> { wave.into_iter().map(|(t, _)| t).collect() };
+        assert_eq!(times(fst.read_wave(VarId(0)).unwrap()), [0, 10, 15, 20, 30]);
+        assert_eq!(
+            times(fst.read_wave_with_options(VarId(0), &options).unwrap()),
+            [0, 15, 30]
+        );
+    }
+
+    #[test]
+    fn test_for_each_wave() {
+        let file = Fixture::new()
+            .var("clk", 1, "0", &[(10, "1"), (20, "0")])
+            .var("bus", 3, "x01", &[(15, "1z0"), (20, "zzz")])
+            .var("idle", 2, "zx", &[])
+            .write();
+        let fst = Fst::load(file.path()).unwrap();
+
+        let mut waves = Vec::new();
+        fst.for_each_wave(|varid, wave| waves.push((varid, wave.clone())))
+            .unwrap();
+        let expected: Vec<_> = (0..3)
+            .map(|varid| (VarId(varid), fst.read_wave(VarId(varid)).unwrap()))
+            .collect();
+        assert_eq!(waves, expected);
+    }
+
+    #[test]
+    fn test_export_csv() {
+        let file = Fixture::new()
+            .var("clk", 1, "0", &[(10, "1"), (20, "0")])
+            .var("a,b", 3, "x01", &[(15, "1z0")])
+            .write();
+        let fst = Fst::load(file.path()).unwrap();
+
+        let mut out = Vec::new();
+        fst.export_csv(&mut out, &[VarId(0), VarId(1)]).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "time,clk,\"a,b\"\n0,0,x01\n10,1,x01\n15,1,1z0\n20,0,1z0\n"
+        );
+    }
+
+    #[test]
+    fn test_missing_geometry_block() {
+        let fixture = Fixture::new()
+            .var("clk", 1, "0", &[(10, "1"), (20, "0")])
+            .var("bus", 3, "x01", &[(15, "1z0")])
+            .var("wide", 300, &"x".repeat(300), &[]);
+        let file = write_blocks(&[
+            fixture.header_block(),
+            fixture.value_change_block(),
+            fixture.hierarchy_block(),
+        ]);
+        let fst = Fst::load(file.path()).unwrap();
+
+        assert_eq!(fst.var_lengths.length(VarId(0)), VarLength::Bits(1));
+        assert_eq!(fst.var_lengths.length(VarId(1)), VarLength::Bits(3));
+        assert_eq!(fst.var_lengths.length(VarId(2)), VarLength::Bits(300));
+        assert_eq!(fst.read_wave(VarId(1)).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_geometry_count_mismatch() {
+        let fixture = Fixture::new()
+            .var("clk", 1, "0", &[(10, "1"), (20, "0")])
+            .var("data", 1, "x", &[(20, "1")]);
+
+        let with_geometry = |lengths: &[u64]| {
+            write_blocks(&[
+                fixture.header_block(),
+                fixture.value_change_block(),
+                geometry_block(lengths),
+                fixture.hierarchy_block(),
+            ])
+        };
+
+        // Extra trailing entries are ignored.
+        let file = with_geometry(&[1, 1, 8, 1]);
+        let fst = Fst::load(file.path()).unwrap();
+        assert_eq!(fst.var_data.len(), 2);
+        assert_eq!(fst.var_lengths.length(VarId(1)), VarLength::Bits(1));
+        let wave = fst.read_wave(VarId(1)).unwrap();
+        assert_eq!(wave.iter().map(|(t, _)| *t).collect::<Vec<_>>(), [0, 20]);
+
+        // Missing entries are an error.
+        let file = with_geometry(&[1]);
+        let err = Fst::load(file.path()).unwrap_err();
+        assert!(err.to_string().contains("Geometry block has 1 entries"));
+    }
+
+    #[test]
+    fn test_compressed_geometry() {
+        let fixture = Fixture::new()
+            .var("clk", 1, "0", &[(10, "1"), (20, "0")])
+            .var("data", 3, "x01", &[]);
+
+        // `data` is the uncompressed lengths and `compressed` what is
+        // actually stored.
+        let with_geometry = |data: &[u8], compressed: &[u8]| {
+            let mut body = Vec::new();
+            body.write_u64::<BigEndian>(data.len() as u64).unwrap();
+            body.write_u64::<BigEndian>(2).unwrap();
+            body.extend_from_slice(compressed);
+            write_blocks(&[
+                fixture.header_block(),
+                fixture.value_change_block(),
+                block(BlockType::FST_BL_GEOM, &body),
+                fixture.hierarchy_block(),
+            ])
+        };
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&[1, 3]).unwrap();
+        let file = with_geometry(&[1, 3], &encoder.finish().unwrap());
+        let fst = Fst::load(file.path()).unwrap();
+        assert_eq!(fst.var_lengths.length(VarId(1)), VarLength::Bits(3));
+
+        // Some other compression.
+        let file = with_geometry(&[1, 3], &lz4_flex::compress(&[1, 3]));
+        let err = Fst::load(file.path()).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("Geometry decompression failed"),
+            "{err:#}"
+        );
+    }
+
+    #[test]
+    fn test_lenient() {
+        let file = Fixture::new()
+            .var("clk", 1, "0", &[(10, "?"), (20, "1")])
+            .write();
+        let fst = Fst::load(file.path()).unwrap();
+
+        let err = fst.read_wave(VarId(0)).unwrap_err();
+        assert!(err.to_string().contains("Unsupported bit value '?'"));
+
+        let options = ReadOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let wave = fst.read_wave_with_options(VarId(0), &options).unwrap();
+        let codes: Vec<_> = wave
+            .iter()
+            .map(|(time, value)| (*time, value.bit(0)))
+            .collect();
+        assert_eq!(codes, [(0, 0), (10, 2), (20, 1)]);
+    }
+
+    #[test]
+    fn test_estimate_memory() {
+        let fixture = Fixture::new().var("clk", 1, "0", &[(10, "1"), (20, "0")]);
+        let base = Fst::estimate_memory(fixture.write().path()).unwrap();
+        assert!(base > 0);
+
+        let file = Fixture {
+            memory_required: 1234,
+            ..fixture
+        }
+        .write();
+        assert_eq!(Fst::estimate_memory(file.path()).unwrap(), base + 1234);
+
+        let file = write_blocks(&[geometry_block(&[1])]);
+        let err = Fst::estimate_memory(file.path()).unwrap_err();
+        assert!(err.to_string().contains("Not an FST file"));
+
+        // Corrupt sizes don't overflow.
+        let file = Fixture {
+            memory_required: u64::MAX,
+            ..Fixture::new()
+        }
+        .write();
+        assert_eq!(Fst::estimate_memory(file.path()).unwrap(), u64::MAX);
+        let mut huge = blackout_block(&[]);
+        huge[1..9].copy_from_slice(&u64::MAX.to_be_bytes());
+        let file = write_blocks(&[Fixture::new().header_block(), huge]);
+        let err = Fst::estimate_memory(file.path()).unwrap_err();
+        assert!(err.to_string().contains("invalid length"), "{err}");
+    }
+
+    #[test]
+    fn test_large_lengths() {
+        let fixture = Fixture::new().var("clk", 1, "0", &[(10, "1"), (20, "0")]);
+
+        // Overwrite one of the u64s at the end of the Value Change block: the
+        // time table uncompressed length, compressed length and count.
+        let with_trailer = |offset_from_end: usize, value: u64| {
+            let mut value_change_block = fixture.value_change_block();
+            let start = value_change_block.len() - offset_from_end;
+            value_change_block[start..start + 8].copy_from_slice(&value.to_be_bytes());
+            write_blocks(&[
+                fixture.header_block(),
+                value_change_block,
+                fixture.hierarchy_block(),
+                geometry_block(&[1]),
+            ])
+        };
+
+        let file = with_trailer(16, u64::MAX - 8);
+        let err = Fst::load(file.path()).unwrap_err();
+        assert!(format!("{err:#}").contains("Corrupt Value Change block layout in block 0"));
+
+        let file = with_trailer(8, u64::MAX);
+        let err = Fst::load(file.path()).unwrap_err();
+        assert!(format!("{err:#}").contains("Time table has"));
+
+        assert_eq!(to_usize(5, "Length").unwrap(), 5);
+        if usize::BITS < 64 {
+            let err = to_usize(u64::MAX, "Length").unwrap_err();
+            assert!(err.to_string().contains("too large for this platform"));
+        }
+    }
+
+    #[test]
+    fn test_value_change_block_layout() {
+        let block = Fixture::new()
+            .var("clk", 1, "0", &[(10, "1"), (20, "0")])
+            .var("data", 8, "00000000", &[(10, "10101010")])
+            .value_change_block();
+        // The body is after the block type and length.
+        let body_length = block.len() as u64 - 9;
+        let parse = |block: &[u8], block_length: u64| {
+            let mut var_data = TiVec::new();
+            var_data.resize_with(2, VarData::default);
+            let mut reader = Cursor::new(block);
+            reader.set_position(9);
+            Fst::read_value_change_block(
+                &mut reader,
+                BlockId::from(3),
+                block_length,
+                2,
+                &mut var_data,
+            )
+        };
+        assert!(parse(&block, body_length).is_ok());
+
+        let with_u64 = |offset: usize, value: u64| {
+            let mut block = block.clone();
+            block[offset..offset + 8].copy_from_slice(&value.to_be_bytes());
+            block
+        };
+        let time_compressed_length_at = block.len() - 16;
+        let time_compressed_length =
+            u64::from_be_bytes(block[time_compressed_length_at..][..8].try_into().unwrap());
+        let position_length_at = block.len() - 32 - time_compressed_length as usize;
+
+        // Lengths that put the tables before the waves or the start of the
+        // block, or the end of the block past the end of the address space.
+        for (block, block_length) in [
+            (block.clone(), u64::MAX),
+            (block.clone(), 30),
+            (
+                with_u64(time_compressed_length_at, body_length),
+                body_length,
+            ),
+            (
+                with_u64(time_compressed_length_at, u64::MAX - 8),
+                body_length,
+            ),
+            (with_u64(position_length_at, body_length), body_length),
+            (with_u64(position_length_at, u64::MAX), body_length),
+        ] {
+            let err = parse(&block, block_length).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "Corrupt Value Change block layout in block 3"
+            );
+        }
+
+        // Random lengths, and random bytes anywhere in the block, must only
+        // ever be errors. This is xorshift64 so that failures are repeatable.
+        let mut state = 0x9E37_79B9_7F4A_7C15u64;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for _ in 0..10_000 {
+            let mut block = block.clone();
+            let mut block_length = body_length;
+            for _ in 0..=random() % 3 {
+                // Lengths near the real ones get further into the parser.
+                let value = match random() % 3 {
+                    0 => random(),
+                    1 => random() % (2 * body_length),
+                    _ => (body_length + random() % 16).wrapping_sub(8),
+                };
+                let offset = match random() % 6 {
+                    0 => {
+                        block_length = value;
+                        continue;
+                    }
+                    // The time table lengths and count.
+                    1..=3 => block.len() - 8 * (1 + random() as usize % 3),
+                    4 => position_length_at,
+                    _ => {
+                        let index = random() as usize % block.len();
+                        block[index] = value as u8;
+                        continue;
+                    }
+                };
+                block[offset..offset + 8].copy_from_slice(&value.to_be_bytes());
+            }
+            let _ = parse(&block, block_length);
+        }
+    }
+
+    #[test]
+    fn test_block_order() {
+        let fixture = Fixture::new().var("clk", 1, "0", &[(10, "1"), (20, "0")]);
+
+        // Blackout, hierarchy and geometry in any order after the header.
+        let file = write_blocks(&[
+            fixture.header_block(),
+            blackout_block(&[(false, 30), (true, 40)]),
+            fixture.hierarchy_block(),
+            fixture.value_change_block(),
+            geometry_block(&[1]),
+        ]);
+        let fst = Fst::load(file.path()).unwrap();
+        assert_eq!(
+            fst.blackouts,
+            [(BlackoutType::DumpOff, 30), (BlackoutType::DumpOn, 40)]
+        );
+        assert_eq!(fst.read_wave(VarId(0)).unwrap().len(), 3);
+
+        let file = write_blocks(&[
+            fixture.header_block(),
+            geometry_block(&[1]),
+            fixture.hierarchy_block(),
+            geometry_block(&[1]),
+        ]);
+        let err = Fst::load(file.path()).unwrap_err();
+        assert!(err.to_string().contains("Duplicate FST_BL_GEOM block"));
+
+        let file = write_blocks(&[fixture.hierarchy_block(), fixture.header_block()]);
+        let err = Fst::load(file.path()).unwrap_err();
+        assert!(err.to_string().contains("Not an FST file"));
+    }
+
+    #[test]
+    fn test_raw_block_bytes() {
+        let fixture = Fixture::new()
+            .var("clk", 1, "0", &[(10, "1"), (20, "0")])
+            .var("data", 2, "x1", &[]);
+        let file = fixture.write();
+        let fst = Fst::load(file.path()).unwrap();
+        let raw = |region| fst.raw_block_bytes(BlockId(0), region).unwrap();
+
+        // The fixture doesn't compress these.
+        assert_eq!(raw(BlockRegion::Bits), b"0x1");
+        assert_eq!(raw(BlockRegion::Times), [10, 10]);
+        // One var with an offset of 1, then a run of one var with no changes.
+        assert_eq!(raw(BlockRegion::Positions), [0b11, 0b10]);
+        // Uncompressed, then the two changes of clk.
+        assert_eq!(raw(BlockRegion::Waves)[0], 0);
+
+        // The block is laid out in this order, with the lengths of the
+        // positions and times in between.
+        let info = &fst.value_change_blocks[BlockId(0)].info;
+        let ranges: Vec<_> = [
+            BlockRegion::Bits,
+            BlockRegion::Waves,
+            BlockRegion::Positions,
+            BlockRegion::Times,
+        ]
+        .map(|region| info.region_range(region))
+        .to_vec();
+        assert!(ranges.windows(2).all(|pair| pair[0].end <= pair[1].start));
+
+        assert!(fst.raw_block_bytes(BlockId(1), BlockRegion::Bits).is_err());
+    }
+
+    #[test]
+    fn test_block_for_time() {
+        let file = two_block_file(false);
+        let fst = Fst::load(file.path()).unwrap();
+
+        assert_eq!(fst.block_for_time(0), None);
+        assert_eq!(fst.block_for_time(10), Some(BlockId(1)));
+        assert_eq!(fst.block_for_time(49), Some(BlockId(1)));
+        assert_eq!(fst.block_for_time(50), Some(BlockId(0)));
+        assert_eq!(fst.block_for_time(1000), Some(BlockId(0)));
+
+        assert_eq!(fst.block_time_ranges(), [50..100, 10..49]);
+    }
+
+    #[test]
+    fn test_block_prefetcher() {
+        let file = two_block_file(false);
+        let fst = Fst::load(file.path()).unwrap();
+        let wave = fst.read_wave(VarId(0)).unwrap();
+        let early_bytes = fst.raw_block_bytes(BlockId(1), BlockRegion::Waves).unwrap();
+
+        // Only the earlier block fits.
+        fst.set_block_cache_size(early_bytes.len() as u64);
+        let mut prefetcher = fst.block_prefetcher().unwrap();
+        assert_eq!(prefetcher.remaining(), 2);
+        assert!(prefetcher.prefetch_next().unwrap());
+        assert!(!prefetcher.prefetch_next().unwrap());
+        assert_eq!(prefetcher.remaining(), 1);
+        {
+            let mut cache = fst.block_cache.lock().unwrap();
+            assert_eq!(cache.get(BlockId(1)).as_deref(), Some(&early_bytes));
+            assert!(cache.get(BlockId(0)).is_none());
+        }
+        assert_eq!(fst.read_wave(VarId(0)).unwrap(), wave);
+
+        fst.set_block_cache_size(1 << 20);
+        assert!(prefetcher.prefetch_next().unwrap());
+        assert!(!prefetcher.prefetch_next().unwrap());
+        assert_eq!(fst.read_wave(VarId(0)).unwrap(), wave);
+    }
+
+    #[test]
+    fn test_has_changes_in_block() {
+        let file = two_block_file(true);
+        let fst = Fst::load(file.path()).unwrap();
+
+        assert!(fst.has_changes_in_block(VarId(0), BlockId(0)));
+        assert!(!fst.has_changes_in_block(VarId(0), BlockId(1)));
+        assert!(!fst.has_changes_in_block(VarId(1), BlockId(0)));
+        assert!(fst.has_changes_in_block(VarId(1), BlockId(1)));
+
+        assert!(!fst.has_changes_in_block(VarId(0), BlockId(2)));
+        assert!(!fst.has_changes_in_block(VarId(2), BlockId(0)));
+
+        // Var n is n + 1 bits wide.
+        let final_bits = |varid: usize, block: usize| {
+            fst.block_final_value(VarId(varid), BlockId(block))
+                .unwrap()
+                .map(|value| value.bits_string(varid as u32 + 1))
+        };
+        assert_eq!(final_bits(0, 0).as_deref(), Some("1"));
+        assert_eq!(final_bits(0, 1), None);
+        assert_eq!(final_bits(1, 0), None);
+        assert_eq!(final_bits(1, 1).as_deref(), Some("11"));
+        assert!(fst.block_final_value(VarId(0), BlockId(2)).is_err());
+    }
+
+    #[test]
+    fn test_sample() {
+        let file = two_block_file(true);
+
+        let times = [60, 0, 20, 55, 100, 19];
+        let expected = [
+            ["1", "11"],
+            ["0", "00"],
+            ["1", "00"],
+            ["1", "00"],
+            ["1", "11"],
+            ["0", "00"],
+        ];
+        for (read_initial_values, mmap, read_blocks) in [
+            (true, false, true),
+            (false, false, true),
+            (true, true, true),
+            (true, false, false),
+        ] {
+            let options = LoadOptions {
+                read_initial_values,
+                mmap,
+                read_blocks,
+            };
+            let fst = Fst::load_with_options(file.path(), &options).unwrap();
+            let samples = fst.sample(&[VarId(0), VarId(1)], &times).unwrap();
+            let bits: Vec<[String; 2]> = samples
+                .iter()
+                .map(|sample| [sample[0].bits_string(1), sample[1].bits_string(2)])
+                .collect();
+            assert_eq!(bits, expected, "{options:?}");
+            assert_eq!(
+                fst.sample(&[VarId(1)], &[]).unwrap(),
+                Vec::<Vec<Value>>::new()
+            );
+            assert!(fst.sample(&[VarId(2)], &times).is_err());
+        }
+
+        // The same as reading the whole waves.
+        let fst = Fst::load(Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../samples/hdl-example.fst"
+        )))
+        .unwrap();
+        let varids: Vec<VarId> = (0..fst.var_data.len()).map(VarId).collect();
+        let times: Vec<u64> = (0..=40)
+            .map(|step| fst.header.end_time * step / 40)
+            .rev()
+            .collect();
+        let samples = fst.sample(&varids, &times).unwrap();
+        for (column, &varid) in varids.iter().enumerate() {
+            let wave = fst.read_wave(varid).unwrap();
+            for (sample, &time) in samples.iter().zip(&times) {
+                assert_eq!(
+                    Some(&sample[column]),
+                    value_at(&wave, time),
+                    "{varid:?} at {time}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_canonical_var() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(
+            b"$scope module top $end
+$var wire 1 ! a $end
+$var wire 1 \" b $end
+$scope module sub $end
+$var wire 1 ! a_alias $end
+$var wire 1 # c $end
+$upscope $end
+$upscope $end
+$enddefinitions $end
+",
+        )
+        .unwrap();
+        let fst = crate::vcd::load(file.path()).unwrap();
+
+        let alias = fst.var_by_path("top.sub.a_alias").unwrap();
+        assert_eq!(fst.canonical_var(alias), fst.var_by_path("top.a").unwrap());
+        assert_eq!(
+            fst.unique_vars().collect::<Vec<_>>(),
+            [VarId(0), VarId(1), VarId(2)]
+        );
+    }
+
+    #[test]
+    fn test_real_values() {
+        for real_endianness in [REAL_ENDIANNESS_LITTLE, REAL_ENDIANNESS_BIG] {
+            let file = Fixture {
+                real_endianness,
+                ..Fixture::new()
+            }
+            .var("clk", 1, "0", &[(10, "1")])
+            .real("level", 2.5, &[(10, -1.0), (20, 1e-9)])
+            .write();
+            let fst = Fst::load(file.path()).unwrap();
+            assert_eq!(
+                fst.header.real_is_little_endian(),
+                real_endianness == REAL_ENDIANNESS_LITTLE
+            );
+            assert_eq!(fst.var_lengths.length(VarId(1)), VarLength::Real);
+
+            let wave = fst.read_wave(VarId(1)).unwrap();
+            let values: Vec<_> = wave
+                .iter()
+                .map(|(time, value)| (*time, value.to_f64()))
+                .collect();
+            assert_eq!(values, [(0, Some(2.5)), (10, Some(-1.0)), (20, Some(1e-9))]);
+            // The bit var is still fine.
+            assert_eq!(fst.read_wave(VarId(0)).unwrap().len(), 2);
+        }
+
+        // Doubles whose bytes are all '0' or '1' are packed.
+        let mut reader: &[u8] = &[3 << 1, 0b0100_0001];
+        let (value, time_index_delta) =
+            value_and_time_index_delta_from_waves_table(&mut reader, VarLength::Real, false, true)
+                .unwrap();
+        assert_eq!(time_index_delta, 3);
+        assert_eq!(value, Value::from_real_bytes(*b"01000001", true));
+    }
+
+    #[test]
+    fn test_value_from_packed_bits() {
+        // 101, 10110 and 101101001 with the padding bits all set.
+        let cases: [(u32, &[u8], &str); 3] = [
+            (3, &[0b1011_1111], "101"),
+            (5, &[0b1011_0111], "10110"),
+            (9, &[0b1011_0100, 0b1111_1111], "101101001"),
+        ];
+        for (bits, packed, expected) in cases {
+            let value = value_from_packed_bits(&mut &packed[..], bits).unwrap();
+            assert_eq!(value, Value::from_bits_str(expected).unwrap());
+            assert_eq!(value.bits_string(bits), expected);
+        }
+
+        // Only the bytes for `bits` bits are read.
+        let mut reader: &[u8] = &[0xFF, 0x12];
+        value_from_packed_bits(&mut reader, 8).unwrap();
+        assert_eq!(reader, [0x12]);
+
+        // And through a file.
+        let file = Fixture::new()
+            .var("count", 5, "00000", &[(10, "10110"), (20, "00011")])
+            .var("wide", 9, "101101001", &[])
+            .write();
+        let fst = Fst::load(file.path()).unwrap();
+        let bits_strings = |varid, bits| -> Vec<(u64, String)> {
+            let wave = fst.read_wave(varid).unwrap();
+            wave.into_iter()
+                .map(|(t, v)| (t, v.bits_string(bits)))
+                .collect()
+        };
+        assert_eq!(
+            bits_strings(VarId(0), 5),
+            [
+                (0, "00000".into()),
+                (10, "10110".into()),
+                (20, "00011".into())
+            ]
+        );
+        assert_eq!(bits_strings(VarId(1), 9), [(0, "101101001".into())]);
+    }
+
+    #[test]
+    fn test_read_wave_by_path() {
+        let file = Fixture::new()
+            .var("clk", 1, "0", &[(10, "1")])
+            .var("data", 2, "xx", &[(15, "z1")])
+            .write();
+        let fst = Fst::load(file.path()).unwrap();
+
+        assert_eq!(
+            fst.read_wave_by_path("top.data").unwrap(),
+            fst.read_wave(VarId(1)).unwrap()
+        );
+        let err = fst.read_wave_by_path("top.nope").unwrap_err();
+        assert_eq!(err.to_string(), "No var called top.nope");
+    }
+
+    #[test]
+    fn test_enum_table() {
+        let file = Fixture::new()
+            .var("clk", 1, "0", &[(10, "1")])
+            .var("state", 2, "00", &[(10, "01"), (20, "11")])
+            .with_enum(
+                "state_t",
+                &[("IDLE", "00"), ("RUN", "01"), ("DONE WAIT", "11")],
+            )
+            .write();
+        let fst = Fst::load(file.path()).unwrap();
+
+        let scope = &fst.hierarchy.get(ScopeId(0)).unwrap().value;
+        assert!(scope.vars[0].enum_def.is_none());
+        let enum_def = scope.vars[1].enum_def.as_ref().unwrap();
+        assert_eq!(enum_def.name, "state_t");
+        let names: Vec<_> = enum_def.members.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["IDLE", "RUN", "DONE WAIT"]);
+
+        let wave = fst.read_wave(VarId(1)).unwrap();
+        let states: Vec<_> = wave
+            .iter()
+            .map(|(_, value)| enum_def.member_name(value, 2))
+            .collect();
+        assert_eq!(states, [Some("IDLE"), Some("RUN"), Some("DONE WAIT")]);
+        assert_eq!(
+            enum_def.member_name(&Value::from_bits_str("10").unwrap(), 2),
+            None
+        );
+
+        // The definition and the reference are both recorded.
+        assert_eq!(scope.attrs.len(), 2);
+        assert!(scope
+            .attrs
+            .iter()
+            .all(|attr| attr.type_ == FST_AT_MISC && attr.subtype == FST_MT_ENUMTABLE));
+
+        assert!(EnumDef::parse("e 2 A B 0").is_err());
+        assert!(EnumDef::parse("e 1 A 2").is_err());
+
+        // A table that won't parse (and so the reference to it) is skipped.
+        let file = Fixture::new()
+            .var("state", 2, "00", &[])
+            .with_enum("bad_t", &[("IDLE", "0q")])
+            .var("next", 2, "00", &[])
+            .with_enum("good_t", &[("IDLE", "00")])
+            .write();
+        let fst = Fst::load(file.path()).unwrap();
+        let scope = &fst.hierarchy.get(ScopeId(0)).unwrap().value;
+        assert!(scope.vars[0].enum_def.is_none());
+        assert_eq!(scope.vars[1].enum_def.as_ref().unwrap().name, "good_t");
+    }
+
+    #[test]
+    fn test_var_source() {
+        let fixture = Fixture::new()
+            .var("clk", 1, "0", &[(10, "1")])
+            .with_source("rtl/top.sv", 12)
+            .var("rst", 1, "1", &[(10, "0")])
+            .var("data", 8, "00000000", &[])
+            .with_source("rtl/top.sv", 300);
+        let file = fixture.write();
+        let fst = Fst::load(file.path()).unwrap();
+
+        assert_eq!(
+            fst.var_source(VarId(0)),
+            Some(("rtl/top.sv".to_string(), 12))
+        );
+        assert_eq!(fst.var_source(VarId(1)), None);
+        assert_eq!(
+            fst.var_source(VarId(2)),
+            Some(("rtl/top.sv".to_string(), 300))
+        );
+        assert_eq!(fst.var_source(VarId(3)), None);
+
+        // The path is declared once and both stems refer to it.
+        let scope = &fst.hierarchy.get(ScopeId(0)).unwrap().value;
+        let stems: Vec<_> = scope
+            .attrs
+            .iter()
+            .filter(|attr| attr.subtype == FST_MT_SOURCESTEM)
+            .map(|attr| (attr.arg_from_name, attr.arg))
+            .collect();
+        assert_eq!(stems, [(1, 12), (1, 300)]);
+        assert!(scope.source.is_none());
+
+        // A path index that isn't a valid varint only loses that stem.
+        let mut blocks = fixture.blocks();
+        // Block type, block length, uncompressed length, then the LZ4 data.
+        let uncompressed_length = u64::from_be_bytes(blocks[3][9..17].try_into().unwrap());
+        let mut data =
+            lz4_flex::decompress(&blocks[3][17..], uncompressed_length as usize).unwrap();
+        let stem = [FST_ST_GEN_ATTRBEGIN, FST_AT_MISC, FST_MT_SOURCESTEM, 1, 0];
+        let start = data.windows(stem.len()).position(|w| w == stem).unwrap();
+        data[start + 3] = 0x81;
+        let mut body = Vec::new();
+        body.write_u64::<BigEndian>(data.len() as u64).unwrap();
+        body.extend_from_slice(&data);
+        blocks[3] = block(BlockType::FST_BL_HIER, &body);
+        let fst = Fst::load(write_blocks(&blocks).path()).unwrap();
+        assert_eq!(fst.var_source(VarId(0)), None);
+        assert_eq!(
+            fst.var_source(VarId(2)),
+            Some(("rtl/top.sv".to_string(), 300))
+        );
+    }
+
+    #[test]
+    fn test_long_names() {
+        let long = "n".repeat(1000);
+        let file = Fixture::new()
+            .var(&long, 1, "0", &[(10, "1")])
+            .with_enum(&"e".repeat(2000), &[("A", "0"), ("B", "1")])
+            .var("after", 1, "1", &[])
+            .write();
+        let fst = Fst::load(file.path()).unwrap();
+        let scope = &fst.hierarchy.get(ScopeId(0)).unwrap().value;
+        assert_eq!(scope.vars[0].name, long);
+        assert_eq!(scope.vars[0].enum_def.as_ref().unwrap().name.len(), 2000);
+        assert_eq!(scope.vars[1].name, "after");
+
+        // Names that are too long are an error, not truncated.
+        let mut reader: &[u8] = b"abcd\0";
+        assert!(reader.read_null_terminated_string(3).is_err());
+        let mut reader: &[u8] = b"abcd\0e";
+        assert_eq!(reader.read_null_terminated_string(4).unwrap(), "abcd");
+        assert_eq!(reader, b"e");
+        // As are unterminated ones.
+        let mut reader: &[u8] = b"abcd";
+        assert!(reader.read_null_terminated_string(10).is_err());
+    }
+
+    #[test]
+    fn test_invalid_utf8_names() {
+        let mut reader: &[u8] = b"ok\0bad\xff\0";
+        let (name, raw) = reader.read_null_terminated_string_exact(10).unwrap();
+        assert_eq!((name.as_str(), raw), ("ok", None));
+        let (name, raw) = reader.read_null_terminated_string_exact(10).unwrap();
+        assert_eq!(name, "bad\u{fffd}");
+        assert_eq!(raw.as_deref(), Some(&b"bad\xff"[..]));
+
+        let var = HierarchyVar {
+            name,
+            raw_name: raw,
+            ..Default::default()
+        };
+        assert_eq!(var.name_bytes(), b"bad\xff");
+        let scope = HierarchyScope {
+            name: "ok".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(scope.name_bytes(), b"ok");
+    }
+
+    #[test]
+    fn test_read_wave_bit() {
+        let file = Fixture::new()
+            .var("clk", 1, "0", &[(10, "1")])
+            .var(
+                "bus",
+                5,
+                "0x001",
+                &[(10, "1x011"), (20, "1z010"), (30, "11010")],
+            )
+            .write();
+        let fst = Fst::load(file.path()).unwrap();
+
+        let bits = |bit| -> Vec<(u64, String)> {
+            let wave = fst.read_wave_bit(VarId(1), bit).unwrap();
+            wave.into_iter()
+                .map(|(t, v)| (t, v.bits_string(1)))
+                .collect()
+        };
+        assert_eq!(bits(0), [(0, "1".into()), (20, "0".into())]);
+        assert_eq!(bits(1), [(0, "0".into()), (10, "1".into())]);
+        assert_eq!(
+            bits(3),
+            [(0, "x".into()), (20, "z".into()), (30, "1".into())]
+        );
+        assert_eq!(bits(4), [(0, "0".into()), (10, "1".into())]);
+        // Each value only has the one bit.
+        let wave = fst.read_wave_bit(VarId(1), 4).unwrap();
+        assert!(wave.iter().all(|(_, v)| v.bytes().len() == 1));
+
+        assert!(fst.read_wave_bit(VarId(1), 5).is_err());
+        assert!(fst.read_wave_bit(VarId(2), 0).is_err());
+    }
+
+    #[test]
+    fn test_hierarchy_trailing_bytes() {
+        let fixture = Fixture::new().var("clk", 1, "0", &[(10, "1")]);
+        let mut blocks = fixture.blocks();
+        // Block type, block length, uncompressed length, then the LZ4 data.
+        let uncompressed_length = u64::from_be_bytes(blocks[3][9..17].try_into().unwrap());
+        let data = lz4_flex::decompress(&blocks[3][17..], uncompressed_length as usize).unwrap();
+
+        let mut garbage = data.clone();
+        garbage.extend_from_slice(&[FST_ST_VCD_SCOPE, 0, b'x', 0]);
+
+        let mut body = Vec::new();
+        body.write_u64::<BigEndian>(garbage.len() as u64).unwrap();
+        body.extend_from_slice(&lz4_flex::compress(&garbage));
+        blocks[3] = block(BlockType::FST_BL_HIER_LZ4, &body);
+        let err = Fst::load(write_blocks(&blocks).path()).unwrap_err();
+        assert!(format!("{err:#}").contains("4 bytes after the top-level upscope"));
+
+        // And uncompressed.
+        let mut body = Vec::new();
+        body.write_u64::<BigEndian>(garbage.len() as u64).unwrap();
+        body.extend_from_slice(&garbage);
+        blocks[3] = block(BlockType::FST_BL_HIER, &body);
+        let err = Fst::load(write_blocks(&blocks).path()).unwrap_err();
+        assert!(format!("{err:#}").contains("4 bytes after the top-level upscope"));
+
+        // Without the garbage it's fine.
+        let mut body = Vec::new();
+        body.write_u64::<BigEndian>(data.len() as u64).unwrap();
+        body.extend_from_slice(&data);
+        blocks[3] = block(BlockType::FST_BL_HIER, &body);
+        Fst::load(write_blocks(&blocks).path()).unwrap();
+    }
+
+    #[test]
+    fn test_write_block() {
+        let fixture = Fixture::new().var("clk", 1, "0", &[(10, "1")]);
+        let mut blocks = fixture.blocks();
+
+        // Write a blackout block's body in pieces, after another block.
+        let mut out = Cursor::new(Vec::new());
+        out.write_all(&blocks[3]).unwrap();
+        write_block(&mut out, BlockType::FST_BL_BLACKOUT, |w| {
+            w.write_u8(1)?;
+            w.write_u8(0)?;
+            w.write_u8(30)
+        })
+        .unwrap();
+        let out = out.into_inner();
+        let blackout = &out[blocks[3].len()..];
+        assert_eq!(blackout.len(), 1 + 8 + 3);
+        assert_eq!(
+            u64::from_be_bytes(blackout[1..9].try_into().unwrap()),
+            8 + 3
+        );
+
+        blocks.push(blackout.to_vec());
+        let fst = Fst::load(write_blocks(&blocks).path()).unwrap();
+        assert_eq!(fst.blackouts, [(BlackoutType::DumpOff, 30)]);
+
+        // The reader finds the end of the block from its length, so a length
+        // that is off by one fails the position check.
+        blocks[4][8] += 1;
+        let err = Fst::load(write_blocks(&blocks).path()).unwrap_err();
+        assert!(format!("{err:#}").contains("Expected to be at position"));
+    }
+
+    #[test]
+    fn test_gzipped_file() {
+        let fixture = Fixture::new()
+            .var("clk", 1, "0", &[(10, "1"), (20, "0")])
+            .var("bus", 3, "x01", &[(15, "1z0")]);
+        let file = fixture.write();
+        let plain = Fst::load(file.path()).unwrap();
+
+        let mut gzipped = NamedTempFile::new().unwrap();
+        let mut encoder = GzEncoder::new(&mut gzipped, Compression::default());
+        encoder
+            .write_all(&std::fs::read(file.path()).unwrap())
+            .unwrap();
+        encoder.finish().unwrap();
+
+        let fst = Fst::load(gzipped.path()).unwrap();
+        assert_ne!(fst.filename, gzipped.path());
+        for varid in [VarId(0), VarId(1)] {
+            assert_eq!(
+                fst.read_wave(varid).unwrap(),
+                plain.read_wave(varid).unwrap()
+            );
+        }
+
+        // The decompressed copy is deleted with the `Fst`.
+        let decompressed = fst.filename.clone();
+        assert!(decompressed.exists());
+        drop(fst);
+        assert!(!decompressed.exists());
+
+        // A truncated gzip file.
+        let bytes = std::fs::read(gzipped.path()).unwrap();
+        std::fs::write(gzipped.path(), &bytes[..bytes.len() / 2]).unwrap();
+        let err = Fst::load(gzipped.path()).unwrap_err();
+        assert_eq!(err.to_string(), "Couldn't decompress gzip file");
+    }
+
+    #[test]
+    fn test_change_count() {
+        let file = Fixture::new()
+            .var("clk", 1, "0", &[(10, "1"), (20, "0"), (30, "1")])
+            .var("tied", 1, "1", &[])
+            // The repeated value isn't a change.
+            .var("bus", 3, "x01", &[(10, "x01"), (20, "110")])
+            .write();
+        let fst = Fst::load(file.path()).unwrap();
+        assert_eq!(fst.change_count(VarId(0)).unwrap(), 3);
+        assert_eq!(fst.change_count(VarId(1)).unwrap(), 0);
+        assert_eq!(fst.change_count(VarId(2)).unwrap(), 1);
+        assert!(fst.change_count(VarId(3)).is_err());
+    }
+
+    #[test]
+    fn test_compressed_time_table() {
+        // A tiny table gets bigger when it's compressed.
+        let fixture = Fixture {
+            compress_times: true,
+            ..Fixture::new().var("clk", 1, "0", &[(10, "1"), (20, "0")])
+        };
+        let fst = Fst::load(fixture.write().path()).unwrap();
+        assert_eq!(fst.read_wave(VarId(0)).unwrap().len(), 3);
+        let info = &fst.value_change_blocks[BlockId(0)].info;
+        assert!(info.time_compressed_length > info.time_uncompressed_length);
+
+        // Find a table that compresses to exactly its uncompressed length,
+        // which looks uncompressed.
+        let mut found = false;
+        for n in 1..400u64 {
+            let changes: Vec<(u64, &str)> = (1..=n)
+                .map(|i| (i * 10 + i * i % 7, if i % 2 == 1 { "1" } else { "0" }))
+                .collect();
+            let fixture = Fixture {
+                compress_times: true,
+                end_time: n * 20,
+                ..Fixture::new().var("clk", 1, "0", &changes)
+            };
+            let fst = Fst::load(fixture.write().path()).unwrap();
+            let info = &fst.value_change_blocks[BlockId(0)].info;
+            if info.time_compressed_length != info.time_uncompressed_length {
+                continue;
+            }
+            let times: Vec<u64> = changes.iter().map(|(t, _)| *t).collect();
+            assert_eq!(fst.time_index().times(BlockId(0)), times);
+            found = true;
+            break;
+        }
+        assert!(found);
+    }
+
+    #[test]
+    fn test_decode_change_times() {
+        assert_eq!(
+            decode_change_times(&[1, 2, 0x80, 0x01], 3).unwrap(),
+            [1, 3, 131]
+        );
+        // Left over bytes.
+        assert!(decode_change_times(&[1, 2, 3], 2).is_err());
+        // Not enough.
+        assert!(decode_change_times(&[1], 2).is_err());
+    }
+
+    /// Position table entries must decode to what a writer encodes with
+    /// `encode_svarint()` and `encode_varint()`.
+    #[test]
+    fn test_position_table_varints() {
+        // Offset deltas, aliases to previous vars (-1 is var 0), "same as the
+        // previous alias" and the extremes that fit after the shift.
+        let values = [
+            0,
+            1,
+            2,
+            63,
+            64,
+            1000,
+            1 << 40,
+            -1,
+            -2,
+            -64,
+            -65,
+            -1000,
+            i64::MAX >> 1,
+            i64::MIN >> 1,
+        ];
+        for value in values {
+            let mut encoded = [0; 10];
+            let length = encode_svarint(&mut encoded, value << 1 | 1);
+            let mut reader = &encoded[..length];
+
+            let mut buffer = [0; 10];
+            let bytes = read_varint_bytes(&mut reader, &mut buffer).unwrap();
+            assert_eq!(bytes.len(), length);
+            assert!(reader.is_empty());
+            assert_eq!(bytes[0] & 1, 1, "{value}");
+            assert_eq!(decode_svarint(bytes).unwrap() >> 1, value);
+        }
+
+        // Runs of vars with no changes.
+        for run in [1, 2, 63, 64, 1 << 20, u64::MAX >> 1] {
+            let mut encoded = [0; 10];
+            let length = encode_varint(&mut encoded, run << 1);
+            let mut reader = &encoded[..length];
+
+            let mut buffer = [0; 10];
+            let bytes = read_varint_bytes(&mut reader, &mut buffer).unwrap();
+            assert_eq!(bytes.len(), length);
+            assert_eq!(bytes[0] & 1, 0, "{run}");
+            assert_eq!(decode_varint(bytes).unwrap() >> 1, run);
+        }
+
+        // Entries are read one at a time from a table.
+        let mut table = Vec::new();
+        for value in [5, -1, 0, 300] {
+            let mut encoded = [0; 10];
+            let length = encode_svarint(&mut encoded, value << 1 | 1);
+            table.extend_from_slice(&encoded[..length]);
+        }
+        let mut reader = table.as_slice();
+        let mut decoded = Vec::new();
+        while !reader.is_empty() {
+            let mut buffer = [0; 10];
+            let bytes = read_varint_bytes(&mut reader, &mut buffer).unwrap();
+            decoded.push(decode_svarint(bytes).unwrap() >> 1);
+        }
+        assert_eq!(decoded, [5, -1, 0, 300]);
+
+        // Too long.
+        let mut reader: &[u8] = &[0x81; 11];
+        assert!(read_varint_bytes(&mut reader, &mut [0; 10]).is_err());
+    }
+
+    #[test]
+    fn test_x_value() {
+        assert_eq!(x_value(VarLength::Bits(1)).bytes(), [0b10]);
+        assert_eq!(x_value(VarLength::Bits(4)).bytes(), [0b1010_1010]);
+        assert_eq!(x_value(VarLength::Bits(6)).bytes(), [0b1010_1010, 0b1010]);
+        assert!(x_value(VarLength::Real).to_f64().unwrap().is_nan());
+    }
+
+    #[test]
+    fn test_split_path() {
+        assert_eq!(split_path("top"), ["top"]);
+        assert_eq!(split_path("top.cpu.alu"), ["top", "cpu", "alu"]);
+        assert_eq!(
+            split_path("top.\\foo.bar .alu"),
+            ["top", "\\foo.bar", "alu"]
+        );
+        assert_eq!(split_path("top.\\foo.bar "), ["top", "\\foo.bar"]);
+        assert!(name_matches("\\foo.bar ", "\\foo.bar"));
+        assert!(name_matches("result [31:0]", "result"));
+        assert!(!name_matches("result_q", "result"));
+    }
+
+    #[test]
+    fn test_paths() {
+        let file = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../samples/hdl-example.fst"
+        ));
+        let fst = Fst::load(file).unwrap();
+
+        assert_eq!(fst.scope_by_path("fejkon_fc_debug"), Some(ScopeId(0)));
+        assert_eq!(
+            fst.scope_by_path("fejkon_fc_debug.csr_rd"),
+            Some(ScopeId(2))
+        );
+        assert_eq!(fst.scope_by_path("fejkon_fc_debug.nope"), None);
+        assert_eq!(
+            fst.var_by_path("fejkon_fc_debug.csr_address"),
+            Some(VarId(1))
+        );
+        assert_eq!(
+            fst.var_by_path("fejkon_fc_debug.csr_address [7:0]"),
+            Some(VarId(1))
+        );
+        assert_eq!(fst.var_by_path("fejkon_fc_debug"), None);
+
+        assert_eq!(
+            fst.scope_path(ScopeId(2)).unwrap(),
+            "fejkon_fc_debug.csr_rd"
+        );
+        assert_eq!(fst.scope_path(ScopeId(1000)), None);
+        let var_path = fst.var_path(ScopeId(0), "csr_address [7:0]").unwrap();
+        assert_eq!(var_path, "fejkon_fc_debug.csr_address");
+        assert_eq!(fst.var_by_path(&var_path), Some(VarId(1)));
+        assert_eq!(fst.var_path_by_id(VarId(1)).unwrap(), var_path);
+        assert_eq!(fst.var_path_by_id(VarId(1000)), None);
+        // Every scope's path leads back to it.
+        for id in (0..fst.hierarchy.len()).map(ScopeId) {
+            let path = fst.scope_path(id).unwrap();
+            assert_eq!(fst.scope_by_path(&path), Some(id), "{path}");
+        }
+    }
+
+    #[test]
+    fn test_wave_mode() {
+        let zero = Value::Logic(tiny_vec!([u8; 16] => 0));
+        let one = Value::Logic(tiny_vec!([u8; 16] => 1));
+        // Short pulses of 1; 0 most of the time even though 1 occurs more often.
+        let wave = vec![
+            (0, zero.clone()),
+            (10, one.clone()),
+            (12, zero.clone()),
+            (50, one.clone()),
+            (52, zero.clone()),
+            (90, one.clone()),
+        ];
+
+        assert_eq!(wave_mode(&wave, 0..100), Some((zero.clone(), 0.86)));
+        // Only the part of each value inside the range counts.
+        assert_eq!(wave_mode(&wave, 88..98), Some((one.clone(), 0.8)));
+        // Before the first change nothing is held.
+        assert_eq!(wave_mode(&wave[1..].to_vec(), 0..10), None);
+        // Ties go to the earliest value.
+        assert_eq!(wave_mode(&wave, 8..12), Some((zero, 0.5)));
+    }
+
+    #[test]
+    fn test_wave_stats() {
+        let zero = Value::Logic(tiny_vec!([u8; 16] => 0));
+        let one = Value::Logic(tiny_vec!([u8; 16] => 1));
+        let clock: ValAndTimeVec = (0..20)
+            .map(|i| {
+                (
+                    i * 5,
+                    if i % 2 == 0 {
+                        zero.clone()
+                    } else {
+                        one.clone()
+                    },
+                )
+            })
+            .collect();
+
+        let stats = wave_stats(&clock, VarLength::Bits(1), false, 0..100);
+        assert_eq!(stats.transitions, 19);
+        assert_eq!(stats.min, Some(zero.clone()));
+        assert_eq!(stats.max, Some(one.clone()));
+        assert_eq!(stats.high_fraction, Some(0.5));
+        assert_eq!(stats.period, Some(10));
+
+        // Only changes inside the range count.
+        let stats = wave_stats(&clock, VarLength::Bits(1), false, 12..18);
+        assert_eq!(stats.transitions, 1);
+        assert_eq!(stats.high_fraction, Some(0.5));
+        assert_eq!(stats.period, None);
+
+        let bus: ValAndTimeVec = [(0, "101"), (10, "1x0"), (20, "011")]
+            .iter()
+            .map(|(time, bits)| (*time, Value::from_bits_str(bits).unwrap()))
+            .collect();
+        let stats = wave_stats(&bus, VarLength::Bits(3), false, 0..30);
+        assert_eq!(stats.transitions, 2);
+        assert_eq!(stats.min, Some(bus[2].1.clone()));
+        assert_eq!(stats.max, Some(bus[0].1.clone()));
+        assert_eq!(stats.high_fraction, None);
+
+        // Signed, 101 is -3.
+        let stats = wave_stats(&bus, VarLength::Bits(3), true, 0..30);
+        assert_eq!(stats.min, Some(bus[0].1.clone()));
+        assert_eq!(stats.max, Some(bus[2].1.clone()));
+
+        assert_eq!(
+            wave_stats(&bus, VarLength::Bits(3), false, 5..5),
+            WaveStats::default()
+        );
+    }
 }
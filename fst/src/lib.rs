@@ -4,55 +4,108 @@
 //!
 //! I reverse engineered a specification from the GtkWave source code here:
 //! https://blog.timhutt.co.uk/fst_spec/
+//!
+//! # Features
+//!
+//! * `std` (default) - Everything apart from the `varint` module needs this.
+//!   Without it the crate is `no_std`.
+//! * `arrow` - `Fst::to_arrow()` for exporting waves to Apache Arrow.
+//! * `bench` - The `codecs` module, for benchmarking the wave codecs.
+//! * `tracing` - `tracing` spans around each phase of `Fst::load()` (the
+//!   header, geometry, hierarchy, each Value Change block and reading the
+//!   initial values), e.g. to get a flamegraph of load time. Without it the
+//!   start of each phase is logged at debug level instead.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
+/// Enter a span for a phase of loading a file until the end of the enclosing
+/// block. Fields are `name = value` pairs, e.g. the index of a block.
+#[cfg(feature = "std")]
+macro_rules! load_span {
+    ($name:literal $(, $field:ident = $value:expr)*) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!($name $(, $field = $value)*).entered();
+        #[cfg(not(feature = "tracing"))]
+        log::debug!(
+            concat!("Loading ", $name $(, " ", stringify!($field), "={:?}")*)
+            $(, $value)*
+        );
+    };
+}
+
+#[cfg(feature = "arrow")]
+mod arrow;
+#[cfg(feature = "std")]
+pub mod block_cache;
+#[cfg(feature = "bench")]
+pub mod codecs;
+#[cfg(feature = "std")]
+mod content_hash;
+#[cfg(all(test, feature = "std"))]
+mod fixtures;
+#[cfg(feature = "std")]
 pub mod fst;
+#[cfg(feature = "std")]
+pub mod time_index;
+#[cfg(feature = "std")]
 pub mod valvec;
 pub mod varint;
+#[cfg(feature = "std")]
+pub mod vcd;
 
-// use anyhow::Result;
-// use std::collections::HashSet;
-
-// #[derive(From, Into)]
-// struct VarId(usize);
+#[cfg(feature = "std")]
+use anyhow::Result;
+#[cfg(feature = "std")]
+use std::collections::HashSet;
 
-// trait Waves {
-//     /// Get the design hiearchy.
-//     fn hierarchy(&self) -> &Hierarchy;
+#[cfg(feature = "std")]
+use crate::{
+    fst::{HierarchyScope, ScopeId, VarId, VarLength},
+    valvec::ValAndTimeVec,
+};
 
-//     /// Ensure the given set of waves are loaded. Does not reload them if they
-//     /// are already loaded.
-//     fn load_waves(&mut self, varids: HashSet<usize>) -> Result<()>;
+/// The design hierarchy. Scopes are stored in depth-first order.
+#[cfg(feature = "std")]
+pub type Hierarchy = espalier::Tree<ScopeId, HierarchyScope>;
 
-//     /// Get the values of a wave. Must already be loaded.
-//     fn wave(&self, varid: VarId) -> Result<&Wave<u8>>; // TODO: How to handle different types?
+/// Wave data from some file format.
+#[cfg(feature = "std")]
+pub trait Waves {
+    /// Get the design hiearchy.
+    fn hierarchy(&self) -> &Hierarchy;
 
-//     /// Get the points in time at which the wave values change.
-//     fn times(&self) -> &[u64];
+    /// Ensure the given set of waves are loaded. Does not reload them if they
+    /// are already loaded.
+    fn load_waves(&mut self, varids: &HashSet<VarId>) -> Result<()>;
 
-//     /// Get the timebase order of magnitude.
-//     fn timebase_order(&self) -> i8;
+    /// Get the values of a wave. Must already be loaded.
+    fn wave(&self, varid: VarId) -> Result<&ValAndTimeVec>;
 
-//     /// Get info about a variable.
-//     fn variable_info(&self, varid: VarId) -> Result<&VariableInfo>;
-// }
+    /// Get the points in time at which the wave values change.
+    fn times(&self) -> Vec<u64>;
 
-// struct Hierarchy {
-//     design: Scope,
-// }
+    /// Get the timebase order of magnitude, e.g. -9 for nanoseconds.
+    fn timebase_order(&self) -> i8;
 
-// struct Scope {
-//     name: String,
-//     children: Vec<Scope>,
-//     variables: Vec<VarId>,
-// }
+    /// Get info about a variable.
+    fn variable_info(&self, varid: VarId) -> Result<VariableInfo>;
+}
 
-// struct VariableInfo {
-//     name: String,
-//     direction: (),
-//     bits: u64,
-//     type_: String,
-// }
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VariableInfo {
+    /// The name of the first (non-alias) declaration of the variable.
+    pub name: String,
+    pub direction: u8,
+    pub length: VarLength,
+    pub type_: u8,
+}
 
-// struct Wave<T> {
-//     changes: Vec<(u64, T)>,
-// }
+#[cfg(feature = "std")]
+impl VariableInfo {
+    /// Whether it is declared as a signed integer. See
+    /// `HierarchyVar::is_signed()`.
+    pub fn is_signed(&self) -> bool {
+        fst::SIGNED_VAR_TYPES.contains(&self.type_)
+    }
+}
@@ -0,0 +1,104 @@
+//! Mapping between times and positions in the Value Change blocks.
+
+use typed_index_collections::TiVec;
+
+use crate::fst::BlockId;
+
+/// The change times of every Value Change block, indexed both ways: from a
+/// time to the block and the index of the change time in it, and back. Wave
+/// data refers to times by their index in the block, so this is what
+/// anything navigating the waves in time (e.g. a renderer) needs.
+#[derive(Debug, Default, Clone)]
+pub struct TimeIndex {
+    /// The times at which anything changes in each block, in order.
+    times: TiVec<BlockId, Vec<u64>>,
+    /// The start time of each block, sorted by time. Writers normally write
+    /// the blocks in time order but nothing guarantees it.
+    starts: Vec<(u64, BlockId)>,
+}
+
+impl TimeIndex {
+    /// Add the next block, given its start time and change times.
+    pub(crate) fn push(&mut self, start_time: u64, times: Vec<u64>) {
+        let block = self.times.push_and_get_key(times);
+        let index = self
+            .starts
+            .partition_point(|(start, _)| *start <= start_time);
+        self.starts.insert(index, (start_time, block));
+    }
+
+    /// The number of blocks.
+    pub fn len(&self) -> usize {
+        self.times.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.times.is_empty()
+    }
+
+    /// The change times of a block, in order. Empty for an invalid block.
+    pub fn times(&self, block: BlockId) -> &[u64] {
+        self.times.get(block).map_or(&[], Vec::as_slice)
+    }
+
+    /// The change times of all the blocks, in block order.
+    pub fn all_times(&self) -> impl Iterator<Item = u64> + '_ {
+        self.times.iter().flatten().copied()
+    }
+
+    /// The block that contains `time`, i.e. the one with the latest start
+    /// time that is not after it. None if `time` is before the first block
+    /// or there are no blocks.
+    pub fn block_for_time(&self, time: u64) -> Option<BlockId> {
+        let index = self.starts.partition_point(|(start, _)| *start <= time);
+        index.checked_sub(1).map(|index| self.starts[index].1)
+    }
+
+    /// The index in `block` of the last change time that is not after
+    /// `time`. None if there isn't one.
+    pub fn index_in_block(&self, block: BlockId, time: u64) -> Option<usize> {
+        self.times(block)
+            .partition_point(|&t| t <= time)
+            .checked_sub(1)
+    }
+
+    /// The time of the change at `index` in `block`, e.g. from the time index
+    /// deltas in the wave data.
+    pub fn time_at(&self, block: BlockId, index: usize) -> Option<u64> {
+        self.times(block).get(index).copied()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_time_index() {
+        let mut index = TimeIndex::default();
+        assert_eq!(index.block_for_time(0), None);
+
+        // Out of time order.
+        index.push(50, vec![50, 60, 70]);
+        index.push(10, vec![10, 20]);
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.all_times().collect::<Vec<_>>(), [50, 60, 70, 10, 20]);
+
+        assert_eq!(index.block_for_time(5), None);
+        assert_eq!(index.block_for_time(10), Some(BlockId::from(1)));
+        assert_eq!(index.block_for_time(49), Some(BlockId::from(1)));
+        assert_eq!(index.block_for_time(50), Some(BlockId::from(0)));
+        assert_eq!(index.block_for_time(1000), Some(BlockId::from(0)));
+
+        let block = BlockId::from(0);
+        assert_eq!(index.index_in_block(block, 49), None);
+        assert_eq!(index.index_in_block(block, 50), Some(0));
+        assert_eq!(index.index_in_block(block, 65), Some(1));
+        assert_eq!(index.index_in_block(block, 1000), Some(2));
+        assert_eq!(index.index_in_block(BlockId::from(2), 50), None);
+
+        assert_eq!(index.time_at(block, 1), Some(60));
+        assert_eq!(index.time_at(block, 3), None);
+        assert_eq!(index.time_at(BlockId::from(2), 0), None);
+    }
+}
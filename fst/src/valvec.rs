@@ -1,4 +1,10 @@
 use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+use std::{fmt, ops::Range, str::FromStr};
+
+use anyhow::{bail, Error};
+
+use crate::fst::VarLength;
 
 // use crate::fst::VarLength;
 
@@ -55,8 +61,517 @@ pub type ValAndTimeVec = Vec<(u64, Value)>;
 
 // With 16 bytes this is the same size as Vec<> (24 bytes). Any more and it is
 // bigger. This allows storing 64 bits on the stack.
-#[derive(Eq, PartialEq, Clone, Debug, Default)]
-pub struct Value(pub tinyvec::TinyVec<[u8; 16]>);
+//
+// Bits are stored LSB first, 2 bits per bit, so bit `i` is at
+// `(bytes[i / 4] >> ((i % 4) * 2)) & 0b11`. The codes are 0, 1, 2 (X) and
+// 3 (Z).
+pub type LogicBytes = tinyvec::TinyVec<[u8; 16]>;
+
+/// A value of a var. Reals are converted from the byte order of the file when
+/// they are read, so they can't be mistaken for bits. Logic values don't know
+/// how many bits they have though; that comes from the var's `VarLength`.
+#[derive(Clone, Debug)]
+pub enum Value {
+    Logic(LogicBytes),
+    Real(f64),
+}
+
+impl Default for Value {
+    /// A logic value with no bits.
+    fn default() -> Self {
+        Value::Logic(LogicBytes::default())
+    }
+}
+
+// Reals are compared by their bit patterns, so NaN is equal to itself and
+// unchanged NaN values can be coalesced.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Logic(a), Value::Logic(b)) => a == b,
+            (Value::Real(a), Value::Real(b)) => a.to_bits() == b.to_bits(),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Value {}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Value::Logic(bytes) => {
+                state.write_u8(0);
+                bytes.hash(state);
+            }
+            Value::Real(x) => {
+                state.write_u8(1);
+                x.to_bits().hash(state);
+            }
+        }
+    }
+}
+
+/// Get the 2-bit code for a value character from a VCD or FST file. The nine
+/// IEEE 1164 values are mapped to four states: the weak H and L are treated
+/// as 1 and 0, and U, W and - (don't care) as X.
+pub(crate) fn bit_code(c: u8) -> Option<u8> {
+    Some(match c {
+        b'0' | b'l' | b'L' => 0,
+        b'1' | b'h' | b'H' => 1,
+        b'x' | b'X' | b'u' | b'U' | b'w' | b'W' | b'-' => 2,
+        b'z' | b'Z' => 3,
+        _ => return None,
+    })
+}
+
+impl Value {
+    /// Make a value from VCD-style characters, MSB first, e.g. `"1010xz"`.
+    /// See `bit_code()` for the characters that are accepted.
+    pub fn from_bits_str(s: &str) -> Result<Value, Error> {
+        let mut bytes = LogicBytes::default();
+        bytes.resize(s.len().div_ceil(4), 0);
+        for (i, c) in s.bytes().rev().enumerate() {
+            let Some(code) = bit_code(c) else {
+                bail!("Unsupported bit value {:?} in {s:?}", c as char);
+            };
+            bytes[i / 4] |= code << ((i % 4) * 2);
+        }
+        Ok(Value::Logic(bytes))
+    }
+
+    /// Make a `bits` bit value from an integer. Bits past the 64th are 0.
+    pub fn from_u64(n: u64, bits: u32) -> Value {
+        let mut bytes = LogicBytes::default();
+        bytes.resize((bits as usize).div_ceil(4), 0);
+        for i in 0..(bits as usize).min(64) {
+            bytes[i / 4] |= (((n >> i) & 1) as u8) << ((i % 4) * 2);
+        }
+        Value::Logic(bytes)
+    }
+
+    /// Make a real from the raw bytes of a double in a file, which are in the
+    /// byte order of the machine that wrote it. See
+    /// `Header::real_is_little_endian()`.
+    pub fn from_real_bytes(bytes: [u8; 8], little_endian: bool) -> Value {
+        Value::Real(if little_endian {
+            f64::from_le_bytes(bytes)
+        } else {
+            f64::from_be_bytes(bytes)
+        })
+    }
+
+    /// The encoded bits of a logic value. Empty for a real.
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            Value::Logic(bytes) => bytes,
+            Value::Real(_) => &[],
+        }
+    }
+
+    /// Get the 2-bit code of bit `i` (0 is the LSB). Bits past the end are 0,
+    /// as are all the bits of a real.
+    pub fn bit(&self, i: usize) -> u8 {
+        self.bytes()
+            .get(i / 4)
+            .map_or(0, |b| (b >> ((i % 4) * 2)) & 0b11)
+    }
+
+    /// Get bits `range` as a value of their own, e.g. `slice(4..8)` is the
+    /// second nibble. Bits past the end are 0.
+    pub fn slice(&self, range: Range<u32>) -> Value {
+        let mut bytes = LogicBytes::default();
+        bytes.resize(range.len().div_ceil(4), 0);
+        for (i, bit) in range.enumerate() {
+            bytes[i / 4] |= self.bit(bit as usize) << ((i % 4) * 2);
+        }
+        Value::Logic(bytes)
+    }
+
+    /// Compare the first `bits` bits of two values, ignoring any padding
+    /// after them in the last byte. Reals are compared whole.
+    pub fn eq_bits(&self, other: &Value, bits: u32) -> bool {
+        match (self, other) {
+            (Value::Logic(_), Value::Logic(_)) => {
+                (0..bits as usize).all(|i| self.bit(i) == other.bit(i))
+            }
+            _ => self == other,
+        }
+    }
+
+    /// Format the bits as VCD-style characters, MSB first, e.g. `"01xz"`.
+    pub fn bits_string(&self, bits: u32) -> String {
+        (0..bits as usize)
+            .rev()
+            .map(|i| b"01xz"[self.bit(i) as usize] as char)
+            .collect()
+    }
+
+    /// The number of bits that differ between two values of the same var.
+    /// Bits that are X or Z in either value count as different, since they
+    /// may have changed.
+    pub fn hamming(&self, other: &Value) -> u32 {
+        let bits = self.bytes().len().max(other.bytes().len()) * 4;
+        (0..bits)
+            .filter(|&i| {
+                let (a, b) = (self.bit(i), other.bit(i));
+                a != b || a >= 2 || b >= 2
+            })
+            .count() as u32
+    }
+
+    /// Compare this `bits_a` bit value with a `bits_b` bit value as if the
+    /// narrower one were zero-extended to the width of the wider one. The
+    /// extra bits of the wider value match if they are 0, X or Z, so only a
+    /// 1 there is a difference. This is for comparing the same signal
+    /// declared with different widths in different files.
+    pub fn eq_zero_extended(&self, other: &Value, bits_a: u32, bits_b: u32) -> bool {
+        let common = bits_a.min(bits_b) as usize;
+        if (0..common).any(|i| self.bit(i) != other.bit(i)) {
+            return false;
+        }
+        let (wider, wider_bits) = if bits_a > bits_b {
+            (self, bits_a)
+        } else {
+            (other, bits_b)
+        };
+        (common..wider_bits as usize).all(|i| wider.bit(i) != 1)
+    }
+
+    /// Format the first `bits` bits as a number in the given radix. Hex and
+    /// octal digits that contain any X or Z bits are shown as `x` or `z`
+    /// (`x` if they have both), and decimal values are shown as just `x` or
+    /// `z`. Binary is the same as `bits_string()`.
+    pub fn format_radix(&self, bits: u32, radix: Radix) -> String {
+        let bits = bits as usize;
+        let digit_bits = match radix {
+            Radix::Bin => return self.bits_string(bits as u32),
+            Radix::Oct => 3,
+            Radix::Hex => 4,
+            Radix::Dec | Radix::SDec => {
+                if (0..bits).any(|i| self.bit(i) == 2) {
+                    return "x".to_string();
+                }
+                if (0..bits).any(|i| self.bit(i) == 3) {
+                    return "z".to_string();
+                }
+                return self.format_decimal(bits, radix == Radix::SDec);
+            }
+        };
+
+        (0..bits.div_ceil(digit_bits))
+            .rev()
+            .map(|digit| {
+                let codes =
+                    (digit * digit_bits..((digit + 1) * digit_bits).min(bits)).map(|i| self.bit(i));
+                let mut n = 0;
+                let (mut x, mut z) = (false, false);
+                for (shift, code) in codes.enumerate() {
+                    match code {
+                        0 | 1 => n |= (code as u32) << shift,
+                        2 => x = true,
+                        _ => z = true,
+                    }
+                }
+                if x {
+                    'x'
+                } else if z {
+                    'z'
+                } else {
+                    char::from_digit(n, 16).unwrap()
+                }
+            })
+            .collect()
+    }
+
+    /// Format the first `bits` bits, which must all be 0 or 1, in decimal.
+    fn format_decimal(&self, bits: usize, signed: bool) -> String {
+        // Little endian 32-bit limbs so wide buses work too.
+        let mut limbs = vec![0u32; bits.div_ceil(32)];
+        for i in (0..bits).filter(|&i| self.bit(i) == 1) {
+            limbs[i / 32] |= 1 << (i % 32);
+        }
+
+        let negative = signed && bits > 0 && self.bit(bits - 1) == 1;
+        if negative {
+            // Two's complement negate, ignoring bits past the end.
+            let mut carry = true;
+            for limb in limbs.iter_mut() {
+                (*limb, carry) = (!*limb).overflowing_add(carry as u32);
+            }
+            if limbs.len() * 32 > bits {
+                *limbs.last_mut().unwrap() &= (1 << (bits % 32)) - 1;
+            }
+        }
+
+        // Repeatedly divide by 10^9 to get 9 digits at a time.
+        let mut chunks = Vec::new();
+        while limbs.iter().any(|&limb| limb != 0) {
+            let mut remainder = 0u64;
+            for limb in limbs.iter_mut().rev() {
+                let n = (remainder << 32) | *limb as u64;
+                *limb = (n / 1_000_000_000) as u32;
+                remainder = n % 1_000_000_000;
+            }
+            chunks.push(remainder);
+        }
+
+        let mut out = if negative { "-" } else { "" }.to_string();
+        match chunks.split_last() {
+            None => out.push('0'),
+            Some((first, rest)) => {
+                write!(out, "{first}").unwrap();
+                for chunk in rest.iter().rev() {
+                    write!(out, "{chunk:09}").unwrap();
+                }
+            }
+        }
+        out
+    }
+
+    /// Get the first `bits` bits as an integer. None if any of them are X or
+    /// Z, or there are more than 64.
+    pub fn to_u64(&self, bits: u32) -> Option<u64> {
+        if bits > 64 {
+            return None;
+        }
+        (0..bits as usize).try_fold(0, |n, i| match self.bit(i) {
+            code @ (0 | 1) => Some(n | (code as u64) << i),
+            _ => None,
+        })
+    }
+
+    /// Get the first `bits` bits as a two's complement signed integer, so the
+    /// top one is the sign. None if any of them are X or Z, or there are more
+    /// than 128.
+    pub fn to_i128(&self, bits: u32) -> Option<i128> {
+        if bits > 128 {
+            return None;
+        }
+        if bits == 0 {
+            return Some(0);
+        }
+        let n = (0..bits as usize).try_fold(0u128, |n, i| match self.bit(i) {
+            code @ (0 | 1) => Some(n | (code as u128) << i),
+            _ => None,
+        })?;
+        // Sign extend by shifting the sign bit to the top and back.
+        let shift = 128 - bits;
+        Some(((n << shift) as i128) >> shift)
+    }
+
+    /// Get a real value. None if it is a logic value.
+    pub fn to_f64(&self) -> Option<f64> {
+        match self {
+            Value::Real(x) => Some(*x),
+            Value::Logic(_) => None,
+        }
+    }
+
+    /// Format the value for export: logic values as with `bits_string()`,
+    /// and reals as with `format_real()`.
+    pub fn format(&self, var_length: VarLength) -> String {
+        match (self, var_length) {
+            (Value::Logic(_), VarLength::Bits(bits)) => self.bits_string(bits),
+            _ => self.format_real(),
+        }
+    }
+
+    /// Format a real value for display. Logic values are "invalid".
+    ///
+    /// NaN and infinities are rendered explicitly. Everything else uses the
+    /// shortest representation that round-trips, switching to scientific
+    /// notation for very large or very small magnitudes.
+    pub fn format_real(&self) -> String {
+        let Some(value) = self.to_f64() else {
+            return "invalid".to_string();
+        };
+
+        if value.is_nan() {
+            "NaN".to_string()
+        } else if value.is_infinite() {
+            if value > 0.0 { "+inf" } else { "-inf" }.to_string()
+        } else if value != 0.0 && !(1e-4..1e15).contains(&value.abs()) {
+            format!("{value:e}")
+        } else {
+            format!("{value}")
+        }
+    }
+}
+
+/// Analysis of a whole wave. This is a trait because `ValAndTimeVec` is just
+/// a `Vec`.
+pub trait DetectPeriod {
+    /// If the rising edges of bit 0 are evenly spaced (allowing for a little
+    /// jitter and the odd missing edge), as they are for a clock, get the
+    /// median time between them. This needs at least a few periods.
+    fn detect_period(&self) -> Option<u64>;
+}
+
+impl DetectPeriod for [(u64, Value)] {
+    fn detect_period(&self) -> Option<u64> {
+        const MIN_PERIODS: usize = 4;
+
+        let rising_edges: Vec<u64> = self
+            .windows(2)
+            .filter(|pair| pair[0].1.bit(0) == 0 && pair[1].1.bit(0) == 1)
+            .map(|pair| pair[1].0)
+            .collect();
+        let mut periods: Vec<u64> = rising_edges
+            .windows(2)
+            .map(|pair| pair[1] - pair[0])
+            .collect();
+        if periods.len() < MIN_PERIODS {
+            return None;
+        }
+
+        let middle = periods.len() / 2;
+        let median = *periods.select_nth_unstable(middle).1;
+        if median == 0 {
+            return None;
+        }
+
+        // At least 90% of the periods must be within 10% of the median.
+        let tolerance = median / 10;
+        let regular = periods
+            .iter()
+            .filter(|&&period| period.abs_diff(median) <= tolerance)
+            .count();
+        (regular * 10 >= periods.len() * 9).then_some(median)
+    }
+}
+
+/// Run-length encoding of a wave. A trait for the same reason as
+/// `DetectPeriod`.
+pub trait IntoTransitions {
+    /// Collapse each run of consecutive values whose first `bits` bits are
+    /// equal into its first entry, so that only the times at which the value
+    /// actually changes are left. E.g. for rendering or VCD export.
+    fn into_transitions(self, bits: u32) -> Self;
+}
+
+impl IntoTransitions for ValAndTimeVec {
+    fn into_transitions(mut self, bits: u32) -> Self {
+        // `dedup_by()` passes the later entry first.
+        self.dedup_by(|(_, value), (_, first)| value.eq_bits(first, bits));
+        self
+    }
+}
+
+/// A wave with all of its values in one buffer, instead of a `Value` each.
+/// Values wider than 64 bits don't fit in a `Value` without a heap
+/// allocation, so this uses much less memory (and fragments the heap much
+/// less) for waves of wide buses with lots of changes.
+///
+/// The values are encoded the same way as `Value::Logic`, so this is only for
+/// logic values.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PackedWave {
+    times: Vec<u64>,
+    /// The end of each value in `data`. Each value starts where the previous
+    /// one ends.
+    ends: Vec<usize>,
+    data: Vec<u8>,
+}
+
+impl PackedWave {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, time: u64, value: &Value) {
+        self.times.push(time);
+        self.data.extend_from_slice(value.bytes());
+        self.ends.push(self.data.len());
+    }
+
+    pub fn len(&self) -> usize {
+        self.times.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.times.is_empty()
+    }
+
+    /// The time of each change.
+    pub fn times(&self) -> &[u64] {
+        &self.times
+    }
+
+    /// Get the time and encoded value of a change.
+    pub fn get(&self, index: usize) -> Option<(u64, &[u8])> {
+        let end = *self.ends.get(index)?;
+        let start = index.checked_sub(1).map_or(0, |prev| self.ends[prev]);
+        Some((self.times[index], &self.data[start..end]))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &[u8])> + '_ {
+        (0..self.len()).filter_map(|index| self.get(index))
+    }
+
+    /// Convert to the normal representation.
+    pub fn unpack(&self) -> ValAndTimeVec {
+        self.iter()
+            .map(|(time, value)| (time, Value::Logic(value.into())))
+            .collect()
+    }
+}
+
+impl Extend<(u64, Value)> for PackedWave {
+    fn extend<T: IntoIterator<Item = (u64, Value)>>(&mut self, iter: T) {
+        for (time, value) in iter {
+            self.push(time, &value);
+        }
+    }
+}
+
+impl FromIterator<(u64, Value)> for PackedWave {
+    fn from_iter<T: IntoIterator<Item = (u64, Value)>>(iter: T) -> Self {
+        let mut wave = Self::new();
+        wave.extend(iter);
+        wave
+    }
+}
+
+/// How to show the value of a bit var.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Radix {
+    #[default]
+    Bin,
+    Oct,
+    Hex,
+    /// Unsigned decimal.
+    Dec,
+    /// Signed (two's complement) decimal.
+    SDec,
+}
+
+impl fmt::Display for Radix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Radix::Bin => "bin",
+            Radix::Oct => "oct",
+            Radix::Hex => "hex",
+            Radix::Dec => "dec",
+            Radix::SDec => "sdec",
+        })
+    }
+}
+
+impl FromStr for Radix {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "bin" => Radix::Bin,
+            "oct" => Radix::Oct,
+            "hex" => Radix::Hex,
+            "dec" => Radix::Dec,
+            "sdec" => Radix::SDec,
+            _ => bail!("Unknown radix {s:?}, expected bin, oct, hex, dec or sdec"),
+        })
+    }
+}
 
 // pub struct ValVec {
 //     /// Data that encodes the data.
@@ -91,3 +606,314 @@ pub struct Value(pub tinyvec::TinyVec<[u8; 16]>);
 //         todo!()
 //     }
 // }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn from_bits(bits: &str) -> Value {
+        Value::from_bits_str(bits).unwrap()
+    }
+
+    #[test]
+    fn test_slice() {
+        let value = from_bits("10xz0110");
+        assert_eq!(value.slice(0..1), from_bits("0"));
+        assert_eq!(value.slice(1..2), from_bits("1"));
+        assert_eq!(value.slice(4..8), from_bits("10xz"));
+        assert_eq!(value.slice(2..7), from_bits("0xz01"));
+        // Past the end.
+        assert_eq!(value.slice(6..10), from_bits("0010"));
+        assert_eq!(value.slice(3..3), Value::default());
+    }
+
+    #[test]
+    fn test_packed_wave() {
+        let wave: ValAndTimeVec = vec![
+            (0, from_bits("0")),
+            (10, Value::from_u64(u64::MAX, 128)),
+            (20, from_bits("x1z")),
+        ];
+        let packed: PackedWave = wave.iter().cloned().collect();
+        assert_eq!(packed.len(), 3);
+        assert_eq!(packed.times(), [0, 10, 20]);
+        assert_eq!(packed.get(0), Some((0, [0].as_slice())));
+        assert_eq!(packed.get(2), Some((20, [0b10_01_11].as_slice())));
+        assert_eq!(packed.get(3), None);
+        assert_eq!(packed.unpack(), wave);
+        assert!(PackedWave::new().is_empty());
+    }
+
+    #[test]
+    fn test_bit_code() {
+        let codes: Vec<_> = b"01xzXZuUwWlLhH-?".iter().map(|&c| bit_code(c)).collect();
+        assert_eq!(
+            codes,
+            [
+                Some(0),
+                Some(1),
+                Some(2),
+                Some(3),
+                Some(2),
+                Some(3),
+                Some(2),
+                Some(2),
+                Some(2),
+                Some(2),
+                Some(0),
+                Some(0),
+                Some(1),
+                Some(1),
+                Some(2),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn test_eq_bits() {
+        let a = Value::Logic([0b00_01_00_01].as_slice().into());
+        let b = Value::Logic([0b11_01_00_01].as_slice().into());
+        assert!(a.eq_bits(&b, 3));
+        assert!(!a.eq_bits(&b, 4));
+        // Missing bytes are 0.
+        assert!(Value::default().eq_bits(&Value::Logic([0].as_slice().into()), 4));
+
+        // Reals have no bits to compare, and are never equal to bits.
+        assert!(!Value::Real(1.0).eq_bits(&Value::Real(2.0), 4));
+        assert!(Value::Real(f64::NAN).eq_bits(&Value::Real(f64::NAN), 4));
+        assert!(!Value::Real(0.0).eq_bits(&Value::default(), 4));
+    }
+
+    #[test]
+    fn test_hamming() {
+        // 0b0101 and 0b0110.
+        let a = Value::Logic([0b00_01_00_01].as_slice().into());
+        let b = Value::Logic([0b00_01_01_00].as_slice().into());
+        assert_eq!(a.hamming(&b), 2);
+        assert_eq!(a.hamming(&a), 0);
+
+        // 0bx101 and 0bz101.
+        let x = Value::Logic([0b10_01_00_01].as_slice().into());
+        let z = Value::Logic([0b11_01_00_01].as_slice().into());
+        assert_eq!(a.hamming(&x), 1);
+        assert_eq!(x.hamming(&x), 1);
+        assert_eq!(x.hamming(&z), 1);
+    }
+
+    #[test]
+    fn test_eq_zero_extended() {
+        // 0b0101 and 0b101.
+        let a = Value::Logic([0b00_01_00_01].as_slice().into());
+        let b = Value::Logic([0b01_00_01].as_slice().into());
+        assert!(a.eq_zero_extended(&b, 4, 3));
+        assert!(b.eq_zero_extended(&a, 3, 4));
+
+        // 0b1101 has a 1 in the extension.
+        let c = Value::Logic([0b01_01_00_01].as_slice().into());
+        assert!(!c.eq_zero_extended(&b, 4, 3));
+
+        // X and Z in the extension don't matter, but they do elsewhere.
+        let d = Value::Logic([0b10_01_00_01, 0b11].as_slice().into());
+        assert!(d.eq_zero_extended(&b, 5, 3));
+        assert!(!d.eq_zero_extended(&a, 5, 4));
+
+        assert!(a.eq_zero_extended(&a, 4, 4));
+    }
+
+    #[test]
+    fn test_bits_string() {
+        let value = Value::Logic(tinyvec::tiny_vec!([u8; 16] => 0b11_10_01_00, 0b01));
+        assert_eq!(value.bits_string(5), "1zx10");
+        assert_eq!(value.bits_string(2), "10");
+        // Missing bytes are zero.
+        assert_eq!(value.bits_string(10), "000001zx10");
+    }
+
+    #[test]
+    fn test_format_radix() {
+        let value = from_bits("110101");
+        assert_eq!(value.format_radix(6, Radix::Bin), "110101");
+        assert_eq!(value.format_radix(6, Radix::Oct), "65");
+        assert_eq!(value.format_radix(6, Radix::Hex), "35");
+        assert_eq!(value.format_radix(6, Radix::Dec), "53");
+        assert_eq!(value.format_radix(6, Radix::SDec), "-11");
+        assert_eq!(from_bits("000").format_radix(3, Radix::SDec), "0");
+        assert_eq!(from_bits("100").format_radix(3, Radix::SDec), "-4");
+
+        // Digits with X or Z.
+        let value = from_bits("1x0z00001111");
+        assert_eq!(value.format_radix(12, Radix::Hex), "x0f");
+        assert_eq!(from_bits("z000").format_radix(4, Radix::Hex), "z");
+        assert_eq!(value.format_radix(12, Radix::Dec), "x");
+        assert_eq!(from_bits("z1").format_radix(2, Radix::Dec), "z");
+
+        // Wider than 64 bits.
+        let value = from_bits(&format!("1{}", "0".repeat(100)));
+        assert_eq!(
+            value.format_radix(101, Radix::Dec),
+            "1267650600228229401496703205376"
+        );
+        assert_eq!(
+            value.format_radix(101, Radix::SDec),
+            "-1267650600228229401496703205376"
+        );
+        assert_eq!(
+            from_bits(&"1".repeat(100)).format_radix(100, Radix::SDec),
+            "-1"
+        );
+    }
+
+    #[test]
+    fn test_detect_period() {
+        let clock = |times: &[u64]| -> ValAndTimeVec {
+            times
+                .iter()
+                .enumerate()
+                .map(|(i, &time)| (time, from_bits(if i % 2 == 0 { "0" } else { "1" })))
+                .collect()
+        };
+
+        let times: Vec<u64> = (0..20).map(|i| i * 5).collect();
+        assert_eq!(clock(&times).detect_period(), Some(10));
+
+        // A little jitter is OK.
+        let mut jittery = times.clone();
+        jittery[7] += 1;
+        assert_eq!(clock(&jittery).detect_period(), Some(10));
+
+        // Too few edges.
+        assert_eq!(clock(&times[..8]).detect_period(), None);
+
+        // Irregular edges.
+        let irregular: Vec<u64> = (0..20).map(|i| i * i).collect();
+        assert_eq!(clock(&irregular).detect_period(), None);
+    }
+
+    #[test]
+    fn test_into_transitions() {
+        let wave: ValAndTimeVec = vec![
+            (0, from_bits("01")),
+            (5, from_bits("01")),
+            (10, from_bits("11")),
+            (15, from_bits("x1")),
+            (20, from_bits("x1")),
+            (25, from_bits("x1")),
+            (30, from_bits("01")),
+        ];
+        let transitions = wave.clone().into_transitions(2);
+        assert_eq!(
+            transitions,
+            [
+                (0, from_bits("01")),
+                (10, from_bits("11")),
+                (15, from_bits("x1")),
+                (30, from_bits("01")),
+            ]
+        );
+
+        // Only the first `bits` bits are compared.
+        let transitions = wave.into_transitions(1);
+        assert_eq!(transitions, [(0, from_bits("01"))]);
+
+        assert!(ValAndTimeVec::new().into_transitions(4).is_empty());
+    }
+
+    #[test]
+    fn test_radix_round_trip() {
+        for radix in [Radix::Bin, Radix::Oct, Radix::Hex, Radix::Dec, Radix::SDec] {
+            assert_eq!(radix.to_string().parse::<Radix>().unwrap(), radix);
+        }
+        assert_eq!(Radix::Hex.to_string(), "hex");
+        assert_eq!(Radix::SDec.to_string(), "sdec");
+        assert!("hexadecimal".parse::<Radix>().is_err());
+    }
+
+    #[test]
+    fn test_from_bits_str() {
+        let value = Value::from_bits_str("1zx10").unwrap();
+        assert_eq!(value.bytes(), [0b11_10_01_00, 0b01]);
+        assert_eq!(value.bits_string(5), "1zx10");
+        assert_eq!(Value::from_bits_str("H-").unwrap().bits_string(2), "1x");
+        assert_eq!(Value::from_bits_str("").unwrap(), Value::default());
+
+        let err = Value::from_bits_str("10?1").unwrap_err();
+        assert!(err.to_string().contains("'?'"));
+    }
+
+    #[test]
+    fn test_from_u64() {
+        assert_eq!(Value::from_u64(0b1011, 4), from_bits("1011"));
+        assert_eq!(Value::from_u64(0b1011, 6), from_bits("001011"));
+        // Bits past the width are dropped.
+        assert_eq!(Value::from_u64(0b1011, 2), from_bits("11"));
+        assert_eq!(
+            Value::from_u64(u64::MAX, 66),
+            from_bits(&format!("00{}", "1".repeat(64)))
+        );
+        for n in [0, 1, 12345, u64::MAX] {
+            assert_eq!(Value::from_u64(n, 64).to_u64(64), Some(n));
+        }
+    }
+
+    #[test]
+    fn test_to_u64() {
+        assert_eq!(from_bits("1011").to_u64(4), Some(11));
+        assert_eq!(from_bits("1011").to_u64(2), Some(3));
+        assert_eq!(from_bits("1x11").to_u64(4), None);
+        assert_eq!(from_bits("1z11").to_u64(2), Some(3));
+        assert_eq!(from_bits(&"1".repeat(64)).to_u64(64), Some(u64::MAX));
+        assert_eq!(from_bits(&"1".repeat(65)).to_u64(65), None);
+    }
+
+    #[test]
+    fn test_to_i128() {
+        assert_eq!(from_bits("0111").to_i128(4), Some(7));
+        assert_eq!(from_bits("1011").to_i128(4), Some(-5));
+        assert_eq!(from_bits("1111").to_i128(4), Some(-1));
+        assert_eq!(from_bits("1000").to_i128(4), Some(-8));
+        // The declared width decides where the sign is.
+        assert_eq!(from_bits("1011").to_i128(3), Some(3));
+        assert_eq!(from_bits("1011").to_i128(2), Some(-1));
+        assert_eq!(from_bits("1").to_i128(1), Some(-1));
+        assert_eq!(from_bits("1x11").to_i128(4), None);
+        assert_eq!(from_bits(&"1".repeat(128)).to_i128(128), Some(-1));
+        assert_eq!(
+            from_bits(&format!("1{}", "0".repeat(127))).to_i128(128),
+            Some(i128::MIN)
+        );
+        assert_eq!(from_bits(&"0".repeat(129)).to_i128(129), None);
+    }
+
+    #[test]
+    fn test_format_real() {
+        assert_eq!(Value::Real(1.5).format_real(), "1.5");
+        assert_eq!(Value::Real(-0.25).format_real(), "-0.25");
+        assert_eq!(Value::Real(0.0).format_real(), "0");
+        assert_eq!(Value::Real(1e300).format_real(), "1e300");
+        assert_eq!(Value::Real(1.5e-9).format_real(), "1.5e-9");
+        assert_eq!(Value::Real(f64::NAN).format_real(), "NaN");
+        assert_eq!(Value::Real(f64::INFINITY).format_real(), "+inf");
+        assert_eq!(Value::Real(f64::NEG_INFINITY).format_real(), "-inf");
+
+        assert_eq!(Value::default().format_real(), "invalid");
+    }
+
+    #[test]
+    fn test_real_value() {
+        let little = Value::from_real_bytes(2.5f64.to_le_bytes(), true);
+        let big = Value::from_real_bytes(2.5f64.to_be_bytes(), false);
+        assert_eq!(little, Value::Real(2.5));
+        assert_eq!(big, Value::Real(2.5));
+        assert_eq!(big.to_f64(), Some(2.5));
+        assert_eq!(big.format(VarLength::Real), "2.5");
+
+        // Reals are never bits, even if their bytes look like it.
+        assert_eq!(Value::from_u64(0, 32).to_f64(), None);
+        assert_ne!(Value::Real(0.0), Value::from_u64(0, 32));
+        assert!(Value::Real(0.0).bytes().is_empty());
+
+        assert_eq!(Value::Real(f64::NAN), Value::Real(f64::NAN));
+        assert_ne!(Value::Real(0.0), Value::Real(-0.0));
+    }
+}
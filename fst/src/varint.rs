@@ -1,5 +1,7 @@
 // use prusti_contracts::*;
 
+// Only `VarintReader` needs std; the rest of this module is `no_std`.
+#[cfg(feature = "std")]
 use std::{io, slice};
 
 /// Decode an unsigned varint. Return None if there was an error. This can
@@ -11,11 +13,10 @@ pub fn decode_varint(input: &[u8]) -> Option<u64> {
         // Check for overflow.
         // This allows the compiler to unroll the loop. I'm not sure it is
         // faster tbh.
-        if shift >= 64 {
+        // Only the lowest bit of the 10th byte is used.
+        if shift >= 64 || (shift == 63 && byte & 0x7E != 0) {
             return None;
         }
-        // Note that we don't check for overflow in the 10th byte (of which
-        // only one bit is used), but never mind.
         value |= ((byte & 0x7F) as u64) << shift;
         // Check if we're finished.
         if byte & 0x80 == 0 {
@@ -35,16 +36,16 @@ pub fn decode_svarint(input: &[u8]) -> Option<i64> {
         // Check for overflow.
         // This allows the compiler to unroll the loop. I'm not sure it is
         // faster tbh.
-        if shift >= 64 {
+        // Only the lowest bit of the 10th byte is used; the rest must be
+        // copies of it (the sign extension).
+        if shift >= 64 || (shift == 63 && !matches!(byte & 0x7F, 0x00 | 0x7F)) {
             return None;
         }
-        // Note that we don't check for overflow in the 10th byte (of which
-        // only one bit is used), but never mind.
         value |= ((byte & 0x7F) as u64) << shift;
         // Check if we're finished.
         if byte & 0x80 == 0 {
             // Sign-extend if the top byte of `byte` is 1.
-            if byte & 0x40 != 0 {
+            if byte & 0x40 != 0 && shift + 7 < 64 {
                 value |= u64::MAX << (shift + 7);
             }
             return Some(value as i64);
@@ -126,11 +127,13 @@ pub fn encode_svarint(output: &mut [u8], mut value: i64) -> usize {
 //     assert_eq!(decode_varint(output), Some(value));
 // }
 
+#[cfg(feature = "std")]
 pub trait VarintReader {
     fn read_varint(&mut self) -> io::Result<u64>;
     fn read_svarint(&mut self) -> io::Result<i64>;
 }
 
+#[cfg(feature = "std")]
 impl<R> VarintReader for R
 where
     R: io::Read,
@@ -145,11 +148,10 @@ where
             // Check for overflow.
             // This allows the compiler to unroll the loop. I'm not sure it is
             // faster tbh.
-            if shift >= 64 {
+            // Only the lowest bit of the 10th byte is used.
+            if shift >= 64 || (shift == 63 && byte & 0x7E != 0) {
                 return Err(io::Error::new(io::ErrorKind::Other, "varint overflow"));
             }
-            // Note that we don't check for overflow in the 10th byte (of which
-            // only one bit is used), but never mind.
             value |= ((byte & 0x7F) as u64) << shift;
             // Check if we're finished.
             if byte & 0x80 == 0 {
@@ -169,16 +171,16 @@ where
             // Check for overflow.
             // This allows the compiler to unroll the loop. I'm not sure it is
             // faster tbh.
-            if shift >= 64 {
+            // Only the lowest bit of the 10th byte is used; the rest must be
+            // copies of it (the sign extension).
+            if shift >= 64 || (shift == 63 && !matches!(byte & 0x7F, 0x00 | 0x7F)) {
                 return Err(io::Error::new(io::ErrorKind::Other, "svarint overflow"));
             }
-            // Note that we don't check for overflow in the 10th byte (of which
-            // only one bit is used), but never mind.
             value |= ((byte & 0x7F) as u64) << shift;
             // Check if we're finished.
             if byte & 0x80 == 0 {
                 // Sign-extend if the top byte of `byte` is 1.
-                if byte & 0x40 != 0 {
+                if byte & 0x40 != 0 && shift + 7 < 64 {
                     value |= u64::MAX << (shift + 7);
                 }
                 return Ok(value as i64);
@@ -254,6 +256,127 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_varint_overflow() {
+        // u64::MAX uses the lowest bit of the 10th byte.
+        let max = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01];
+        assert_eq!(decode_varint(&max), Some(u64::MAX));
+        check_round_trip_varint(u64::MAX);
+
+        // One more than u64::MAX.
+        let too_big = [0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x02];
+        assert_eq!(decode_varint(&too_big), None);
+
+        // 11 bytes.
+        let too_long = [0xFF; 11];
+        assert_eq!(decode_varint(&too_long), None);
+    }
+
+    #[test]
+    fn test_svarint_overflow() {
+        for value in [i64::MAX, i64::MIN, i64::MIN + 1, -1, 0] {
+            check_round_trip_svarint(value);
+        }
+
+        // The 10th byte of i64::MAX with the wrong sign.
+        let too_big = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01];
+        assert_eq!(decode_svarint(&too_big), None);
+
+        let too_long = [0xFF; 11];
+        assert_eq!(decode_svarint(&too_long), None);
+    }
+
+    /// Values that need 9 and 10 bytes. When the last byte is the 10th the
+    /// sign extension would shift by 70, so it must be skipped.
+    #[test]
+    fn test_svarint_sign_extension_boundary() {
+        // n bytes hold 7n bits including the sign.
+        let cases = [
+            ((1 << 55) - 1, 8),
+            (1 << 55, 9),
+            (-(1 << 55), 8),
+            (-(1 << 55) - 1, 9),
+            ((1 << 62) - 1, 9),
+            (1 << 62, 10),
+            (-(1 << 62), 9),
+            (-(1 << 62) - 1, 10),
+            (i64::MAX, 10),
+            (i64::MIN, 10),
+        ];
+        for (value, length) in cases {
+            let mut output = [0; 10];
+            assert_eq!(encode_svarint(&mut output, value), length, "{value}");
+            assert_eq!(decode_svarint(&output[..length]), Some(value), "{value}");
+        }
+
+        // Padded to 10 bytes with the sign in the 10th.
+        let minus_one = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F];
+        assert_eq!(decode_svarint(&minus_one), Some(-1));
+        let zero = [0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00];
+        assert_eq!(decode_svarint(&zero), Some(0));
+    }
+
+    /// `VarintReader` gives the same results as `decode_varint()` and
+    /// `decode_svarint()` at the edges.
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_varint_reader() {
+        let read_varint = |mut encoded: &[u8]| encoded.read_varint().ok();
+        let read_svarint = |mut encoded: &[u8]| encoded.read_svarint().ok();
+
+        let max = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01];
+        assert_eq!(read_varint(&max), Some(u64::MAX));
+        let too_big = [0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x02];
+        assert_eq!(read_varint(&too_big), None);
+        assert_eq!(read_varint(&[0xFF; 11]), None);
+
+        for value in [
+            i64::MAX,
+            i64::MIN,
+            i64::MIN + 1,
+            -1,
+            0,
+            1 << 55,
+            -(1 << 62) - 1,
+        ] {
+            let mut output = [0; 10];
+            let length = encode_svarint(&mut output, value);
+            assert_eq!(read_svarint(&output[..length]), Some(value), "{value}");
+        }
+        let too_big = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01];
+        assert_eq!(read_svarint(&too_big), None);
+        assert_eq!(read_svarint(&[0xFF; 11]), None);
+        let minus_one = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F];
+        assert_eq!(read_svarint(&minus_one), Some(-1));
+        let zero = [0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00];
+        assert_eq!(read_svarint(&zero), Some(0));
+    }
+
+    /// Check that the crate builds without std. This runs a whole separate
+    /// build (in its own target directory so it doesn't block this one), so
+    /// it only runs with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn test_no_std_build() {
+        let manifest_dir = env!("CARGO_MANIFEST_DIR");
+        let status = std::process::Command::new(env!("CARGO"))
+            .args([
+                "check",
+                "--quiet",
+                "--lib",
+                "--no-default-features",
+                "--manifest-path",
+            ])
+            .arg(format!("{manifest_dir}/Cargo.toml"))
+            .env(
+                "CARGO_TARGET_DIR",
+                format!("{manifest_dir}/../target/no_std"),
+            )
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
     /// Manually calculated examples (see the figures in the specification).
     #[test]
     fn test_manual_examples() {
@@ -0,0 +1,725 @@
+//! Plain VCD (Value Change Dump) support.
+//!
+//! VCD files are parsed into an `Fst` with all of the waves already decoded,
+//! so anything that works with FST files works with VCD files too. The file
+//! is read a line at a time so only the decoded waves are kept in memory.
+//!
+//! `Fst::write_vcd()` writes VCD files from any `Fst`.
+//!
+//! Only 4-state logic and reals are supported. Other VHDL values are mapped to
+//! the closest 4-state value (see `bit_code()`).
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufRead, BufReader, Lines, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::info;
+use typed_index_collections::TiVec;
+
+use crate::{
+    fst::{
+        BlackoutType, Fst, Header, HierarchyScope, HierarchyVar, MissingInitialValue, ReadOptions,
+        ScopeId, VarId, VarLength, VarLengths, REAL_ENDIANNESS_LITTLE,
+    },
+    valvec::{bit_code, LogicBytes, ValAndTimeVec, Value},
+};
+
+/// Load a VCD file.
+pub fn load(filename: &Path) -> Result<Fst> {
+    let file = File::open(filename).with_context(|| format!("Opening {}", filename.display()))?;
+    let mut tokens = Tokens::new(BufReader::new(file));
+    parse(&mut tokens)
+        .with_context(|| format!("VCD parse error on line {}", tokens.line_number))
+        .map(|parsed| {
+            Fst::from_waves(
+                filename,
+                parsed.header,
+                parsed.hierarchy,
+                parsed.var_lengths,
+                parsed.waves,
+                parsed.blackouts,
+            )
+        })
+}
+
+impl Fst {
+    /// Write the given vars as a VCD file. Only the scopes that contain them
+    /// are included. Vars with no initial value start as X.
+    pub fn write_vcd(&self, mut out: impl Write, varids: &[VarId]) -> Result<()> {
+        let selected: HashSet<VarId> = varids.iter().copied().collect();
+
+        writeln!(out, "$date {} $end", self.header.date_string())?;
+        writeln!(out, "$version {} $end", self.header.writer_string())?;
+        writeln!(
+            out,
+            "$timescale {} $end",
+            timescale_string(self.header.timescale)?
+        )?;
+
+        let has_selected =
+            |scope: &HierarchyScope| scope.vars.iter().any(|var| selected.contains(&var.id));
+
+        // Walk the scopes in depth-first order, closing them as we leave them.
+        let mut open_scopes: Vec<ScopeId> = Vec::new();
+        for (index, node) in self.hierarchy.all().iter().enumerate() {
+            let id = ScopeId(index);
+            while let Some(&open) = open_scopes.last() {
+                if self.hierarchy.parents(id).any(|(parent, _)| parent == open) {
+                    break;
+                }
+                writeln!(out, "$upscope $end")?;
+                open_scopes.pop();
+            }
+
+            let needed = has_selected(&node.value)
+                || self
+                    .hierarchy
+                    .descendents(id)
+                    .iter()
+                    .any(|node| has_selected(&node.value));
+            if !needed {
+                continue;
+            }
+
+            let scope = &node.value;
+            writeln!(
+                out,
+                "$scope {} {} $end",
+                SCOPE_TYPES.get(scope.type_ as usize).unwrap_or(&"module"),
+                scope.name
+            )?;
+            open_scopes.push(id);
+
+            for var in scope.vars.iter().filter(|var| selected.contains(&var.id)) {
+                let (var_type, size) = match self.var_lengths.length(var.id) {
+                    VarLength::Real => ("real", 64),
+                    VarLength::Bits(bits) => {
+                        (*VAR_TYPES.get(var.type_ as usize).unwrap_or(&"wire"), bits)
+                    }
+                };
+                writeln!(
+                    out,
+                    "$var {} {} {} {} $end",
+                    var_type,
+                    size,
+                    id_code(var.id),
+                    var.name
+                )?;
+            }
+        }
+        for _ in open_scopes {
+            writeln!(out, "$upscope $end")?;
+        }
+        writeln!(out, "$enddefinitions $end")?;
+
+        // Merge all the changes in time order.
+        let options = ReadOptions {
+            missing_initial_value: MissingInitialValue::X,
+            ..Default::default()
+        };
+        let mut changes = Vec::new();
+        for &varid in varids {
+            let wave = self.read_wave_with_options(varid, &options)?;
+            changes.extend(wave.into_iter().map(|(time, value)| (time, varid, value)));
+        }
+        // This is stable so changes to the same var stay in order.
+        changes.sort_by_key(|(time, _, _)| *time);
+
+        let mut current_time = None;
+        for (time, varid, value) in changes {
+            if current_time != Some(time) {
+                writeln!(out, "#{time}")?;
+                current_time = Some(time);
+            }
+            match self.var_lengths.length(varid) {
+                VarLength::Real => writeln!(out, "r{} {}", value.format_real(), id_code(varid))?,
+                VarLength::Bits(1) => writeln!(out, "{}{}", value.bits_string(1), id_code(varid))?,
+                VarLength::Bits(bits) => {
+                    writeln!(out, "b{} {}", value.bits_string(bits), id_code(varid))?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The VCD identifier code for a var: a base-94 number using the printable
+/// ASCII characters.
+fn id_code(varid: VarId) -> String {
+    let mut n = varid.0;
+    let mut code = String::new();
+    loop {
+        code.push((b'!' + (n % 94) as u8) as char);
+        n /= 94;
+        if n == 0 {
+            return code;
+        }
+        n -= 1;
+    }
+}
+
+/// The inverse of `parse_timescale()`.
+fn timescale_string(order: i8) -> Result<String> {
+    if !(-15..=2).contains(&order) {
+        bail!("Timescale 1e{order} s can't be represented in VCD");
+    }
+    let unit = order.div_euclid(3) * 3;
+    let magnitude = 10u32.pow((order - unit) as u32);
+    let unit = match unit {
+        0 => "s",
+        -3 => "ms",
+        -6 => "us",
+        -9 => "ns",
+        -12 => "ps",
+        _ => "fs",
+    };
+    Ok(format!("{magnitude}{unit}"))
+}
+
+struct Parsed {
+    header: Header,
+    hierarchy: espalier::Tree<ScopeId, HierarchyScope>,
+    var_lengths: VarLengths,
+    waves: TiVec<VarId, ValAndTimeVec>,
+    blackouts: Vec<(BlackoutType, u64)>,
+}
+
+fn parse<R: BufRead>(tokens: &mut Tokens<R>) -> Result<Parsed> {
+    let mut header = Header {
+        start_time: 0,
+        end_time: 0,
+        real_endianness: REAL_ENDIANNESS_LITTLE,
+        writer_memory_use: 0,
+        num_scopes: 0,
+        num_hiearchy_vars: 0,
+        num_vars: 0,
+        num_vc_blocks: 0,
+        timescale: 0,
+        writer: [0; 128],
+        date: [0; 26],
+        reserved: [0; 93],
+        filetype: 0,
+        timezero: 0,
+    };
+
+    let mut hierarchy = espalier::Tree::new();
+    // The current scope. We can't use `hierarchy.last_mut()` for this because
+    // it is the last scope added, which isn't the current one after `$upscope`.
+    let mut scope_stack: Vec<ScopeId> = Vec::new();
+    let mut var_lengths = VarLengths {
+        lengths: TiVec::new(),
+        lengths_long: HashMap::new(),
+    };
+    let mut ids: HashMap<String, VarId> = HashMap::new();
+
+    // Declarations.
+    loop {
+        let token = tokens.next()?.context("Missing $enddefinitions")?;
+        match token.as_str() {
+            "$scope" => {
+                let args = tokens.until_end()?;
+                let [scope_type, name] = args.as_slice() else {
+                    bail!("Invalid $scope: {args:?}");
+                };
+                let id = hierarchy.push(HierarchyScope {
+                    type_: scope_type_code(scope_type),
+                    name: name.clone(),
+                    ..Default::default()
+                });
+                scope_stack.push(id);
+            }
+            "$upscope" => {
+                tokens.until_end()?;
+                hierarchy.up();
+                scope_stack.pop().context("$upscope without $scope")?;
+            }
+            "$var" => {
+                let args = tokens.until_end()?;
+                let [var_type, size, id_code, reference, index @ ..] = args.as_slice() else {
+                    bail!("Invalid $var: {args:?}");
+                };
+                let size: u32 = size
+                    .parse()
+                    .with_context(|| format!("Invalid $var size {size:?}"))?;
+                let type_ = var_type_code(var_type);
+                let length = if matches!(var_type.as_str(), "real" | "realtime" | "shortreal") {
+                    VarLength::Real
+                } else {
+                    VarLength::Bits(size)
+                };
+
+                let mut name = reference.clone();
+                if !index.is_empty() {
+                    name.push(' ');
+                    name.push_str(&index.join(""));
+                }
+
+                let (id, is_alias) = match ids.get(id_code) {
+                    Some(&id) => (id, true),
+                    None => {
+                        let id = VarId(var_lengths.lengths.len());
+                        var_lengths.push(length);
+                        ids.insert(id_code.clone(), id);
+                        (id, false)
+                    }
+                };
+
+                let scope = *scope_stack.last().context("$var outside of a $scope")?;
+                hierarchy
+                    .get_mut(scope)
+                    .expect("Scope stack contains invalid ID")
+                    .value
+                    .vars
+                    .push(HierarchyVar {
+                        type_,
+                        direction: 0,
+                        name,
+                        raw_name: None,
+                        length: size as u64,
+                        id,
+                        is_alias,
+                        enum_def: None,
+                        source: None,
+                    });
+                header.num_hiearchy_vars += 1;
+            }
+            "$timescale" => {
+                header.timescale = parse_timescale(&tokens.until_end()?.join(""))?;
+            }
+            "$date" => {
+                copy_truncated(&mut header.date, &tokens.until_end()?.join(" "));
+            }
+            "$version" => {
+                copy_truncated(&mut header.writer, &tokens.until_end()?.join(" "));
+            }
+            "$enddefinitions" => {
+                tokens.until_end()?;
+                break;
+            }
+            command if command.starts_with('$') => {
+                // $comment and anything else we don't care about.
+                tokens.until_end()?;
+            }
+            _ => bail!("Unexpected {token:?} in declarations"),
+        }
+    }
+
+    header.num_scopes = hierarchy.len() as u64;
+    header.num_vars = var_lengths.lengths.len() as u64;
+
+    info!(
+        "Read VCD declarations: {} scopes, {} vars",
+        header.num_scopes, header.num_vars
+    );
+
+    // Value changes.
+    let mut waves: TiVec<VarId, ValAndTimeVec> = TiVec::new();
+    waves.resize_with(var_lengths.lengths.len(), Default::default);
+    let mut blackouts = Vec::new();
+    let mut time = 0;
+    let mut seen_time = false;
+
+    while let Some(token) = tokens.next()? {
+        let (value, id_code) = match token.as_bytes()[0] {
+            b'#' => {
+                let t: u64 = token[1..]
+                    .parse()
+                    .with_context(|| format!("Invalid time {token:?}"))?;
+                if !seen_time {
+                    header.start_time = t;
+                    seen_time = true;
+                } else if t < time {
+                    bail!("Time goes backwards from {time} to {t}");
+                }
+                time = t;
+                header.end_time = t;
+                continue;
+            }
+            b'$' => {
+                match token.as_str() {
+                    "$dumpoff" => blackouts.push((BlackoutType::DumpOff, time)),
+                    "$dumpon" => blackouts.push((BlackoutType::DumpOn, time)),
+                    "$comment" => {
+                        tokens.until_end()?;
+                    }
+                    // The values in $dumpvars etc. are just normal value changes.
+                    _ => {}
+                }
+                continue;
+            }
+            b'b' | b'B' | b'r' | b'R' => {
+                let id_code = tokens.next()?.context("Missing identifier after value")?;
+                (token, id_code)
+            }
+            _ => {
+                let id_code = token[1..].to_string();
+                (token[..1].to_string(), id_code)
+            }
+        };
+
+        let id = *ids
+            .get(&id_code)
+            .ok_or_else(|| anyhow!("Unknown identifier {id_code:?}"))?;
+
+        let value = match var_lengths.length(id) {
+            VarLength::Real => {
+                let real = value
+                    .strip_prefix(['r', 'R'])
+                    .context("Real var must have an r value")?;
+                let real: f64 = real
+                    .parse()
+                    .with_context(|| format!("Invalid real {real:?}"))?;
+                Value::Real(real)
+            }
+            VarLength::Bits(bits) => {
+                let digits = value.strip_prefix(['b', 'B']).unwrap_or(&value);
+                value_from_vcd_bits(digits, bits)?
+            }
+        };
+
+        let wave = &mut waves[id];
+        match wave.last_mut() {
+            // Multiple changes at the same time; only the last one counts.
+            Some(last) if last.0 == time => last.1 = value,
+            _ => wave.push((time, value)),
+        }
+    }
+
+    Ok(Parsed {
+        header,
+        hierarchy,
+        var_lengths,
+        waves,
+        blackouts,
+    })
+}
+
+/// Convert a VCD binary value (MSB first) into a `Value`. If it has fewer
+/// digits than the var it is extended with 0, or with X/Z if the leftmost
+/// digit is X/Z. If it has more, the leftmost digits are ignored.
+fn value_from_vcd_bits(digits: &str, bits: u32) -> Result<Value> {
+    let bits = bits as usize;
+    let digits = digits.as_bytes();
+
+    let fill = match digits.first() {
+        Some(c @ (b'x' | b'X' | b'z' | b'Z')) => *c,
+        _ => b'0',
+    };
+
+    let mut bytes = LogicBytes::default();
+    bytes.resize(bits.div_ceil(4), 0);
+
+    for i in 0..bits {
+        // Bit 0 is the last digit.
+        let c = match digits.len().checked_sub(i + 1) {
+            Some(index) => digits[index],
+            None => fill,
+        };
+        let code = bit_code(c).with_context(|| format!("Invalid value digit {:?}", c as char))?;
+        bytes[i / 4] |= code << ((i % 4) * 2);
+    }
+    Ok(Value::Logic(bytes))
+}
+
+/// Parse e.g. `1ns` or `100ps` (already joined if there was a space) into
+/// the order of magnitude in seconds.
+fn parse_timescale(timescale: &str) -> Result<i8> {
+    let unit_start = timescale
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(timescale.len());
+    let (magnitude, unit) = timescale.split_at(unit_start);
+    let magnitude = match magnitude {
+        "1" => 0,
+        "10" => 1,
+        "100" => 2,
+        _ => bail!("Unsupported timescale {timescale:?}"),
+    };
+    let unit = match unit {
+        "s" => 0,
+        "ms" => -3,
+        "us" => -6,
+        "ns" => -9,
+        "ps" => -12,
+        "fs" => -15,
+        _ => bail!("Unsupported timescale {timescale:?}"),
+    };
+    Ok(magnitude + unit)
+}
+
+/// VCD scope types, indexed by FST scope type (`FST_ST_VCD_*`).
+const SCOPE_TYPES: &[&str] = &[
+    "module",
+    "task",
+    "function",
+    "begin",
+    "fork",
+    "generate",
+    "struct",
+    "union",
+    "class",
+    "interface",
+    "package",
+    "program",
+];
+
+/// VCD var types, indexed by FST var type (`FST_VT_*`).
+const VAR_TYPES: &[&str] = &[
+    "event",
+    "integer",
+    "parameter",
+    "real",
+    "real_parameter",
+    "reg",
+    "supply0",
+    "supply1",
+    "time",
+    "tri",
+    "triand",
+    "trior",
+    "trireg",
+    "tri0",
+    "tri1",
+    "wand",
+    "wire",
+    "wor",
+    "port",
+    "sparray",
+    "realtime",
+    "string",
+    "bit",
+    "logic",
+    "int",
+    "shortint",
+    "longint",
+    "byte",
+    "enum",
+    "shortreal",
+];
+
+/// The FST scope type for a VCD scope type. Unknown types are modules.
+fn scope_type_code(scope_type: &str) -> u8 {
+    SCOPE_TYPES
+        .iter()
+        .position(|t| *t == scope_type)
+        .unwrap_or(0) as u8
+}
+
+/// The FST var type for a VCD var type. Unknown types are wires.
+fn var_type_code(var_type: &str) -> u8 {
+    VAR_TYPES.iter().position(|t| *t == var_type).unwrap_or(16) as u8
+}
+
+/// Copy a string into a fixed size null-terminated array, truncating if necessary.
+fn copy_truncated<const N: usize>(dest: &mut [u8; N], s: &str) {
+    let len = s.len().min(N - 1);
+    dest[..len].copy_from_slice(&s.as_bytes()[..len]);
+}
+
+/// Whitespace separated tokens, read a line at a time.
+struct Tokens<R> {
+    lines: Lines<R>,
+    words: std::vec::IntoIter<String>,
+    line_number: usize,
+}
+
+impl<R: BufRead> Tokens<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+            words: Vec::new().into_iter(),
+            line_number: 0,
+        }
+    }
+
+    fn next(&mut self) -> Result<Option<String>> {
+        loop {
+            if let Some(word) = self.words.next() {
+                return Ok(Some(word));
+            }
+            let Some(line) = self.lines.next() else {
+                return Ok(None);
+            };
+            self.line_number += 1;
+            self.words = line?
+                .split_whitespace()
+                .map(str::to_owned)
+                .collect::<Vec<_>>()
+                .into_iter();
+        }
+    }
+
+    /// Read the arguments of a command, up to and including `$end`.
+    fn until_end(&mut self) -> Result<Vec<String>> {
+        let mut args = Vec::new();
+        loop {
+            match self.next()? {
+                Some(token) if token == "$end" => return Ok(args),
+                Some(token) => args.push(token),
+                None => bail!("Missing $end"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Waves;
+    use std::io::Write;
+
+    const EXAMPLE: &str = "$date Mon Jan 1 00:00:00 2024 $end
+$version example 1.0 $end
+$timescale 10 ps $end
+$scope module top $end
+$var wire 1 ! clk $end
+$var wire 4 \" data [3:0] $end
+$scope module sub $end
+$var wire 1 ! clk_alias $end
+$var real 64 # level $end
+$upscope $end
+$var reg 1 $ late $end
+$upscope $end
+$enddefinitions $end
+$comment start $end
+#5
+$dumpvars
+0!
+bx \"
+r0 #
+x$
+$end
+#10
+1!
+b10 \"
+r1.5 #
+#20
+0!
+bz1 \"
+$dumpoff
+#30
+1$
+";
+
+    #[test]
+    fn test_load() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(EXAMPLE.as_bytes()).unwrap();
+        let mut fst = load(file.path()).unwrap();
+
+        assert_eq!(fst.header.timescale, -11);
+        assert_eq!(fst.header.start_time, 5);
+        assert_eq!(fst.header.end_time, 30);
+        assert_eq!(fst.header.num_vars, 4);
+        assert_eq!(fst.header.num_hiearchy_vars, 5);
+        assert_eq!(fst.header.writer_string(), "example 1.0");
+
+        // `late` is declared after `sub`, so it must still be in `top`.
+        assert_eq!(fst.var_by_path("top.late"), Some(VarId(3)));
+        assert_eq!(fst.var_by_path("top.data"), Some(VarId(1)));
+        assert_eq!(fst.var_by_path("top.sub.clk_alias"), Some(VarId(0)));
+        assert_eq!(fst.var_lengths.length(VarId(2)), VarLength::Real);
+
+        let values = |wave: ValAndTimeVec| -> Vec<(u64, Vec<u8>)> {
+            wave.into_iter()
+                .map(|(t, v)| (t, v.bytes().to_vec()))
+                .collect()
+        };
+
+        assert_eq!(
+            values(fst.read_wave(VarId(0)).unwrap()),
+            [(5, vec![0]), (10, vec![1]), (20, vec![0])]
+        );
+        // Extended to the left with X, 0 and Z.
+        assert_eq!(
+            values(fst.read_wave(VarId(1)).unwrap()),
+            [
+                (5, vec![0b10_10_10_10]),
+                (10, vec![0b00_00_01_00]),
+                (20, vec![0b11_11_11_01]),
+            ]
+        );
+        let level = fst.read_wave(VarId(2)).unwrap();
+        assert_eq!(level[1].1.format_real(), "1.5");
+        assert_eq!(
+            values(fst.read_wave(VarId(3)).unwrap()),
+            [(5, vec![2]), (30, vec![1])]
+        );
+
+        // In-memory waves don't need loading.
+        assert_eq!(fst.wave(VarId(0)).unwrap().len(), 3);
+        assert_eq!(fst.times(), [5, 10, 20, 30]);
+
+        assert!(matches!(fst.blackouts[..], [(BlackoutType::DumpOff, 20)]));
+        assert!(fst.reload_appended().is_err());
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(EXAMPLE.as_bytes()).unwrap();
+        let fst = load(file.path()).unwrap();
+
+        let mut written = tempfile::NamedTempFile::new().unwrap();
+        let all: Vec<VarId> = (0..4).map(VarId).collect();
+        fst.write_vcd(&mut written, &all).unwrap();
+        let reread = load(written.path()).unwrap();
+
+        assert_eq!(reread.header.timescale, fst.header.timescale);
+        // Var IDs are assigned in declaration order so they can change.
+        for path in [
+            "top.clk",
+            "top.data",
+            "top.sub.clk_alias",
+            "top.sub.level",
+            "top.late",
+        ] {
+            let original = fst.var_by_path(path).unwrap();
+            let copy = reread.var_by_path(path).unwrap();
+            assert_eq!(
+                reread.read_wave(copy).unwrap(),
+                fst.read_wave(original).unwrap(),
+                "{path}"
+            );
+        }
+
+        // Only the scopes containing the selected var.
+        let mut out = Vec::new();
+        fst.write_vcd(&mut out, &[VarId(2)]).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(
+            "$scope module top $end\n$scope module sub $end\n$var real 64 # level $end\n$upscope $end\n$upscope $end\n"
+        ));
+        assert!(!out.contains("clk"));
+    }
+
+    #[test]
+    fn test_id_code_and_timescale() {
+        assert_eq!(id_code(VarId(0)), "!");
+        assert_eq!(id_code(VarId(93)), "~");
+        assert_eq!(id_code(VarId(94)), "!!");
+        assert_eq!(timescale_string(-11).unwrap(), "10ps");
+        assert_eq!(timescale_string(-9).unwrap(), "1ns");
+        assert_eq!(timescale_string(2).unwrap(), "100s");
+        assert!(timescale_string(3).is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        let check = |vcd: &str, message: &str| {
+            let mut file = tempfile::NamedTempFile::new().unwrap();
+            file.write_all(vcd.as_bytes()).unwrap();
+            let err = format!("{:#}", load(file.path()).unwrap_err());
+            assert!(err.contains(message), "{err}");
+        };
+        check("$scope module top $end\n", "Missing $enddefinitions");
+        check(
+            "$scope module top $end\n$var wire 1 ! a $end\n$enddefinitions $end\n#0\n1?\n",
+            "line 5: Unknown identifier \"?\"",
+        );
+        check("$timescale 3ns $end\n", "Unsupported timescale");
+    }
+}
@@ -0,0 +1,279 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, Thread},
+};
+
+use anyhow::Result;
+use egui::Ui;
+use fst::{
+    block_cache::BlockPrefetcher,
+    fst::{Fst, VarId},
+    valvec::{DetectPeriod, ValAndTimeVec},
+};
+use log::{error, info};
+
+/// The waves that have been read from the file. A var's wave is kept while
+/// it is in the waves view. When it is removed the wave is dropped, unless
+/// `max_removed` is non-zero, in which case the most recently removed waves
+/// are kept so that adding them back doesn't read the file again.
+#[derive(Default)]
+pub struct WaveCache {
+    /// The waves of the vars in the waves view.
+    pub shown: HashMap<VarId, ValAndTimeVec>,
+    /// The clock period of each shown wave (see `DetectPeriod`). Finding it
+    /// scans the whole wave, so it is done once when the wave is shown
+    /// instead of every frame.
+    periods: HashMap<VarId, Option<u64>>,
+    /// Waves of vars that have been removed from the view, least recently
+    /// removed first.
+    removed: VecDeque<(VarId, ValAndTimeVec)>,
+    /// How many removed waves to keep. This is saved between runs.
+    pub max_removed: usize,
+    /// How many MB of Value Change block data the file keeps in memory (see
+    /// `Fst::set_block_cache_size()`), which is filled in the background
+    /// when a file is loaded. 0 disables it. This is saved between runs.
+    pub block_cache_mb: u64,
+    /// Filling the block cache. It is paused as soon as a wave is read so
+    /// the two don't compete for the file, and resumed by `end_frame()` after
+    /// a frame in which no waves were read.
+    prefetch: Option<BackgroundPrefetch>,
+    /// Whether a wave has been read from the file since the last
+    /// `end_frame()`.
+    read_this_frame: bool,
+}
+
+impl WaveCache {
+    /// Add a var to the view, reusing its wave if it was recently removed
+    /// and otherwise getting it from `read`.
+    pub fn show(
+        &mut self,
+        varid: VarId,
+        read: impl FnOnce() -> Result<ValAndTimeVec>,
+    ) -> Result<()> {
+        if self.shown.contains_key(&varid) {
+            return Ok(());
+        }
+        let wave = match self.removed.iter().position(|(id, _)| *id == varid) {
+            Some(index) => self.removed.remove(index).unwrap().1,
+            None => {
+                if let Some(prefetch) = &self.prefetch {
+                    prefetch.pause();
+                }
+                self.read_this_frame = true;
+                read()?
+            }
+        };
+        self.periods.insert(varid, wave.detect_period());
+        self.shown.insert(varid, wave);
+        Ok(())
+    }
+
+    /// The clock period of a shown var's wave, if it is a clock.
+    pub fn period(&self, varid: VarId) -> Option<u64> {
+        self.periods.get(&varid).copied().flatten()
+    }
+
+    /// Remove a var from the view, keeping its wave if `max_removed` allows.
+    pub fn hide(&mut self, varid: VarId) {
+        self.periods.remove(&varid);
+        if let Some(wave) = self.shown.remove(&varid) {
+            self.removed.push_back((varid, wave));
+        }
+        self.evict();
+    }
+
+    /// Drop removed waves until there are at most `max_removed`.
+    pub fn evict(&mut self) {
+        while self.removed.len() > self.max_removed {
+            self.removed.pop_front();
+        }
+    }
+
+    /// Drop the waves of all removed vars.
+    pub fn clear_removed(&mut self) {
+        self.removed.clear();
+    }
+
+    /// Drop everything, e.g. when a different file is loaded.
+    pub fn clear(&mut self) {
+        self.shown.clear();
+        self.periods.clear();
+        self.removed.clear();
+        self.prefetch = None;
+    }
+
+    /// Start reading a newly loaded file's Value Change blocks into its block
+    /// cache in the background, if the cache is enabled.
+    pub fn start_prefetch(&mut self, file: &Fst) {
+        file.set_block_cache_size(self.block_cache_bytes());
+        if self.block_cache_mb == 0 {
+            return;
+        }
+        match file.block_prefetcher() {
+            Ok(prefetcher) => self.prefetch = Some(BackgroundPrefetch::start(prefetcher)),
+            Err(err) => info!("Not prefetching blocks: {err}"),
+        }
+    }
+
+    /// Call at the end of each frame. If no waves were read during it the
+    /// user is idle, so the prefetcher can carry on.
+    pub fn end_frame(&mut self) {
+        if !self.read_this_frame {
+            if let Some(prefetch) = &self.prefetch {
+                prefetch.resume();
+            }
+        }
+        self.read_this_frame = false;
+    }
+
+    /// `block_cache_mb` in bytes.
+    pub fn block_cache_bytes(&self) -> u64 {
+        self.block_cache_mb * 1024 * 1024
+    }
+
+    /// The total number of waves held, shown and removed.
+    pub fn len(&self) -> usize {
+        self.shown.len() + self.removed.len()
+    }
+}
+
+/// Runs a `BlockPrefetcher` in a background thread until it has read every
+/// block, the block cache is full or this is dropped. It can be paused in
+/// between blocks.
+struct BackgroundPrefetch {
+    stop: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    thread: Thread,
+}
+
+impl BackgroundPrefetch {
+    fn start(mut prefetcher: BlockPrefetcher) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let paused = Arc::new(AtomicBool::new(false));
+        let stop_thread = stop.clone();
+        let paused_thread = paused.clone();
+        let handle = thread::spawn(move || {
+            while !stop_thread.load(Ordering::Relaxed) {
+                if paused_thread.load(Ordering::Relaxed) {
+                    // Woken by `resume()` or dropping.
+                    thread::park();
+                    continue;
+                }
+                match prefetcher.prefetch_next() {
+                    Ok(true) => {}
+                    Ok(false) => break,
+                    Err(err) => {
+                        error!("Error prefetching blocks: {err:?}");
+                        break;
+                    }
+                }
+            }
+            info!(
+                "Stopped prefetching with {} blocks left",
+                prefetcher.remaining()
+            );
+        });
+        Self {
+            stop,
+            paused,
+            thread: handle.thread().clone(),
+        }
+    }
+
+    /// Stop after the block that is being read, until `resume()`.
+    fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    fn resume(&self) {
+        if self.paused.swap(false, Ordering::Relaxed) {
+            self.thread.unpark();
+        }
+    }
+}
+
+impl Drop for BackgroundPrefetch {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread.unpark();
+    }
+}
+
+pub fn show_cache_menu(ui: &mut Ui, cache: &mut WaveCache) {
+    ui.label(format!(
+        "{} waves cached, {} of them removed from the view.",
+        cache.len(),
+        cache.removed.len()
+    ));
+    let changed = ui
+        .add(egui::Slider::new(&mut cache.max_removed, 0..=64).text("Removed waves to keep"))
+        .changed();
+    if changed {
+        cache.evict();
+    }
+    // This applies to the file when it is next drawn, and is prefetched into
+    // when the next file is loaded.
+    ui.add(egui::Slider::new(&mut cache.block_cache_mb, 0..=1024).text("Block cache (MB)"));
+    if ui.button("Clear cache").clicked() {
+        ui.close_menu();
+        cache.clear_removed();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::bail;
+    use fst::valvec::Value;
+
+    fn wave(value: u64) -> ValAndTimeVec {
+        vec![(0, Value::from_u64(value, 4))]
+    }
+
+    #[test]
+    fn test_wave_cache() {
+        let mut cache = WaveCache::default();
+        cache.show(VarId(1), || Ok(wave(1))).unwrap();
+        cache.show(VarId(2), || Ok(wave(2))).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.period(VarId(1)), None);
+        let clock = (0..20)
+            .map(|i| (i * 5, Value::from_u64(i % 2, 1)))
+            .collect();
+        cache.show(VarId(9), || Ok(clock)).unwrap();
+        assert_eq!(cache.period(VarId(9)), Some(10));
+        cache.hide(VarId(9));
+        assert_eq!(cache.period(VarId(9)), None);
+
+        // By default removed waves are dropped.
+        cache.hide(VarId(1));
+        assert_eq!(cache.len(), 1);
+        assert!(!cache.shown.contains_key(&VarId(1)));
+
+        // Otherwise they are reused.
+        cache.max_removed = 2;
+        cache.hide(VarId(2));
+        assert_eq!(cache.len(), 1);
+        cache.show(VarId(2), || bail!("read again")).unwrap();
+        assert_eq!(cache.shown[&VarId(2)], wave(2));
+
+        // The least recently removed are dropped first.
+        cache.show(VarId(3), || Ok(wave(3))).unwrap();
+        cache.show(VarId(4), || Ok(wave(4))).unwrap();
+        cache.hide(VarId(2));
+        cache.hide(VarId(3));
+        cache.hide(VarId(4));
+        assert_eq!(cache.len(), 2);
+        assert!(cache.show(VarId(2), || bail!("read again")).is_err());
+        cache.show(VarId(3), || bail!("read again")).unwrap();
+
+        cache.clear_removed();
+        assert_eq!(cache.len(), 1);
+        cache.clear();
+        assert_eq!(cache.len(), 0);
+    }
+}
@@ -0,0 +1,36 @@
+use egui::{Align2, Area, Context, Frame};
+
+/// What the debug overlay shows, gathered during the frame.
+pub struct FrameStats {
+    /// Time since the previous frame in seconds. This is only meaningful
+    /// while something is repainting continuously, e.g. during a drag.
+    pub frame_time: f32,
+    /// Time spent in the previous `update()` in seconds, if known.
+    pub cpu_time: Option<f32>,
+    /// Shapes that the waves view submitted to the painter.
+    pub shapes: usize,
+    pub cached_waves: usize,
+}
+
+/// A small window in the top right corner showing frame timings and how
+/// much was drawn, so it is clear whether slowness comes from reading waves
+/// or from painting them.
+pub fn show_debug_overlay(ctx: &Context, stats: &FrameStats) {
+    Area::new("debug_overlay")
+        .anchor(Align2::RIGHT_TOP, [-8.0, 32.0])
+        .interactable(false)
+        .show(ctx, |ui| {
+            Frame::popup(ui.style()).show(ui, |ui| {
+                ui.monospace(format!(
+                    "Frame time:   {:6.1} ms",
+                    stats.frame_time * 1000.0
+                ));
+                ui.monospace(match stats.cpu_time {
+                    Some(cpu_time) => format!("Update time:  {:6.1} ms", cpu_time * 1000.0),
+                    None => "Update time:       -".to_string(),
+                });
+                ui.monospace(format!("Shapes:       {:6}", stats.shapes));
+                ui.monospace(format!("Cached waves: {:6}", stats.cached_waves));
+            });
+        });
+}
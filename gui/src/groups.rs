@@ -0,0 +1,144 @@
+use egui::Ui;
+use fst::fst::{Fst, VarId};
+
+/// A named set of vars that are drawn together in the waves view, under a
+/// header that can be collapsed. These are saved between runs.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Group {
+    pub name: String,
+    /// The `VarId`s of the vars in the group, in the order they are drawn.
+    /// These are only meaningful for the loaded file.
+    #[serde(skip)]
+    pub vars: Vec<usize>,
+    /// The dotted paths of the vars, which is how they are saved and found
+    /// again in the next file that is loaded. See `save_paths()` and
+    /// `find_vars()`.
+    pub paths: Vec<String>,
+    pub collapsed: bool,
+}
+
+/// Set each group's `paths` from its `vars`, e.g. before saving the session
+/// or loading another file.
+pub fn save_paths(groups: &mut [Group], file: &Fst) {
+    for group in groups {
+        group.paths = group
+            .vars
+            .iter()
+            .filter_map(|&v| file.var_path_by_id(VarId(v)))
+            .collect();
+    }
+}
+
+/// Set each group's `vars` from its `paths` in a newly loaded file. Vars
+/// that aren't in it are dropped.
+pub fn find_vars(groups: &mut [Group], file: &Fst) {
+    for group in groups {
+        group.vars = group
+            .paths
+            .iter()
+            .filter_map(|path| file.var_by_path(path))
+            .map(|varid| varid.0)
+            .collect();
+    }
+}
+
+/// Move a var into a group (appending it), or out of all of them if `group`
+/// is None. A var is in at most one group.
+pub fn move_to_group(groups: &mut [Group], varid: VarId, group: Option<usize>) {
+    for g in groups.iter_mut() {
+        g.vars.retain(|&v| v != varid.0);
+    }
+    if let Some(g) = group.and_then(|group| groups.get_mut(group)) {
+        g.vars.push(varid.0);
+    }
+}
+
+pub fn show_groups_menu(ui: &mut Ui, groups: &mut Vec<Group>) {
+    if ui.button("New group").clicked() {
+        groups.push(Group {
+            name: format!("Group {}", groups.len() + 1),
+            ..Default::default()
+        });
+    }
+    if groups.is_empty() {
+        return;
+    }
+    ui.label("Drag waves onto a group's header to add them to it.");
+
+    let mut remove = None;
+    for (index, group) in groups.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut group.name);
+            ui.label(format!("{} vars", group.vars.len()));
+            if ui.button("Remove").clicked() {
+                remove = Some(index);
+            }
+        });
+    }
+    if let Some(index) = remove {
+        groups.remove(index);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_move_to_group() {
+        let mut groups = vec![
+            Group {
+                name: "a".to_string(),
+                vars: vec![1, 2],
+                ..Default::default()
+            },
+            Group {
+                name: "b".to_string(),
+                vars: vec![3],
+                ..Default::default()
+            },
+        ];
+
+        move_to_group(&mut groups, VarId(1), Some(1));
+        assert_eq!(groups[0].vars, [2]);
+        assert_eq!(groups[1].vars, [3, 1]);
+
+        // Moving to the same group moves it to the end.
+        move_to_group(&mut groups, VarId(3), Some(1));
+        assert_eq!(groups[1].vars, [1, 3]);
+
+        move_to_group(&mut groups, VarId(2), None);
+        assert!(groups[0].vars.is_empty());
+
+        // Invalid groups just remove it.
+        move_to_group(&mut groups, VarId(3), Some(5));
+        assert_eq!(groups[1].vars, [1]);
+    }
+
+    fn load_vcd(vars: &[&str]) -> Fst {
+        let mut vcd = String::from("$scope module top $end\n");
+        for (i, var) in vars.iter().enumerate() {
+            vcd += &format!("$var wire 1 v{i} {var} $end\n");
+        }
+        vcd += "$upscope $end\n$enddefinitions $end\n";
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), vcd).unwrap();
+        fst::vcd::load(file.path()).unwrap()
+    }
+
+    #[test]
+    fn test_group_paths() {
+        let mut groups = vec![Group {
+            name: "a".to_string(),
+            vars: vec![1, 2],
+            ..Default::default()
+        }];
+        save_paths(&mut groups, &load_vcd(&["a", "b", "c"]));
+        assert_eq!(groups[0].paths, ["top.b", "top.c"]);
+
+        // The vars have different IDs in the new file, and b is gone.
+        find_vars(&mut groups, &load_vcd(&["c", "x", "a"]));
+        assert_eq!(groups[0].vars, [0]);
+    }
+}
@@ -1,13 +1,30 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    time::{Duration, Instant},
+};
 
-use egui::{Context, ScrollArea, SidePanel, Ui};
+use anyhow::Result;
+use egui::{Context, Event, Key, ScrollArea, SidePanel, Ui};
 use fst::{
-    fst::{Fst, HierarchyScope, ScopeId, VarId},
-    valvec::ValAndTimeVec,
+    fst::{Fst, HierarchyScope, HierarchyVar, ScopeId, VarId, VarLength},
+    valvec::{Radix, ValAndTimeVec},
+    Waves,
+};
+use log::{error, info};
+
+use crate::{
+    cache::WaveCache,
+    waves::{auto_y_range, expand_bits, ViewState},
 };
-use log::info;
 
-pub fn show_scopes_panel(ctx: &Context, e: &mut Fst, selected_scope: &mut Option<ScopeId>) {
+pub fn show_scopes_panel(
+    ctx: &Context,
+    e: &mut Fst,
+    selected_scope: &mut Option<ScopeId>,
+    navigation: &mut ListNavigation,
+) {
     SidePanel::left("scopes_panel")
         .resizable(true)
         .show(ctx, |ui| {
@@ -17,58 +34,146 @@ pub fn show_scopes_panel(ctx: &Context, e: &mut Fst, selected_scope: &mut Option
 
             ui.separator();
 
+            // The scopes that aren't hidden in collapsed parents, in order.
+            let mut shown = Vec::new();
+
             // TODO: This will panic if there are no nodes.
             ScrollArea::vertical()
                 .auto_shrink([false, false])
                 .show(ui, |ui| {
-                    show_hierarchy(ui, &e.hierarchy, ScopeId(0), selected_scope);
+                    show_hierarchy(
+                        ui,
+                        e,
+                        ScopeId(0),
+                        selected_scope,
+                        navigation.take_scroll(),
+                        &mut shown,
+                    );
                 });
+
+            // The keyboard moves the selection.
+            navigation.focused = shown
+                .iter()
+                .position(|(id, _)| Some(*id) == *selected_scope);
+            let names: Vec<&str> = shown.iter().map(|(_, name)| name.as_str()).collect();
+            if navigation.handle_keys(ui, &names) == Some(NavigationKey::Moved) {
+                *selected_scope = navigation.focused.map(|index| shown[index].0);
+            }
         });
 }
 
 fn show_hierarchy(
     ui: &mut Ui,
-    hierarchy: &espalier::Tree<ScopeId, HierarchyScope>,
+    e: &Fst,
     node_id: ScopeId,
     selected_id: &mut Option<ScopeId>,
+    scroll_to_selected: bool,
+    shown: &mut Vec<(ScopeId, String)>,
 ) {
-    let node = match hierarchy.get(node_id) {
+    let node = match e.hierarchy.get(node_id) {
         Some(n) => n,
         None => return,
     };
 
     let selected = Some(node_id) == *selected_id;
+    shown.push((node_id, node.value.name.clone()));
 
     // This is necessary because otherwise it uses the node.value.name as the ID
     // and there can be duplicates.
     ui.push_id(node_id, |ui| {
-        if node.num_descendants() == 0 {
-            if ui.selectable_label(selected, &node.value.name).clicked() {
+        let mut show_label = |ui: &mut Ui| {
+            let response = ui.selectable_label(selected, node.value.display_name());
+            if response.clicked() {
                 *selected_id = Some(node_id);
             }
+            if selected && scroll_to_selected {
+                response.scroll_to_me(None);
+            }
+            response.context_menu(|ui| {
+                if ui.button("Copy path").clicked() {
+                    ui.close_menu();
+                    if let Some(path) = e.scope_path(node_id) {
+                        ui.output().copied_text = path;
+                    }
+                }
+            });
+        };
+
+        if node.num_descendants() == 0 {
+            show_label(ui);
         } else {
             let id = ui.make_persistent_id("scope_header");
             egui::collapsing_header::CollapsingState::load_with_default_open(ui.ctx(), id, true)
                 .show_header(ui, |ui| {
-                    if ui.selectable_label(selected, &node.value.name).clicked() {
-                        *selected_id = Some(node_id);
-                    }
+                    show_label(ui);
+                    // Including sub-scopes.
+                    ui.weak(e.scope_var_count_recursive(node_id).to_string());
                 })
                 .body(|ui| {
-                    for (child_id, _child) in hierarchy.children(node_id) {
-                        show_hierarchy(ui, hierarchy, child_id, selected_id);
+                    for (child_id, _child) in e.hierarchy.children(node_id) {
+                        show_hierarchy(ui, e, child_id, selected_id, scroll_to_selected, shown);
                     }
                 });
         }
     });
 }
 
+/// Which vars the vars panel shows.
+#[derive(Default)]
+pub struct VarsFilter {
+    /// Only show vars whose names contain this.
+    pub text: String,
+    /// Only show vars that change at least once.
+    pub only_changing: bool,
+    /// The number of changes of each var that has been counted so far. This
+    /// must be cleared when a different file is loaded.
+    pub change_counts: HashMap<VarId, usize>,
+}
+
+/// How long to spend counting changes each frame. Large scopes are counted
+/// over several frames so the UI stays responsive.
+const COUNT_TIME_PER_FRAME: Duration = Duration::from_millis(20);
+
+impl VarsFilter {
+    fn matches(&self, var: &HierarchyVar) -> bool {
+        var.name.contains(&self.text)
+            && (!self.only_changing
+                || self
+                    .change_counts
+                    .get(&var.id)
+                    .is_some_and(|&count| count > 0))
+    }
+
+    /// Count the changes of the vars in `scope` that haven't been counted
+    /// yet, for a limited time. Returns whether there are any left.
+    fn count_changes(&mut self, e: &Fst, scope: &HierarchyScope) -> bool {
+        let start = Instant::now();
+        for var in &scope.vars {
+            if self.change_counts.contains_key(&var.id) {
+                continue;
+            }
+            if start.elapsed() > COUNT_TIME_PER_FRAME {
+                return true;
+            }
+            let count = e.change_count(var.id).unwrap_or_else(|err| {
+                error!("Error counting changes of {}: {err:?}", var.name);
+                // Show it rather than hiding it because of the error.
+                1
+            });
+            self.change_counts.insert(var.id, count);
+        }
+        false
+    }
+}
+
 pub fn show_vars_panel(
     ctx: &Context,
     e: &mut Fst,
     selected_scope: &Option<ScopeId>,
-    vars_filter: &mut String,
-    cached_waves: &mut HashMap<VarId, ValAndTimeVec>,
+    vars_filter: &mut VarsFilter,
+    cache: &mut WaveCache,
+    view: &mut ViewState,
+    navigation: &mut ListNavigation,
 ) {
     SidePanel::left("vars_panel")
         .resizable(true)
@@ -77,38 +182,451 @@ pub fn show_vars_panel(
                 ui.heading("Vars");
             });
 
-            ui.text_edit_singleline(vars_filter);
+            ui.text_edit_singleline(&mut vars_filter.text);
+            ui.checkbox(&mut vars_filter.only_changing, "Only changing vars");
+
+            if vars_filter.only_changing {
+                let scope = selected_scope.and_then(|scope| e.hierarchy.get(scope));
+                if let Some(scope) = scope {
+                    if vars_filter.count_changes(e, &scope.value) {
+                        ui.horizontal(|ui| {
+                            ui.spinner();
+                            ui.label("Counting changes...");
+                        });
+                        ctx.request_repaint();
+                    }
+                }
+            }
 
             ui.separator();
 
-            ScrollArea::vertical()
+            // The vars that match the filter, in order.
+            let mut shown = Vec::new();
+
+            let action = ScrollArea::vertical()
                 .auto_shrink([false, false])
                 .show(ui, |ui| {
-                    if let Some(selected_scope) = selected_scope {
-                        if let Some(scope) = e.hierarchy.get(*selected_scope) {
-                            let append_var = show_vars(ui, &scope.value, vars_filter.as_str());
-
-                            if let Some(varid) = append_var {
-                                info!("Reading wave {:?}", varid);
-                                // TODO: Do in another thread.
-                                if let Ok(w) = e.read_wave(varid) {
-                                    cached_waves.insert(varid, w);
+                    let scope = selected_scope.and_then(|scope| e.hierarchy.get(scope))?;
+                    show_vars(
+                        ui,
+                        &scope.value,
+                        vars_filter,
+                        &cache.shown,
+                        view,
+                        navigation,
+                        &mut shown,
+                    )
+                })
+                .inner;
+
+            let names: Vec<&str> = shown.iter().map(|(_, name)| name.as_str()).collect();
+            let action = match navigation.handle_keys(ui, &names) {
+                Some(NavigationKey::Activate) => navigation
+                    .focused
+                    .map(|index| shown[index].0)
+                    .filter(|varid| !cache.shown.contains_key(varid))
+                    .map(VarAction::Add),
+                _ => action,
+            };
+
+            match action {
+                Some(VarAction::Add(varid)) => {
+                    info!("Reading wave {:?}", varid);
+                    // TODO: Do in another thread.
+                    if let Err(err) = cache.show(varid, || e.read_wave(varid)) {
+                        error!("Error reading wave {varid:?}: {err:?}");
+                    }
+                    if e.variable_info(varid).is_ok_and(|info| info.is_signed()) {
+                        view.signed.insert(varid);
+                    }
+                }
+                Some(VarAction::Remove(varid)) => {
+                    cache.hide(varid);
+                    view.pinned.remove(&varid);
+                    view.expanded.remove(&varid);
+                    view.signed.remove(&varid);
+                    view.radices.remove(&varid);
+                    view.analog.remove(&varid);
+                    view.y_ranges.remove(&varid);
+                }
+                Some(VarAction::TogglePin(varid)) => {
+                    let was_pinned = view.pinned.remove(&varid);
+                    if !was_pinned {
+                        view.pinned.insert(varid);
+                    }
+                }
+                Some(VarAction::ToggleSigned(varid)) => {
+                    let was_signed = view.signed.remove(&varid);
+                    if !was_signed {
+                        view.signed.insert(varid);
+                    }
+                }
+                Some(VarAction::SetRadices(varid, radices)) => {
+                    view.radices.insert(varid, radices);
+                }
+                Some(VarAction::ToggleAnalog(varid)) => {
+                    let was_analog = view.analog.remove(&varid);
+                    if !was_analog {
+                        view.analog.insert(varid);
+                    }
+                }
+                Some(VarAction::EditYRange(varid)) => {
+                    // Start from the range it is drawn with over the whole
+                    // file if the user hasn't set one.
+                    let (min, max) = view.y_ranges.get(&varid).copied().unwrap_or_else(|| {
+                        cache
+                            .shown
+                            .get(&varid)
+                            .map_or((0.0, 1.0), |wave| auto_y_range(wave, 0..u64::MAX))
+                    });
+                    view.editing_y_range = Some((varid, min, max));
+                }
+                Some(VarAction::ToggleExpand(varid)) => {
+                    let was_expanded = view.expanded.remove(&varid).is_some();
+                    if let (false, VarLength::Bits(bits), Some(wave)) = (
+                        was_expanded,
+                        e.var_lengths.length(varid),
+                        cache.shown.get(&varid),
+                    ) {
+                        view.expanded.insert(varid, expand_bits(wave, bits));
+                    }
+                }
+                Some(VarAction::Export(varid, name)) => {
+                    if let Err(err) = export_var(e, varid, &name) {
+                        error!("Error exporting {name}: {err:?}");
+                    }
+                }
+                Some(VarAction::CopyPath(name)) => {
+                    if let Some(path) = selected_scope.and_then(|scope| e.var_path(scope, &name)) {
+                        ui.output().copied_text = path;
+                    }
+                }
+                None => {}
+            }
+        });
+}
+
+/// The radices that buses can be shown in, in menu order.
+const RADICES: [Radix; 5] = [Radix::Hex, Radix::Dec, Radix::SDec, Radix::Oct, Radix::Bin];
+
+enum VarAction {
+    /// Show the var in the waves view.
+    Add(VarId),
+    /// Stop showing the var.
+    Remove(VarId),
+    /// Pin or unpin the var at the top of the waves view.
+    TogglePin(VarId),
+    /// Show or hide a row for each bit of a bus.
+    ToggleExpand(VarId),
+    /// Treat a bus as signed or unsigned.
+    ToggleSigned(VarId),
+    /// Set the radix of each row of a bus. See `ViewState::radices`.
+    SetRadices(VarId, Vec<Radix>),
+    /// Draw a real as a line graph or as values.
+    ToggleAnalog(VarId),
+    /// Open the window for setting the range of an analog real.
+    EditYRange(VarId),
+    /// Save its changes to a file. Also has the var name.
+    Export(VarId, String),
+    /// Copy the dotted path of the var with this name in the selected scope
+    /// to the clipboard.
+    CopyPath(String),
+}
+
+fn show_vars(
+    ui: &mut Ui,
+    scope: &HierarchyScope,
+    filter: &VarsFilter,
+    cached_waves: &HashMap<VarId, ValAndTimeVec>,
+    view: &ViewState,
+    navigation: &mut ListNavigation,
+    shown: &mut Vec<(VarId, String)>,
+) -> Option<VarAction> {
+    let scroll_to_focused = navigation.take_scroll();
+    let mut action = None;
+    for var in scope.vars.iter() {
+        if filter.matches(var) {
+            let focused = navigation.focused == Some(shown.len());
+            shown.push((var.id, var.name.clone()));
+
+            // The checkbox shows whether the var is in the waves view.
+            let mut is_shown = cached_waves.contains_key(&var.id);
+            let mut response = ui.checkbox(&mut is_shown, var.display_name());
+            if let Some(source) = &var.source {
+                response = response.on_hover_text(format!("{}:{}", source.path, source.line));
+            }
+            if response.changed() {
+                action = Some(if is_shown {
+                    VarAction::Add(var.id)
+                } else {
+                    VarAction::Remove(var.id)
+                });
+            }
+            if focused {
+                ui.painter().rect_stroke(
+                    response.rect.expand(1.0),
+                    2.0,
+                    ui.visuals().selection.stroke,
+                );
+                if scroll_to_focused {
+                    response.scroll_to_me(None);
+                }
+            }
+            response.context_menu(|ui| {
+                if ui.button("Copy path").clicked() {
+                    ui.close_menu();
+                    action = Some(VarAction::CopyPath(var.name.clone()));
+                }
+                // Vars that are shown can be pinned and exported, and buses
+                // expanded.
+                if cached_waves.contains_key(&var.id) {
+                    ui.separator();
+                    let pin_label = if view.pinned.contains(&var.id) {
+                        "Unpin"
+                    } else {
+                        "Pin"
+                    };
+                    if ui.button(pin_label).clicked() {
+                        ui.close_menu();
+                        action = Some(VarAction::TogglePin(var.id));
+                    }
+                    if var.length > 1 && !var.is_real() {
+                        let expand_label = if view.expanded.contains_key(&var.id) {
+                            "Collapse bits"
+                        } else {
+                            "Expand bits"
+                        };
+                        if ui.button(expand_label).clicked() {
+                            ui.close_menu();
+                            action = Some(VarAction::ToggleExpand(var.id));
+                        }
+                        let signed_label = if view.signed.contains(&var.id) {
+                            "Show as unsigned"
+                        } else {
+                            "Show as signed"
+                        };
+                        if ui.button(signed_label).clicked() {
+                            ui.close_menu();
+                            action = Some(VarAction::ToggleSigned(var.id));
+                        }
+                        let radices = view
+                            .radices
+                            .get(&var.id)
+                            .cloned()
+                            .unwrap_or_else(|| vec![view.radix(var.id, 0)]);
+                        ui.menu_button("Radix", |ui| {
+                            for radix in RADICES {
+                                if ui.radio(radices[0] == radix, radix.to_string()).clicked() {
+                                    ui.close_menu();
+                                    let mut radices = radices.clone();
+                                    radices[0] = radix;
+                                    action = Some(VarAction::SetRadices(var.id, radices));
+                                }
+                            }
+                        });
+                        // The same bus can be shown in other radices too, in
+                        // extra rows.
+                        ui.menu_button("Add row in", |ui| {
+                            for radix in RADICES.into_iter().filter(|r| !radices.contains(r)) {
+                                if ui.button(radix.to_string()).clicked() {
+                                    ui.close_menu();
+                                    let mut radices = radices.clone();
+                                    radices.push(radix);
+                                    action = Some(VarAction::SetRadices(var.id, radices));
                                 }
                             }
+                        });
+                        if radices.len() > 1 && ui.button("Remove extra rows").clicked() {
+                            ui.close_menu();
+                            action = Some(VarAction::SetRadices(var.id, radices[..1].to_vec()));
                         }
                     }
-                });
-        });
+                    if var.is_real() {
+                        let analog = view.analog.contains(&var.id);
+                        let analog_label = if analog {
+                            "Show as values"
+                        } else {
+                            "Show as analog"
+                        };
+                        if ui.button(analog_label).clicked() {
+                            ui.close_menu();
+                            action = Some(VarAction::ToggleAnalog(var.id));
+                        }
+                        if analog && ui.button("Set Y range...").clicked() {
+                            ui.close_menu();
+                            action = Some(VarAction::EditYRange(var.id));
+                        }
+                    }
+                    if ui.button("Export...").clicked() {
+                        ui.close_menu();
+                        action = Some(VarAction::Export(var.id, var.name.clone()));
+                    }
+                }
+            });
+        }
+    }
+    action
 }
 
-fn show_vars(ui: &mut Ui, scope: &HierarchyScope, filter: &str) -> Option<VarId> {
-    let mut add_var = None;
-    for var in scope.vars.iter() {
-        if var.name.contains(filter) {
-            if ui.selectable_label(false, &var.name).double_clicked() {
-                add_var = Some(var.id);
+/// How long after the last character typed a type-ahead search starts again.
+const TYPE_AHEAD_TIMEOUT: f64 = 1.0;
+
+/// Keyboard navigation of a list in a panel. Up and down move the focus,
+/// Enter activates the focused item, and typing jumps to the first item
+/// whose name starts with what was typed.
+#[derive(Default)]
+pub struct ListNavigation {
+    /// Index of the focused item.
+    focused: Option<usize>,
+    /// The focus moved, so scroll to it the next time the list is shown.
+    scroll: bool,
+    /// What has been typed so far.
+    typed: String,
+    /// When the last character was typed, from `InputState::time`.
+    last_typed: f64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum NavigationKey {
+    Moved,
+    Activate,
+}
+
+impl ListNavigation {
+    /// Handle this frame's key presses for the items with `names`, as long as
+    /// the pointer is over the panel and no other widget (e.g. the filter)
+    /// has keyboard focus.
+    fn handle_keys(&mut self, ui: &Ui, names: &[&str]) -> Option<NavigationKey> {
+        if names.is_empty() || !ui.ui_contains_pointer() || ui.memory().focus().is_some() {
+            return None;
+        }
+        let last = names.len() - 1;
+        self.focused = self.focused.map(|index| index.min(last));
+
+        let mut key = None;
+        {
+            let input = ui.input();
+            if input.key_pressed(Key::ArrowDown) {
+                self.focused = Some(self.focused.map_or(0, |index| (index + 1).min(last)));
+                key = Some(NavigationKey::Moved);
             }
+            if input.key_pressed(Key::ArrowUp) {
+                self.focused = Some(self.focused.map_or(0, |index| index.saturating_sub(1)));
+                key = Some(NavigationKey::Moved);
+            }
+            if input.key_pressed(Key::Enter) && self.focused.is_some() {
+                key = Some(NavigationKey::Activate);
+            }
+            for event in &input.events {
+                if let Event::Text(text) = event {
+                    if input.time - self.last_typed > TYPE_AHEAD_TIMEOUT {
+                        self.typed.clear();
+                    }
+                    self.last_typed = input.time;
+                    self.typed.push_str(&text.to_lowercase());
+                    if let Some(index) = find_prefix(names, &self.typed) {
+                        self.focused = Some(index);
+                        key = Some(NavigationKey::Moved);
+                    }
+                }
+            }
+        }
+
+        if key == Some(NavigationKey::Moved) {
+            self.scroll = true;
+            ui.ctx().request_repaint();
         }
+        key
+    }
+
+    /// Whether to scroll to the focused item, which is only done once.
+    fn take_scroll(&mut self) -> bool {
+        std::mem::take(&mut self.scroll)
+    }
+}
+
+/// The index of the first name that starts with `prefix`, ignoring case.
+/// `prefix` must be lower case.
+fn find_prefix(names: &[&str], prefix: &str) -> Option<usize> {
+    names
+        .iter()
+        .position(|name| name.to_lowercase().starts_with(prefix))
+}
+
+/// Ask where to save a var's changes, and save them as CSV or VCD depending
+/// on the extension.
+fn export_var(e: &mut Fst, varid: VarId, name: &str) -> Result<()> {
+    // Drop the bit range and anything that isn't safe in a file name.
+    let file_name: String = name
+        .split(' ')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    let Some(path) = rfd::FileDialog::new()
+        .add_filter("CSV", &["csv"])
+        .add_filter("VCD", &["vcd"])
+        .set_file_name(&format!("{file_name}.csv"))
+        .save_file()
+    else {
+        return Ok(());
+    };
+
+    let is_vcd = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("vcd"));
+
+    let mut out = BufWriter::new(File::create(&path)?);
+    if is_vcd {
+        e.write_vcd(&mut out, &[varid])?;
+    } else {
+        e.export_csv(&mut out, &[varid])?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_vars_filter() {
+        let var = |id, name: &str| HierarchyVar {
+            name: name.to_string(),
+            id: VarId(id),
+            ..Default::default()
+        };
+        let mut filter = VarsFilter {
+            text: "cl".to_string(),
+            ..Default::default()
+        };
+        assert!(filter.matches(&var(0, "clk")));
+        assert!(!filter.matches(&var(1, "data")));
+
+        filter.only_changing = true;
+        filter.change_counts.insert(VarId(0), 3);
+        filter.change_counts.insert(VarId(2), 0);
+        assert!(filter.matches(&var(0, "clk")));
+        // Constant, and not counted yet.
+        assert!(!filter.matches(&var(2, "clk_en")));
+        assert!(!filter.matches(&var(3, "clk_div")));
+    }
+
+    #[test]
+    fn test_find_prefix() {
+        let names = ["clk", "Data_in", "data_out", "rst"];
+        assert_eq!(find_prefix(&names, "d"), Some(1));
+        assert_eq!(find_prefix(&names, "data_o"), Some(2));
+        assert_eq!(find_prefix(&names, "r"), Some(3));
+        assert_eq!(find_prefix(&names, "x"), None);
     }
-    add_var
 }
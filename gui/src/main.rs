@@ -1,7 +1,6 @@
 use std::{
-    collections::HashMap,
     ops::Range,
-    path::Path,
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicI32, Ordering},
         Arc, Mutex,
@@ -11,20 +10,28 @@ use std::{
 
 use eframe::egui;
 
-use egui::{menu, CentralPanel, TopBottomPanel};
-use fst::{
-    fst::{Fst, ScopeId, VarId},
-    valvec::ValAndTimeVec,
-};
+use egui::{menu, CentralPanel, Key, TopBottomPanel};
+use fst::fst::{Fst, ScopeId};
 
-use hierarchy::{show_scopes_panel, show_vars_panel};
+use hierarchy::{show_scopes_panel, show_vars_panel, ListNavigation, VarsFilter};
 
+mod cache;
+mod debug;
 mod decoder;
+mod groups;
 mod hierarchy;
+mod stats;
 mod waves;
 
 use anyhow::Result;
-use waves::show_waves_widget;
+use cache::{show_cache_menu, WaveCache};
+use debug::{show_debug_overlay, FrameStats};
+use groups::{find_vars, save_paths, show_groups_menu};
+use stats::show_stats_panel;
+use waves::{
+    clamp_timespan, show_markers_menu, show_waves_widget, show_y_range_window, SavedView,
+    ViewState, WaveStyle, MIN_TIMESPAN,
+};
 
 fn main() {
     let native_options = eframe::NativeOptions::default();
@@ -77,7 +84,14 @@ impl FstLoader {
                 update_callback();
                 *cancelled_thread.lock().unwrap()
             };
-            let fst = Fst::load(&filename);
+            let is_vcd = filename
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("vcd"));
+            let fst = if is_vcd {
+                fst::vcd::load(&filename)
+            } else {
+                Fst::load(&filename)
+            };
             *loaded_file_thread.lock().unwrap() = Some(fst);
             cancel_progress_callback(100);
         });
@@ -109,26 +123,52 @@ impl FstLoader {
 struct MainApp {
     // The file (or in-progress loading of said file).
     file: FileState,
+    /// The path of the file, for reloading it.
+    path: Option<PathBuf>,
+    /// The view to restore once the file has been reloaded.
+    reloaded_view: Option<SavedView>,
     // Waves that we have loaded.
-    cached_waves: HashMap<VarId, ValAndTimeVec>,
+    cache: WaveCache,
+    view: ViewState,
+    show_stats_panel: bool,
     // backend_panel: BackendPanel,
     selected_scope: Option<ScopeId>,
     /// The filter for the vars panel.
-    vars_filter: String,
+    vars_filter: VarsFilter,
+    /// Keyboard navigation state for the scopes and vars panels.
+    scopes_navigation: ListNavigation,
+    vars_navigation: ListNavigation,
     // Bit of a hack, but if this is Some(foo) then foo was passed on the
     // command line and we should load that.
     pending_file_load: Option<String>,
     // Currently shown time span in the waves view.
     timespan: Range<f64>,
+    /// Named time markers, e.g. "reset deasserted". These are saved between runs.
+    markers: Vec<(u64, String)>,
+    wave_style: WaveStyle,
+    /// Show frame timings and drawing stats over the waves.
+    show_debug_overlay: bool,
 }
 
 impl MainApp {
-    fn new(_cc: &eframe::CreationContext<'_>) -> Self {
+    fn new(cc: &eframe::CreationContext<'_>) -> Self {
         // Customize egui here with cc.egui_ctx.set_fonts and cc.egui_ctx.set_visuals.
-        // Restore app state using cc.storage (requires the "persistence" feature).
         // Use the cc.gl (a glow::Context) to create graphics shaders and buffers that you can use
         // for e.g. egui::PaintCallback.
-        let mut app = Self::default();
+        let mut app = Self {
+            // Not empty, so that nothing divides by zero before a file is loaded.
+            timespan: 0.0..MIN_TIMESPAN,
+            ..Default::default()
+        };
+        // Restore the session.
+        if let Some(storage) = cc.storage {
+            app.markers = eframe::get_value(storage, MARKERS_KEY).unwrap_or_default();
+            app.wave_style = eframe::get_value(storage, WAVE_STYLE_KEY).unwrap_or_default();
+            app.view.groups = eframe::get_value(storage, GROUPS_KEY).unwrap_or_default();
+            app.cache.max_removed = eframe::get_value(storage, CACHE_SIZE_KEY).unwrap_or_default();
+            app.cache.block_cache_mb =
+                eframe::get_value(storage, BLOCK_CACHE_KEY).unwrap_or_default();
+        }
         // Load files from command line.
         let args: Vec<String> = std::env::args().skip(1).collect();
         if args.len() == 1 {
@@ -143,12 +183,40 @@ impl MainApp {
             ctx2.request_repaint();
         });
 
+        // Groups are found in the new file by path.
+        if let FileState::Loaded(fst) = &self.file {
+            save_paths(&mut self.view.groups, fst);
+        }
         self.file = FileState::Loading(FstLoader::new(path, update));
+        self.path = Some(path.to_owned());
     }
 }
 
+const MARKERS_KEY: &str = "markers";
+const WAVE_STYLE_KEY: &str = "wave_style";
+const GROUPS_KEY: &str = "groups";
+const CACHE_SIZE_KEY: &str = "cache_size";
+const BLOCK_CACHE_KEY: &str = "block_cache_mb";
+
 impl eframe::App for MainApp {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, MARKERS_KEY, &self.markers);
+        eframe::set_value(storage, WAVE_STYLE_KEY, &self.wave_style);
+        if let FileState::Loaded(fst) = &self.file {
+            save_paths(&mut self.view.groups, fst);
+        }
+        eframe::set_value(storage, GROUPS_KEY, &self.view.groups);
+        eframe::set_value(storage, CACHE_SIZE_KEY, &self.cache.max_removed);
+        eframe::set_value(storage, BLOCK_CACHE_KEY, &self.cache.block_cache_mb);
+    }
+
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        ctx.tessellation_options().feathering = self.wave_style.anti_aliasing;
+
+        if ctx.input().key_pressed(Key::F11) {
+            self.show_debug_overlay = !self.show_debug_overlay;
+        }
+
         // Handle pending command line arguments.
         let pending_file_load = self.pending_file_load.take();
         if let Some(pending_file_load) = pending_file_load {
@@ -173,8 +241,21 @@ impl eframe::App for MainApp {
         };
         if let Some(new_file) = new_file {
             self.file = new_file;
+            let reloaded_view = self.reloaded_view.take();
             if let FileState::Loaded(fst) = &self.file {
-                self.timespan = fst.header.start_time as f64..fst.header.end_time as f64;
+                let full_range = fst.header.start_time as f64..fst.header.end_time as f64;
+                self.timespan = clamp_timespan(full_range.clone(), full_range);
+                self.vars_filter.change_counts.clear();
+                // The old waves are keyed by the old file's var IDs.
+                self.cache.clear();
+                self.view.forget_vars();
+                find_vars(&mut self.view.groups, fst);
+                // Show the same vars again if it was reloaded. They are
+                // matched by path since their IDs may have changed.
+                if let Some(saved) = reloaded_view {
+                    saved.restore(fst, &mut self.cache, &mut self.view, &mut self.timespan);
+                }
+                self.cache.start_prefetch(fst);
             }
         }
 
@@ -184,13 +265,58 @@ impl eframe::App for MainApp {
                     if ui.button("Open...").clicked() {
                         ui.close_menu();
                         if let Some(path) = rfd::FileDialog::new()
-                            .add_filter("FST", &["fst"])
+                            .add_filter("Waves", &["fst", "gz", "vcd"])
+                            .add_filter("FST", &["fst", "gz"])
+                            .add_filter("VCD", &["vcd"])
                             .pick_file()
                         {
                             self.load_file(&path, ctx);
                             frame.set_window_title(&format!("Wavery - {}", path.display()));
                         }
                     }
+                    // Load the file again, e.g. after rerunning the
+                    // simulation, keeping what is shown.
+                    let can_reload =
+                        self.path.is_some() && !matches!(self.file, FileState::Loading(_));
+                    if ui
+                        .add_enabled(can_reload, egui::Button::new("Reload"))
+                        .clicked()
+                    {
+                        ui.close_menu();
+                        if let FileState::Loaded(fst) = &self.file {
+                            self.reloaded_view = Some(SavedView::save(
+                                fst,
+                                &self.cache,
+                                &self.view,
+                                &self.timespan,
+                            ));
+                        }
+                        if let Some(path) = self.path.clone() {
+                            self.load_file(&path, ctx);
+                        }
+                    }
+                });
+                ui.menu_button("View", |ui| {
+                    ui.add(
+                        egui::Slider::new(&mut self.wave_style.line_thickness, 0.5..=4.0)
+                            .text("Line thickness"),
+                    );
+                    ui.checkbox(&mut self.wave_style.anti_aliasing, "Anti-aliasing");
+                    ui.checkbox(
+                        &mut self.wave_style.show_changed_bits,
+                        "Show changed bit counts",
+                    );
+                    ui.checkbox(&mut self.show_stats_panel, "Signal statistics");
+                    ui.checkbox(&mut self.show_debug_overlay, "Debug overlay (F11)");
+                });
+                ui.menu_button("Markers", |ui| {
+                    show_markers_menu(ui, &mut self.markers);
+                });
+                ui.menu_button("Groups", |ui| {
+                    show_groups_menu(ui, &mut self.view.groups);
+                });
+                ui.menu_button("Cache", |ui| {
+                    show_cache_menu(ui, &mut self.cache);
                 });
             });
         });
@@ -201,17 +327,47 @@ impl eframe::App for MainApp {
                 });
             }
             FileState::Loaded(e) => {
-                show_scopes_panel(ctx, e, &mut self.selected_scope);
+                e.set_block_cache_size(self.cache.block_cache_bytes());
+                show_scopes_panel(
+                    ctx,
+                    e,
+                    &mut self.selected_scope,
+                    &mut self.scopes_navigation,
+                );
                 show_vars_panel(
                     ctx,
                     e,
                     &self.selected_scope,
                     &mut self.vars_filter,
-                    &mut self.cached_waves,
+                    &mut self.cache,
+                    &mut self.view,
+                    &mut self.vars_navigation,
                 );
+                if self.show_stats_panel {
+                    show_stats_panel(ctx, e, &self.cache.shown, &self.view, &self.timespan);
+                }
                 CentralPanel::default().show(ctx, |ui| {
-                    show_waves_widget(ui, e, &self.cached_waves, self.timespan.clone());
+                    show_waves_widget(
+                        ui,
+                        e,
+                        &self.cache,
+                        &mut self.view,
+                        &mut self.timespan,
+                        &mut self.markers,
+                        &self.wave_style,
+                    );
                 });
+                show_y_range_window(ctx, &mut self.view);
+                if self.show_debug_overlay {
+                    let stats = FrameStats {
+                        frame_time: ctx.input().unstable_dt,
+                        cpu_time: frame.info().cpu_usage,
+                        shapes: self.view.shapes_drawn,
+                        cached_waves: self.cache.len(),
+                    };
+                    show_debug_overlay(ctx, &stats);
+                }
+                self.cache.end_frame();
             }
             FileState::Error(e) => {
                 CentralPanel::default().show(ctx, |ui| {
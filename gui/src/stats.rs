@@ -0,0 +1,60 @@
+use std::{collections::HashMap, ops::Range};
+
+use egui::{Context, TopBottomPanel};
+use fst::{
+    fst::{wave_stats, Fst, VarId, VarLength},
+    valvec::{Radix, ValAndTimeVec, Value},
+    Waves,
+};
+
+use crate::waves::{format_duration, ViewState};
+
+/// A panel at the bottom showing statistics about the selected wave over the
+/// visible time span.
+pub fn show_stats_panel(
+    ctx: &Context,
+    e: &Fst,
+    cached_waves: &HashMap<VarId, ValAndTimeVec>,
+    view: &ViewState,
+    timespan: &Range<f64>,
+) {
+    TopBottomPanel::bottom("stats_panel").show(ctx, |ui| {
+        let Some((varid, wave)) = view
+            .selected
+            .and_then(|varid| Some((varid, cached_waves.get(&varid)?)))
+        else {
+            ui.label("Click a wave to see its statistics.");
+            return;
+        };
+
+        let var_length = e.var_lengths.length(varid);
+        let range = timespan.start.max(0.0).floor() as u64..timespan.end.max(0.0).ceil() as u64;
+        let signed = view.signed.contains(&varid);
+        let stats = wave_stats(wave, var_length, signed, range);
+        // Signed buses are shown in decimal so the sign is visible.
+        let format = |value: &Value| match var_length {
+            VarLength::Bits(bits) if signed => value.format_radix(bits, Radix::SDec),
+            _ => value.format(var_length),
+        };
+
+        ui.horizontal(|ui| {
+            if let Ok(info) = e.variable_info(varid) {
+                ui.strong(info.name);
+                ui.label(format!("({})", info.length));
+            }
+            ui.label(format!("Transitions: {}", stats.transitions));
+            if let (Some(min), Some(max)) = (&stats.min, &stats.max) {
+                ui.label(format!("Min: {}  Max: {}", format(min), format(max)));
+            }
+            if let Some(high) = stats.high_fraction {
+                ui.label(format!("High: {:.1}%", high * 100.0));
+            }
+            if let Some(period) = stats.period {
+                ui.label(format!(
+                    "Period: {}",
+                    format_duration(period, e.header.timescale)
+                ));
+            }
+        });
+    });
+}
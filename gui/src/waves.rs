@@ -1,24 +1,363 @@
-use std::{collections::HashMap, ops::Range};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::{Range, RangeInclusive},
+};
 
-use eframe::{
-    emath::{self, RectTransform},
-    epaint,
+use crate::{
+    cache::WaveCache,
+    groups::{find_vars, move_to_group, save_paths, Group},
 };
+use eframe::{emath, epaint};
 use egui::{
-    pos2, vec2, Align2, Color32, FontFamily, FontId, Frame, Pos2, Rect, Response, Shape, Stroke,
-    Ui, Vec2,
+    pos2, vec2, Align2, Color32, Context, FontFamily, FontId, Frame, Painter, Pos2, Rect, Response,
+    Shape, Stroke, Ui,
 };
 use fst::{
-    fst::{Fst, VarId, VarLength},
-    valvec::ValAndTimeVec,
+    fst::{wave_stats, Fst, VarId, VarLength},
+    valvec::{DetectPeriod, IntoTransitions, Radix, ValAndTimeVec, Value},
+    Waves,
 };
+use log::{error, info};
+
+/// User adjustable drawing settings. These are saved between runs.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct WaveStyle {
+    pub line_thickness: f32,
+    pub anti_aliasing: bool,
+    /// Show how many bits changed at each transition of a bus.
+    pub show_changed_bits: bool,
+}
+
+impl Default for WaveStyle {
+    fn default() -> Self {
+        Self {
+            line_thickness: 1.0,
+            anti_aliasing: true,
+            show_changed_bits: false,
+        }
+    }
+}
+
+/// Clocks with fewer pixels than this per period are drawn as a band.
+const MIN_PIXELS_PER_CLOCK: f32 = 2.0 * MIN_PIXELS_PER_EDGE;
+
+/// Height of each wave's row in pixels.
+const ROW_HEIGHT: f32 = 24.0;
+
+/// Row height as a multiple of the wave height.
+const LINE_SPACING: f32 = 1.4;
+
+/// Per-var display state of the waves view.
+#[derive(Default)]
+pub struct ViewState {
+    /// Shown vars that stay at the top of the waves view.
+    pub pinned: HashSet<VarId>,
+    /// The wave that was last clicked, e.g. for the statistics panel.
+    pub selected: Option<VarId>,
+    /// Buses that have a row for each bit under them, with the wave and clock
+    /// period of each bit from `expand_bits()`.
+    pub expanded: HashMap<VarId, Vec<(ValAndTimeVec, Option<u64>)>>,
+    /// Buses whose values are two's complement signed. Vars that are
+    /// declared signed are added when they are shown, and users can toggle
+    /// it for others.
+    pub signed: HashSet<VarId>,
+    /// The radices that the values of each bus are shown in. Buses with more
+    /// than one have a row for each, e.g. to see hex and decimal at once.
+    /// Buses that aren't in here are shown in hex.
+    pub radices: HashMap<VarId, Vec<Radix>>,
+    /// Reals that are drawn as a line graph instead of as values.
+    pub analog: HashSet<VarId>,
+    /// The values at the bottom and top of the rows of analog reals that the
+    /// user has set. Ones that aren't in here are scaled to fit their values
+    /// in the visible time range.
+    pub y_ranges: HashMap<VarId, (f64, f64)>,
+    /// The real and range being edited in the "Set Y range" window.
+    pub editing_y_range: Option<(VarId, f64, f64)>,
+    /// Named groups of vars that are drawn together.
+    pub groups: Vec<Group>,
+    /// The var whose row is being dragged onto a group.
+    dragging: Option<VarId>,
+    /// The time of the primary cursor, which is placed by clicking the
+    /// ruler.
+    pub cursor: Option<u64>,
+    /// The time of the secondary cursor, which is placed by right-clicking
+    /// the ruler. The time between the two is shown.
+    pub secondary_cursor: Option<u64>,
+    /// How many shapes the waves were drawn with last frame, for the debug
+    /// overlay.
+    pub shapes_drawn: usize,
+}
+
+impl ViewState {
+    /// Forget the state of each var, e.g. because a different file was
+    /// loaded. Groups are kept, but not their vars' IDs. See `find_vars()`.
+    pub fn forget_vars(&mut self) {
+        for group in &mut self.groups {
+            group.vars.clear();
+        }
+        self.pinned.clear();
+        self.selected = None;
+        self.expanded.clear();
+        self.signed.clear();
+        self.radices.clear();
+        self.analog.clear();
+        self.y_ranges.clear();
+        self.editing_y_range = None;
+        self.dragging = None;
+    }
+
+    /// The radix of the `index`th row of a var. See `radices`.
+    pub fn radix(&self, varid: VarId, index: usize) -> Radix {
+        self.radices
+            .get(&varid)
+            .and_then(|radices| radices.get(index))
+            .copied()
+            .unwrap_or(Radix::Hex)
+    }
+
+    /// The radix that the values in the `index`th row of a var are labelled
+    /// in. This is `radix()`, except that decimal is signed for `signed`
+    /// buses.
+    pub fn label_radix(&self, varid: VarId, index: usize) -> Radix {
+        match self.radix(varid, index) {
+            Radix::Dec if self.signed.contains(&varid) => Radix::SDec,
+            radix => radix,
+        }
+    }
+}
+
+/// The waves view of a file, with vars by path instead of `VarId`, so it can
+/// be restored after the file is reloaded even if the design changed.
+pub struct SavedView {
+    vars: Vec<SavedVar>,
+    /// The groups, with their vars by path.
+    groups: Vec<Group>,
+    timespan: Range<f64>,
+    cursor: Option<u64>,
+    secondary_cursor: Option<u64>,
+}
+
+/// A shown var and its state in `ViewState`.
+struct SavedVar {
+    path: String,
+    pinned: bool,
+    selected: bool,
+    expanded: bool,
+    signed: bool,
+    radices: Option<Vec<Radix>>,
+    analog: bool,
+    y_range: Option<(f64, f64)>,
+}
+
+impl SavedView {
+    /// Vars that don't have a path (e.g. because the file has no hierarchy)
+    /// are left out.
+    pub fn save(file: &Fst, cache: &WaveCache, view: &ViewState, timespan: &Range<f64>) -> Self {
+        let vars = cache
+            .shown
+            .keys()
+            .filter_map(|&varid| {
+                Some(SavedVar {
+                    path: file.var_path_by_id(varid)?,
+                    pinned: view.pinned.contains(&varid),
+                    selected: view.selected == Some(varid),
+                    expanded: view.expanded.contains_key(&varid),
+                    signed: view.signed.contains(&varid),
+                    radices: view.radices.get(&varid).cloned(),
+                    analog: view.analog.contains(&varid),
+                    y_range: view.y_ranges.get(&varid).copied(),
+                })
+            })
+            .collect();
+        let mut groups = view.groups.clone();
+        save_paths(&mut groups, file);
+        Self {
+            vars,
+            groups,
+            timespan: timespan.clone(),
+            cursor: view.cursor,
+            secondary_cursor: view.secondary_cursor,
+        }
+    }
+
+    /// Show the saved vars that are still in `file`, reading their waves
+    /// again, and put the ones that are still there back in their groups.
+    /// The cache and view should have been cleared for the new file.
+    pub fn restore(
+        mut self,
+        file: &Fst,
+        cache: &mut WaveCache,
+        view: &mut ViewState,
+        timespan: &mut Range<f64>,
+    ) {
+        for var in self.vars {
+            let Some(varid) = file.var_by_path(&var.path) else {
+                info!("{} is no longer in the file", var.path);
+                continue;
+            };
+            if let Err(err) = cache.show(varid, || file.read_wave(varid)) {
+                error!("Error reading wave {}: {err:?}", var.path);
+                continue;
+            }
+            if var.pinned {
+                view.pinned.insert(varid);
+            }
+            if var.selected {
+                view.selected = Some(varid);
+            }
+            if let (true, VarLength::Bits(bits)) = (var.expanded, file.var_lengths.length(varid)) {
+                view.expanded
+                    .insert(varid, expand_bits(&cache.shown[&varid], bits));
+            }
+            if var.signed {
+                view.signed.insert(varid);
+            }
+            if let Some(radices) = var.radices {
+                view.radices.insert(varid, radices);
+            }
+            if var.analog {
+                view.analog.insert(varid);
+            }
+            if let Some(y_range) = var.y_range {
+                view.y_ranges.insert(varid, y_range);
+            }
+        }
+        find_vars(&mut self.groups, file);
+        view.groups = self.groups;
+        let full_range = file.header.start_time as f64..file.header.end_time as f64;
+        *timespan = clamp_timespan(self.timespan, full_range);
+        view.cursor = self.cursor;
+        view.secondary_cursor = self.secondary_cursor;
+    }
+}
+
+/// A row of the waves view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Row {
+    /// A var, or one of its bits if it is expanded.
+    Wave(VarId, Option<u32>),
+    /// Another row of a bus showing its values in a different radix. This is
+    /// the index in its `ViewState::radices`.
+    Radix(VarId, usize),
+    /// The header of a group. This is its index in `ViewState::groups`.
+    Group(usize),
+}
+
+/// Get the rows of the waves view, and how many of them are pinned. Pinned
+/// vars come first, then the vars that aren't in a group, then each group
+/// (the header and then its vars unless it is collapsed). Each var's row is
+/// followed by a row for each of its other radices, and then a row for each
+/// bit if it is expanded.
+fn layout_rows(shown: &HashMap<VarId, ValAndTimeVec>, view: &ViewState) -> (Vec<Row>, usize) {
+    let mut varids: Vec<VarId> = shown.keys().copied().collect();
+    varids.sort_unstable_by_key(|varid| varid.0);
+    let grouped: HashSet<VarId> = view
+        .groups
+        .iter()
+        .flat_map(|group| group.vars.iter().map(|&v| VarId(v)))
+        .collect();
+
+    let mut rows = Vec::new();
+    let add_var = |rows: &mut Vec<Row>, varid: VarId| {
+        rows.push(Row::Wave(varid, None));
+        if let Some(radices) = view.radices.get(&varid) {
+            rows.extend((1..radices.len()).map(|index| Row::Radix(varid, index)));
+        }
+        if let Some(bits) = view.expanded.get(&varid) {
+            rows.extend((0..bits.len() as u32).map(|bit| Row::Wave(varid, Some(bit))));
+        }
+    };
+
+    for &varid in varids.iter().filter(|varid| view.pinned.contains(varid)) {
+        add_var(&mut rows, varid);
+    }
+    let num_pinned = rows.len();
+
+    for &varid in &varids {
+        if !view.pinned.contains(&varid) && !grouped.contains(&varid) {
+            add_var(&mut rows, varid);
+        }
+    }
+    for (index, group) in view.groups.iter().enumerate() {
+        rows.push(Row::Group(index));
+        if group.collapsed {
+            continue;
+        }
+        for varid in group.vars.iter().map(|&v| VarId(v)) {
+            if shown.contains_key(&varid) && !view.pinned.contains(&varid) {
+                add_var(&mut rows, varid);
+            }
+        }
+    }
+    (rows, num_pinned)
+}
+
+/// Where the `index`th row of a region of the waves view is. This only
+/// depends on how many rows are shown before it, never on how many vars the
+/// file has, so it stays exact for files with huge numbers of vars.
+fn row_rect(region: Rect, index: usize) -> Rect {
+    let top = region.top() + index as f32 * ROW_HEIGHT;
+    Rect::from_x_y_ranges(region.x_range(), top..=top + ROW_HEIGHT)
+}
+
+/// Maps a time and a value (0 at the bottom of the wave, 1 at the top) to
+/// the screen. egui works in f32, which can't tell times apart past 2^24, so
+/// times are made relative to the start of the view in f64 first.
+#[derive(Clone, Copy, Debug)]
+struct WaveTransform {
+    /// The time at the left of `to_screen`'s scene rect.
+    origin: f64,
+    to_screen: emath::RectTransform,
+}
+
+impl WaveTransform {
+    /// Show `time_range` and values `y_range` across `rect`.
+    fn new(time_range: Range<f64>, y_range: RangeInclusive<f32>, rect: Rect) -> Self {
+        let scene_rect =
+            Rect::from_x_y_ranges(0.0..=(time_range.end - time_range.start) as f32, y_range);
+        Self {
+            origin: time_range.start,
+            to_screen: emath::RectTransform::from_to(scene_rect, rect),
+        }
+    }
+
+    fn pos(&self, time: f64, y: f32) -> Pos2 {
+        self.to_screen * pos2((time - self.origin) as f32, y)
+    }
+}
+
+/// Split the wave of a bus into a wave for each bit, bit 0 first. Values
+/// that don't change the bit are left out. Each bit's clock period (see
+/// `DetectPeriod`) is found here too, so it isn't done every frame.
+pub fn expand_bits(wave: &ValAndTimeVec, bits: u32) -> Vec<(ValAndTimeVec, Option<u64>)> {
+    (0..bits)
+        .map(|bit| {
+            let bit_wave: ValAndTimeVec = wave
+                .iter()
+                .map(|(time, value)| (*time, value.slice(bit..bit + 1)))
+                .collect();
+            let bit_wave = bit_wave.into_transitions(1);
+            let period = bit_wave.detect_period();
+            (bit_wave, period)
+        })
+        .collect()
+}
+
+/// If a wave has fewer pixels than this per edge, edges that are closer
+/// together than this are drawn as a filled "activity band" instead.
+const MIN_PIXELS_PER_EDGE: f32 = 3.0;
 
 pub fn show_waves_widget(
     ui: &mut Ui,
     file: &Fst,
-    cached_waves: &HashMap<VarId, ValAndTimeVec>,
-    timespan: Range<f64>,
+    cache: &WaveCache,
+    view: &mut ViewState,
+    timespan: &mut Range<f64>,
+    markers: &mut Vec<(u64, String)>,
+    style: &WaveStyle,
 ) -> Response {
+    let cached_waves = &cache.shown;
     let wave_colour = if ui.visuals().dark_mode {
         Color32::from_additive_luminance(196)
     } else {
@@ -26,61 +365,541 @@ pub fn show_waves_widget(
     };
 
     let x_colour = if ui.visuals().dark_mode {
-        Color32::from_additive_luminance(196)
+        Color32::from_rgb(255, 90, 90)
     } else {
-        Color32::from_black_alpha(240)
+        Color32::from_rgb(200, 30, 30)
+    };
+
+    let changed_bits_colour = if ui.visuals().dark_mode {
+        Color32::from_rgb(100, 180, 255)
+    } else {
+        Color32::from_rgb(0, 90, 200)
     };
 
+    // Backgrounds for even and odd rows.
+    let row_colours = if ui.visuals().dark_mode {
+        [
+            Color32::from_additive_luminance(8),
+            Color32::from_additive_luminance(20),
+        ]
+    } else {
+        [Color32::from_black_alpha(6), Color32::from_black_alpha(18)]
+    };
+
+    let selected_row_colour = ui.visuals().selection.bg_fill.linear_multiply(0.4);
+    let group_colour = ui.visuals().widgets.inactive.bg_fill;
+
+    let full_range = file.header.start_time as f64..file.header.end_time as f64;
+    *timespan = clamp_timespan(timespan.clone(), full_range.clone());
+
+    view.shapes_drawn = 0;
+
     Frame::canvas(ui.style())
         .show(ui, |ui| {
             let desired_size = ui.available_size();
             let (id, rect) = ui.allocate_space(desired_size);
 
-            let response = ui.interact(rect, id, egui::Sense::click());
+            let response = ui.interact(rect, id, egui::Sense::click_and_drag());
 
             ui.set_clip_rect(rect);
 
-            const LINE_SPACING: f32 = 1.4;
-
+            // The exact time under the pointer when it is over the ruler.
+            let ruler_hover = response
+                .hover_pos()
+                .filter(|pos| pos.y < rect.top() + RULER_HEIGHT);
             draw_timeline(ui, timespan.clone(), rect);
+            if let Some(pos) = ruler_hover {
+                draw_time_readout(ui, timespan.clone(), rect, pos, file.header.timescale);
+            }
 
-            let mut wave_rect = rect;
-            wave_rect.set_top(wave_rect.top() + 30.0);
+            let mut overview_rect = rect;
+            overview_rect.set_top(rect.top() + RULER_HEIGHT);
+            overview_rect.set_height(OVERVIEW_HEIGHT);
+            show_overview(
+                ui,
+                id.with("overview"),
+                full_range.clone(),
+                timespan,
+                overview_rect,
+            );
 
-            let to_screen = emath::RectTransform::from_to(
-                Rect::from_x_y_ranges(
-                    timespan.start as f32..=timespan.end as f32,
-                    0.0..=(file.header.num_vars as f32 * LINE_SPACING),
-                ),
-                wave_rect,
+            let mut activity_rect = rect;
+            activity_rect.set_top(overview_rect.bottom() + 2.0);
+            activity_rect.set_height(ACTIVITY_HEIGHT);
+            show_activity(
+                ui,
+                id.with("activity"),
+                cached_waves,
+                full_range.clone(),
+                timespan,
+                activity_rect,
             );
+            *timespan = clamp_timespan(timespan.clone(), full_range);
+            let timespan = timespan.clone();
+
+            let mut wave_rect = rect;
+            wave_rect.set_top(activity_rect.bottom() + 2.0);
 
-            let mut shapes = vec![];
+            // Clicking the ruler places the primary cursor and right-clicking
+            // it the secondary one. Double-clicking it adds a marker.
+            let ruler_time = response
+                .interact_pointer_pos()
+                .filter(|pos| pos.y < overview_rect.top())
+                .map(|pos| x_to_time(pos.x, timespan.clone(), rect).max(0.0).round() as u64);
+            if let Some(time) = ruler_time {
+                if response.clicked() {
+                    view.cursor = Some(time);
+                }
+                if response.secondary_clicked() {
+                    view.secondary_cursor = Some(time);
+                }
+                if response.double_clicked() {
+                    markers.push((time, format!("M{}", markers.len() + 1)));
+                }
+            }
+
+            draw_markers(ui, markers, timespan.clone(), rect);
+
+            // Pinned vars are drawn in a band at the top that stays put when
+            // the rest of the vars are scrolled.
+            let (rows, num_pinned) = layout_rows(cached_waves, view);
+            // Where each visible row is, for dragging vars onto groups.
+            let mut row_rects: Vec<(Row, Rect)> = Vec::new();
+
+            let mut pinned_rect = wave_rect;
+            pinned_rect.set_height(num_pinned as f32 * ROW_HEIGHT);
+            let mut scroll_rect = wave_rect;
+            scroll_rect.set_top(pinned_rect.bottom());
+
+            // The wave is centred in its row, and rows are contiguous.
+            let margin = (LINE_SPACING - 1.0) / 2.0;
+
+            for (row, &row_kind) in rows.iter().enumerate() {
+                let (region, index) = if row < num_pinned {
+                    (pinned_rect, row)
+                } else {
+                    (scroll_rect, row - num_pinned)
+                };
+                let row_rect = row_rect(region, index);
+                if !row_rect.intersects(region) {
+                    continue;
+                }
+                let painter = ui.painter().with_clip_rect(region);
+                row_rects.push((row_kind, row_rect.intersect(region)));
+
+                let (varid, bit, radix) = match row_kind {
+                    Row::Wave(varid, bit) => (varid, bit, view.label_radix(varid, 0)),
+                    Row::Radix(varid, index) => (varid, None, view.label_radix(varid, index)),
+                    Row::Group(index) => {
+                        let group = &mut view.groups[index];
+                        // Clicking the header collapses or expands it.
+                        if response.clicked() {
+                            if let Some(pos) = response.interact_pointer_pos() {
+                                if row_rect.contains(pos) && region.contains(pos) {
+                                    group.collapsed = !group.collapsed;
+                                }
+                            }
+                        }
+                        draw_group_header(&painter, group, row_rect, wave_colour, group_colour);
+                        continue;
+                    }
+                };
+                let (wave, var_length, period) = match bit {
+                    Some(bit) => {
+                        let (wave, period) = &view.expanded[&varid][bit as usize];
+                        (wave, VarLength::Bits(1), *period)
+                    }
+                    None => (
+                        &cached_waves[&varid],
+                        file.var_lengths.length(varid),
+                        cache.period(varid),
+                    ),
+                };
+
+                // Clicking a wave selects it.
+                if response.clicked() {
+                    if let Some(pos) = response.interact_pointer_pos() {
+                        if row_rect.contains(pos) && region.contains(pos) {
+                            view.selected = Some(varid);
+                        }
+                    }
+                }
+                let background = if view.selected == Some(varid) {
+                    selected_row_colour
+                } else {
+                    row_colours[row % 2]
+                };
 
-            for (varid, wave) in cached_waves.iter() {
-                let mut wave_to_screen =
-                    to_screen.translated(Vec2::UP * (varid.0 as f32 * LINE_SPACING));
                 // Invert Y.
                 // TODO.
+                let wave_to_screen =
+                    WaveTransform::new(timespan.clone(), -margin..=(1.0 + margin), row_rect);
+
+                // Clocks that are too fast to see the edges of are drawn as a
+                // labelled band instead.
+                let clock_period = match var_length {
+                    VarLength::Bits(1) => period,
+                    _ => None,
+                }
+                .filter(|&period| {
+                    let span = (timespan.end - timespan.start) as f32;
+                    period as f32 * row_rect.width() / span < MIN_PIXELS_PER_CLOCK
+                });
+
+                // Analog reals are scaled to their own range, so a big signal
+                // doesn't flatten a small one.
+                let y_range = match (bit, var_length) {
+                    (None, VarLength::Real) if view.analog.contains(&varid) => {
+                        Some(view.y_ranges.get(&varid).copied().unwrap_or_else(|| {
+                            let range = timespan.start.max(0.0).floor() as u64
+                                ..timespan.end.max(0.0).ceil() as u64;
+                            auto_y_range(wave, range)
+                        }))
+                    }
+                    _ => None,
+                };
+
+                let mut shapes = vec![Shape::rect_filled(row_rect, 0.0, background)];
+
+                if let Some(y_range) = y_range {
+                    shapes.extend(build_analog_shapes(
+                        wave,
+                        wave_to_screen,
+                        y_range,
+                        Stroke::new(style.line_thickness, wave_colour),
+                        timespan.clone(),
+                    ));
+                } else if clock_period.is_none() {
+                    shapes.extend(build_wave_shapes(
+                        var_length,
+                        wave,
+                        wave_to_screen,
+                        Stroke::new(style.line_thickness, wave_colour),
+                        x_colour,
+                        timespan.clone(),
+                    ));
+                }
+                view.shapes_drawn += shapes.len();
+                painter.extend(shapes);
+
+                // Bit rows are labelled with the bit number.
+                if let Some(bit) = bit {
+                    painter.text(
+                        row_rect.left_center() + vec2(4.0, 0.0),
+                        Align2::LEFT_CENTER,
+                        format!("[{bit}]"),
+                        FontId {
+                            size: 10.0,
+                            family: FontFamily::Proportional,
+                        },
+                        changed_bits_colour,
+                    );
+                } else if view
+                    .radices
+                    .get(&varid)
+                    .is_some_and(|radices| radices.len() > 1)
+                {
+                    // So are the rows of buses that are shown in several
+                    // radices.
+                    painter.text(
+                        row_rect.left_center() + vec2(4.0, 0.0),
+                        Align2::LEFT_CENTER,
+                        radix.to_string(),
+                        FontId {
+                            size: 10.0,
+                            family: FontFamily::Proportional,
+                        },
+                        changed_bits_colour,
+                    );
+                } else if let Some((min, max)) = y_range {
+                    // And analog rows with their range.
+                    for (value, pos, align) in [
+                        (max, row_rect.left_top(), Align2::LEFT_TOP),
+                        (min, row_rect.left_bottom(), Align2::LEFT_BOTTOM),
+                    ] {
+                        painter.text(
+                            pos + vec2(4.0, 0.0),
+                            align,
+                            Value::Real(value).format_real(),
+                            FontId {
+                                size: 10.0,
+                                family: FontFamily::Proportional,
+                            },
+                            changed_bits_colour,
+                        );
+                    }
+                }
+
+                if let (None, VarLength::Bits(bits @ 2..)) = (clock_period, var_length) {
+                    draw_bus_values(
+                        &painter,
+                        wave,
+                        wave_to_screen,
+                        timespan.clone(),
+                        bits,
+                        radix,
+                        wave_colour,
+                    );
+                }
+
+                if let Some(period) = clock_period {
+                    let name = file
+                        .variable_info(varid)
+                        .map_or_else(|_| "clock".to_string(), |info| info.name);
+                    let name = match bit {
+                        Some(bit) => format!("{name}[{bit}]"),
+                        None => name,
+                    };
+                    draw_clock_band(
+                        &painter,
+                        wave,
+                        wave_to_screen,
+                        timespan.clone(),
+                        &format!(
+                            "{name} ({})",
+                            format_duration(period, file.header.timescale)
+                        ),
+                        wave_colour,
+                    );
+                }
+
+                if style.show_changed_bits {
+                    if let VarLength::Bits(2..) = var_length {
+                        draw_changed_bits(
+                            &painter,
+                            wave,
+                            wave_to_screen,
+                            timespan.clone(),
+                            changed_bits_colour,
+                        );
+                    }
+                }
+            }
 
-                draw_single_wave(
-                    file.var_lengths.length(*varid),
-                    wave,
-                    wave_to_screen,
-                    &mut shapes,
+            // Dragging a wave onto a group (its header or one of its waves)
+            // moves it to that group, and dragging it anywhere else takes it
+            // out of its group.
+            let row_at = |pos: Pos2| {
+                row_rects
+                    .iter()
+                    .find(|(_, rect)| rect.contains(pos))
+                    .map(|(row, _)| *row)
+            };
+            if response.drag_started() {
+                view.dragging = response
+                    .interact_pointer_pos()
+                    .and_then(row_at)
+                    .and_then(|row| match row {
+                        Row::Wave(varid, _) | Row::Radix(varid, _) => Some(varid),
+                        Row::Group(_) => None,
+                    });
+            }
+            if let (Some(varid), Some(pos)) = (view.dragging, response.hover_pos()) {
+                ui.painter().text(
+                    pos,
+                    Align2::LEFT_BOTTOM,
+                    file.variable_info(varid)
+                        .map_or_else(|_| format!("{varid:?}"), |info| info.name),
+                    FontId::proportional(12.0),
                     wave_colour,
-                    x_colour,
-                    0.0..1.0, // TODO
                 );
             }
+            if response.drag_released() {
+                if let (Some(varid), Some(pos)) = (view.dragging.take(), response.hover_pos()) {
+                    let target = match row_at(pos) {
+                        Some(Row::Group(index)) => Some(index),
+                        Some(Row::Wave(target, _) | Row::Radix(target, _)) => view
+                            .groups
+                            .iter()
+                            .position(|group| group.vars.contains(&target.0)),
+                        None => None,
+                    };
+                    move_to_group(&mut view.groups, varid, target);
+                }
+            }
 
-            ui.painter().extend(shapes);
+            if num_pinned > 0 {
+                ui.painter().hline(
+                    wave_rect.x_range(),
+                    pinned_rect.bottom(),
+                    Stroke::new(1.0, wave_colour),
+                );
+            }
+
+            draw_cursors(
+                ui,
+                [view.cursor, view.secondary_cursor],
+                timespan,
+                rect,
+                wave_rect,
+                file.header.timescale,
+            );
 
             response
         })
         .inner
 }
 
+/// The narrowest time range that can be shown. Times are integers so there
+/// is no point going below 1.
+pub const MIN_TIMESPAN: f64 = 1.0;
+
+/// Keep `timespan` inside `bounds` (the time range of the file), moving it
+/// rather than shrinking it where possible, and make it at least
+/// `MIN_TIMESPAN` wide. A zero width range would give NaNs when converting
+/// to screen space. If `bounds` is narrower than that, e.g. for a file with
+/// only one timestamp, it is widened about its centre.
+pub fn clamp_timespan(timespan: Range<f64>, bounds: Range<f64>) -> Range<f64> {
+    // Far from 0 (above 2^53) adjacent f64s are more than `MIN_TIMESPAN`
+    // apart, so that would round back to an empty range.
+    let magnitude = bounds.start.abs().max(bounds.end.abs());
+    let min_width = MIN_TIMESPAN.max(magnitude * 2.0 * f64::EPSILON);
+    let bounds = if bounds.end - bounds.start >= min_width {
+        bounds
+    } else {
+        let centre = (bounds.start + bounds.end) / 2.0;
+        centre - min_width / 2.0..centre + min_width / 2.0
+    };
+    // Not `clamp()`, which panics if rounding leaves `bounds` narrower.
+    let width = (timespan.end - timespan.start)
+        .max(min_width)
+        .min(bounds.end - bounds.start);
+    let centre = (timespan.start + timespan.end) / 2.0;
+    let start = (centre - width / 2.0)
+        .max(bounds.start)
+        .min(bounds.end - width);
+    start..start + width
+}
+
+const RULER_HEIGHT: f32 = 30.0;
+const OVERVIEW_HEIGHT: f32 = 8.0;
+const ACTIVITY_HEIGHT: f32 = 8.0;
+/// Width of each bucket of the activity heatmap in pixels.
+const ACTIVITY_BUCKET_WIDTH: f32 = 4.0;
+
+/// A thin bar showing the whole time range of the file, with a box for the
+/// part that is currently visible. Clicking or dragging it moves the view.
+fn show_overview(
+    ui: &mut Ui,
+    id: egui::Id,
+    full_range: Range<f64>,
+    timespan: &mut Range<f64>,
+    space: Rect,
+) {
+    let response = ui.interact(space, id, egui::Sense::click_and_drag());
+
+    let full_span = full_range.end - full_range.start;
+    if full_span <= 0.0 {
+        return;
+    }
+
+    // Centre the view on the pointer, keeping it inside the file.
+    if response.clicked() || response.dragged() {
+        if let Some(pos) = response.interact_pointer_pos() {
+            let view_span = timespan.end - timespan.start;
+            let fraction = ((pos.x - space.left()) / space.width()) as f64;
+            let mut start = full_range.start + fraction * full_span - view_span / 2.0;
+            start = start.min(full_range.end - view_span).max(full_range.start);
+            *timespan = start..start + view_span;
+        }
+    }
+
+    let background = if ui.visuals().dark_mode {
+        Color32::from_additive_luminance(40)
+    } else {
+        Color32::from_black_alpha(30)
+    };
+    let highlight = if ui.visuals().dark_mode {
+        Color32::from_additive_luminance(140)
+    } else {
+        Color32::from_black_alpha(120)
+    };
+
+    let to_x = |t: f64| space.left() + space.width() * ((t - full_range.start) / full_span) as f32;
+    let view = Rect::from_x_y_ranges(
+        to_x(timespan.start).max(space.left())..=to_x(timespan.end).min(space.right()),
+        space.y_range(),
+    );
+
+    ui.painter().rect_filled(space, 2.0, background);
+    ui.painter().rect_filled(view, 2.0, highlight);
+}
+
+/// Count the changes of all the waves in each of `buckets` equal parts of
+/// `range`.
+fn activity_buckets<'a>(
+    waves: impl Iterator<Item = &'a ValAndTimeVec>,
+    range: Range<f64>,
+    buckets: usize,
+) -> Vec<usize> {
+    let span = range.end - range.start;
+    // The start of each bucket, and the end of the last one.
+    let bounds: Vec<f64> = (0..=buckets)
+        .map(|i| range.start + span * i as f64 / buckets as f64)
+        .collect();
+    let mut counts = vec![0; buckets];
+    for wave in waves {
+        let starts: Vec<usize> = bounds
+            .iter()
+            .map(|&bound| wave.partition_point(|(time, _)| (*time as f64) < bound))
+            .collect();
+        for (count, pair) in counts.iter_mut().zip(starts.windows(2)) {
+            *count += pair[1] - pair[0];
+        }
+    }
+    counts
+}
+
+/// A heatmap of how many changes the shown waves have over the whole file,
+/// busier being darker. Clicking it zooms the view to that part.
+fn show_activity(
+    ui: &mut Ui,
+    id: egui::Id,
+    cached_waves: &HashMap<VarId, ValAndTimeVec>,
+    full_range: Range<f64>,
+    timespan: &mut Range<f64>,
+    space: Rect,
+) {
+    let response = ui.interact(space, id, egui::Sense::click());
+
+    let full_span = full_range.end - full_range.start;
+    let buckets = (space.width() / ACTIVITY_BUCKET_WIDTH) as usize;
+    if full_span <= 0.0 || buckets == 0 {
+        return;
+    }
+    let bucket_span = full_span / buckets as f64;
+
+    if response.clicked() {
+        if let Some(pos) = response.interact_pointer_pos() {
+            let bucket = ((pos.x - space.left()) / ACTIVITY_BUCKET_WIDTH) as usize;
+            let start = full_range.start + bucket.min(buckets - 1) as f64 * bucket_span;
+            *timespan = start..start + bucket_span;
+        }
+    }
+
+    let counts = activity_buckets(cached_waves.values(), full_range, buckets);
+    let Some(&max) = counts.iter().max().filter(|&&max| max > 0) else {
+        return;
+    };
+    for (i, &count) in counts.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        let level = (count as f32 / max as f32 * 255.0).round() as u8;
+        let colour = if ui.visuals().dark_mode {
+            Color32::from_additive_luminance(level)
+        } else {
+            Color32::from_black_alpha(level)
+        };
+        let left = space.left() + i as f32 * ACTIVITY_BUCKET_WIDTH;
+        ui.painter().rect_filled(
+            Rect::from_x_y_ranges(left..=left + ACTIVITY_BUCKET_WIDTH, space.y_range()),
+            0.0,
+            colour,
+        );
+    }
+}
+
 fn draw_timeline(ui: &mut Ui, time_range: Range<f64>, space: Rect) {
     let text = if ui.visuals().dark_mode {
         Color32::from_additive_luminance(196)
@@ -99,6 +918,9 @@ fn draw_timeline(ui: &mut Ui, time_range: Range<f64>, space: Rect) {
     // TODO: I bet it's easier and faster just to loop through [1, 2, 5, 10, 20, 50, etc.]
 
     let time_span = time_range.end - time_range.start;
+    if time_span <= 0.0 || space.width() <= 0.0 {
+        return;
+    }
 
     let log_step = (time_span / space.width() as f64).log10();
     let log_step_floor = log_step.floor();
@@ -149,156 +971,1075 @@ fn draw_timeline(ui: &mut Ui, time_range: Range<f64>, space: Rect) {
     }
 }
 
-fn draw_single_wave(
+/// The time at screen position `x`, the inverse of the time to screen
+/// transform used for the ruler and the waves.
+fn x_to_time(x: f32, time_range: Range<f64>, space: Rect) -> f64 {
+    let fraction = ((x - space.left()) / space.width()) as f64;
+    time_range.start + fraction * (time_range.end - time_range.start)
+}
+
+/// The screen position of `time`, the inverse of `x_to_time()`.
+fn time_to_x(time: f64, time_range: Range<f64>, space: Rect) -> f32 {
+    let fraction = (time - time_range.start) / (time_range.end - time_range.start);
+    space.left() + space.width() * fraction as f32
+}
+
+/// The label for the time between the two cursors.
+fn cursor_delta_label(primary: u64, secondary: u64, timescale: i8) -> String {
+    format!(
+        "|Δt| = {}",
+        format_duration(primary.abs_diff(secondary), timescale)
+    )
+}
+
+/// Draw the primary and secondary cursors from the top of the ruler down.
+/// If both are placed, the time between them is shown on a dimension line
+/// at the top of `waves`.
+fn draw_cursors(
+    ui: &Ui,
+    cursors: [Option<u64>; 2],
+    time_range: Range<f64>,
+    space: Rect,
+    waves: Rect,
+    timescale: i8,
+) {
+    let colours = if ui.visuals().dark_mode {
+        [
+            Color32::from_rgb(0, 200, 255),
+            Color32::from_rgb(255, 100, 220),
+        ]
+    } else {
+        [
+            Color32::from_rgb(0, 110, 200),
+            Color32::from_rgb(190, 0, 150),
+        ]
+    };
+
+    let painter = ui.painter();
+    let mut xs = [None; 2];
+    for ((cursor, colour), x) in cursors.iter().zip(colours).zip(&mut xs) {
+        if let Some(time) = cursor {
+            let cursor_x = time_to_x(*time as f64, time_range.clone(), space);
+            painter.vline(cursor_x, space.y_range(), Stroke::new(1.0, colour));
+            *x = Some(cursor_x);
+        }
+    }
+
+    let ([Some(primary), Some(secondary)], [Some(primary_x), Some(secondary_x)]) = (cursors, xs)
+    else {
+        return;
+    };
+
+    // A line with end ticks between the cursors, with the delta above it.
+    let text = ui.visuals().strong_text_color();
+    let stroke = Stroke::new(1.0, text);
+    let y = waves.top() + 14.0;
+    painter.hline(
+        primary_x.min(secondary_x)..=primary_x.max(secondary_x),
+        y,
+        stroke,
+    );
+    for x in [primary_x, secondary_x] {
+        painter.vline(x, y - 4.0..=y + 4.0, stroke);
+    }
+    let galley = painter.layout_no_wrap(
+        cursor_delta_label(primary, secondary, timescale),
+        FontId::proportional(10.0),
+        text,
+    );
+    let label = Rect::from_center_size(
+        pos2(
+            (primary_x + secondary_x) / 2.0,
+            y - 4.0 - galley.size().y / 2.0,
+        ),
+        galley.size() + vec2(6.0, 2.0),
+    );
+    painter.rect_filled(label, 2.0, ui.visuals().extreme_bg_color);
+    painter.galley(label.min + vec2(3.0, 1.0), galley);
+}
+
+/// A label on the ruler showing the time under the pointer, to the nearest
+/// tick.
+fn draw_time_readout(
+    ui: &mut Ui,
+    time_range: Range<f64>,
+    space: Rect,
+    pointer: Pos2,
+    timescale: i8,
+) {
+    let ticks = x_to_time(pointer.x, time_range, space).max(0.0).round() as u64;
+    let font = FontId {
+        size: 10.0,
+        family: FontFamily::Proportional,
+    };
+    let (background, text) = if ui.visuals().dark_mode {
+        (Color32::from_gray(60), Color32::WHITE)
+    } else {
+        (Color32::from_gray(230), Color32::BLACK)
+    };
+
+    let painter = ui.painter();
+    let galley = painter.layout_no_wrap(format_duration(ticks, timescale), font, text);
+    // Next to the pointer, but flipped to its left near the right edge.
+    let mut label = Rect::from_min_size(
+        pos2(pointer.x + 6.0, space.top() + 2.0),
+        galley.size() + vec2(6.0, 2.0),
+    );
+    if label.right() > space.right() {
+        label = label.translate(vec2(-label.width() - 12.0, 0.0));
+    }
+    painter.line_segment(
+        [
+            pos2(pointer.x, space.top()),
+            pos2(pointer.x, space.top() + RULER_HEIGHT),
+        ],
+        Stroke::new(1.0, text),
+    );
+    painter.rect_filled(label, 2.0, background);
+    painter.galley(label.min + vec2(3.0, 1.0), galley);
+}
+
+fn draw_markers(ui: &mut Ui, markers: &[(u64, String)], time_range: Range<f64>, space: Rect) {
+    let colour = if ui.visuals().dark_mode {
+        Color32::from_rgb(255, 200, 0)
+    } else {
+        Color32::from_rgb(200, 120, 0)
+    };
+
+    let time_span = time_range.end - time_range.start;
+
+    for (time, name) in markers.iter() {
+        let fraction = (*time as f64 - time_range.start) / time_span;
+        let x = space.left() + space.width() * fraction as f32;
+
+        ui.painter().line_segment(
+            [pos2(x, space.top() + 20.0), pos2(x, space.bottom())],
+            Stroke::new(1.0, colour),
+        );
+        ui.painter().text(
+            pos2(x + 2.0, space.top() + 20.0),
+            Align2::LEFT_TOP,
+            name,
+            FontId {
+                size: 8.0,
+                family: FontFamily::Proportional,
+            },
+            colour,
+        );
+    }
+}
+
+/// The contents of the Markers menu, which allows renaming and removing them.
+pub fn show_markers_menu(ui: &mut Ui, markers: &mut Vec<(u64, String)>) {
+    if markers.is_empty() {
+        ui.label("Double-click the time ruler to add a marker.");
+        return;
+    }
+
+    let mut remove = None;
+    for (index, (time, name)) in markers.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(name);
+            ui.label(format!("{}", time));
+            if ui.button("Remove").clicked() {
+                remove = Some(index);
+            }
+        });
+    }
+    if let Some(index) = remove {
+        markers.remove(index);
+    }
+}
+
+/// Draw the header row of a group: its name, how many vars it has and
+/// whether it is collapsed.
+fn draw_group_header(
+    painter: &Painter,
+    group: &Group,
+    row_rect: Rect,
+    text_colour: Color32,
+    background: Color32,
+) {
+    painter.rect_filled(row_rect, 0.0, background);
+    let arrow = if group.collapsed { "▶" } else { "▼" };
+    painter.text(
+        row_rect.left_center() + vec2(4.0, 0.0),
+        Align2::LEFT_CENTER,
+        format!("{arrow} {} ({})", group.name, group.vars.len()),
+        FontId::proportional(14.0),
+        text_colour,
+    );
+}
+
+/// Label each transition of a bus with the number of bits that changed, so
+/// that e.g. single bit glitches in wide registers stand out. Labels that
+/// would overlap the previous one are left out.
+fn draw_changed_bits(
+    painter: &Painter,
+    wave: &ValAndTimeVec,
+    to_screen: WaveTransform,
+    time_range: Range<f64>,
+    colour: Color32,
+) {
+    const MIN_LABEL_SPACING: f32 = 20.0;
+
+    let mut prev_x = f32::NEG_INFINITY;
+    for pair in wave.windows(2) {
+        let ((_, prev_value), (time, value)) = (&pair[0], &pair[1]);
+        if !time_range.contains(&(*time as f64)) || value == prev_value {
+            continue;
+        }
+        let pos = to_screen.pos(*time as f64, 0.0);
+        if pos.x - prev_x < MIN_LABEL_SPACING {
+            continue;
+        }
+        prev_x = pos.x;
+        painter.text(
+            pos,
+            Align2::CENTER_BOTTOM,
+            value.hamming(prev_value).to_string(),
+            FontId {
+                size: 8.0,
+                family: FontFamily::Proportional,
+            },
+            colour,
+        );
+    }
+}
+
+/// Label each value of a bus in the middle of the visible part of its
+/// hexagon, if there is room.
+fn draw_bus_values(
+    painter: &Painter,
+    wave: &ValAndTimeVec,
+    to_screen: WaveTransform,
+    time_range: Range<f64>,
+    bits: u32,
+    radix: Radix,
+    colour: Color32,
+) {
+    // Don't bother laying out labels for hexagons narrower than this.
+    const MIN_LABEL_WIDTH: f32 = 12.0;
+
+    let font = FontId::proportional(10.0);
+    // From the value that is held at the start of the view to the last one
+    // that starts in it. Values after that are only needed to end the last
+    // hexagon, which is cut off at the end of the view anyway.
+    let first = wave
+        .partition_point(|(time, _)| *time as f64 <= time_range.start)
+        .saturating_sub(1);
+    let last = wave.partition_point(|(time, _)| *time as f64 <= time_range.end);
+    let mut values = wave[first..last].iter().peekable();
+    while let Some((start, value)) = values.next() {
+        // Skip repeats of the same value, which are part of the same hexagon.
+        let mut end = time_range.end;
+        while let Some((time, next)) = values.peek() {
+            if next != value {
+                end = *time as f64;
+                break;
+            }
+            values.next();
+        }
+
+        let start = (*start as f64).max(time_range.start);
+        let end = end.min(time_range.end);
+        let left = to_screen.pos(start, 0.5);
+        let right = to_screen.pos(end, 0.5);
+        if right.x - left.x < MIN_LABEL_WIDTH {
+            continue;
+        }
+        let galley = painter.layout_no_wrap(value.format_radix(bits, radix), font.clone(), colour);
+        if galley.size().x + 4.0 > right.x - left.x {
+            continue;
+        }
+        painter.galley(
+            pos2(
+                (left.x + right.x - galley.size().x) / 2.0,
+                left.y - galley.size().y / 2.0,
+            ),
+            galley,
+        );
+    }
+}
+
+/// Draw a clock as a hatched band from its first to its last change, with a
+/// label in the visible part.
+fn draw_clock_band(
+    painter: &Painter,
+    wave: &ValAndTimeVec,
+    to_screen: WaveTransform,
+    time_range: Range<f64>,
+    label: &str,
+    colour: Color32,
+) {
+    const HATCH_SPACING: f32 = 6.0;
+
+    let (Some((first, _)), Some((last, _))) = (wave.first(), wave.last()) else {
+        return;
+    };
+    let start = (*first as f64).max(time_range.start);
+    let end = (*last as f64).min(time_range.end);
+    let band = Rect::from_two_pos(to_screen.pos(start, 0.0), to_screen.pos(end, 1.0));
+    if band.width() <= 0.0 {
+        return;
+    }
+
+    let painter = painter.with_clip_rect(painter.clip_rect().intersect(band));
+    painter.rect_filled(band, 0.0, colour.linear_multiply(0.2));
+    let stroke = Stroke::new(1.0, colour.linear_multiply(0.5));
+    let mut x = band.left() - band.height();
+    while x < band.right() {
+        painter.line_segment(
+            [pos2(x, band.bottom()), pos2(x + band.height(), band.top())],
+            stroke,
+        );
+        x += HATCH_SPACING;
+    }
+
+    painter.text(
+        band.center(),
+        Align2::CENTER_CENTER,
+        label,
+        FontId {
+            size: 10.0,
+            family: FontFamily::Proportional,
+        },
+        colour,
+    );
+}
+
+/// Format a number of ticks with the given timescale (e.g. -9 for
+/// nanoseconds) using the largest unit that it is at least 1 of, e.g.
+/// `"2.5us"`.
+pub fn format_duration(ticks: u64, timescale: i8) -> String {
+    const UNITS: [(i32, &str); 6] = [
+        (0, "s"),
+        (-3, "ms"),
+        (-6, "us"),
+        (-9, "ns"),
+        (-12, "ps"),
+        (-15, "fs"),
+    ];
+    let seconds = ticks as f64 * 10f64.powi(timescale as i32);
+    let (exponent, unit) = UNITS
+        .iter()
+        .find(|(exponent, _)| seconds >= 10f64.powi(*exponent))
+        .unwrap_or(&UNITS[UNITS.len() - 1]);
+    let value = seconds / 10f64.powi(*exponent);
+    // Round to 3 decimal places and drop trailing zeros.
+    format!("{}{unit}", (value * 1000.0).round() / 1000.0)
+}
+
+/// Get the shapes to draw a wave with. This doesn't need a `Ui` so the
+/// geometry can be tested.
+fn build_wave_shapes(
     varlength: VarLength,
-    wave: &Vec<(u64, fst::valvec::Value)>,
-    to_screen: emath::RectTransform,
-    shapes: &mut Vec<Shape>,
-    wave_colour: Color32,
+    wave: &ValAndTimeVec,
+    to_screen: WaveTransform,
+    stroke: Stroke,
     // Colour for 'x' values.
     x_colour: Color32,
+    // The visible time range.
     time_range: Range<f64>,
-) {
-    match varlength {
-        VarLength::Bits(bits) => {
-            if bits == 1 {
-                // The points for a green line. We draw this for the whole
-                // wave even if there are X's. Then we draw red boxes over it
-                // where there are X's.
-                let mut points: Vec<Pos2> = Vec::with_capacity(wave.len() * 2);
-
-                let mut prev_bit4 = None;
-
-                for (time, value) in wave.iter() {
-                    let bit4 = value.0[0] & 0b11;
-                    let bit2 = bit4 & 0b1;
-                    if let Some(prev_bit4) = prev_bit4 {
-                        if bit4 == prev_bit4 {
-                            continue;
-                        }
+) -> Vec<Shape> {
+    let mut shapes = Vec::new();
 
-                        let prev_bit2 = prev_bit4 & 0b1;
+    // Draw clusters of edges as filled bands if there isn't room for them.
+    let visible_edges = wave
+        .iter()
+        .filter(|(time, _)| time_range.contains(&(*time as f64)))
+        .count();
+    let width = to_screen.pos(time_range.end, 0.0).x - to_screen.pos(time_range.start, 0.0).x;
+    let dense = width / (visible_edges as f32) < MIN_PIXELS_PER_EDGE;
 
-                        // Draw a vertical line.
-                        points.push(to_screen * pos2(*time as f32, prev_bit2 as f32));
-                        points.push(to_screen * pos2(*time as f32, bit2 as f32));
-                    } else {
-                        // First point.
-                        points.push(to_screen * pos2(*time as f32, bit2 as f32));
-                    }
+    if varlength == VarLength::Bits(1) {
+        // The points for a green line. We draw this for the whole
+        // wave even if there are X's. Then we draw red boxes over it
+        // where there are X's.
+        let mut points: Vec<Pos2> = Vec::with_capacity(wave.len() * 2);
+
+        let mut prev_bit4 = None;
+
+        // Horizontal screen ranges covered by activity bands.
+        let mut bands: Vec<Range<f32>> = Vec::new();
+        let mut prev_x = f32::NEG_INFINITY;
 
-                    prev_bit4 = Some(bit4);
+        for (time, value) in wave.iter() {
+            let bit4 = value.bit(0);
+            let bit2 = bit4 & 0b1;
+            if let Some(prev_bit4) = prev_bit4 {
+                if bit4 == prev_bit4 {
+                    continue;
                 }
 
-                // TODO: Draw to the end time.
+                let prev_bit2 = prev_bit4 & 0b1;
 
-                let thickness = 1.0;
-                shapes.push(epaint::Shape::line(
-                    points,
-                    Stroke::new(thickness, wave_colour),
-                ));
+                // Draw a vertical line.
+                let top = to_screen.pos(*time as f64, prev_bit2 as f32);
+                points.push(top);
+                points.push(to_screen.pos(*time as f64, bit2 as f32));
+
+                if dense && top.x - prev_x < MIN_PIXELS_PER_EDGE {
+                    match bands.last_mut() {
+                        Some(band) if band.end == prev_x => band.end = top.x,
+                        _ => bands.push(prev_x..top.x),
+                    }
+                }
+                prev_x = top.x;
             } else {
-                // Multiple bits get drawn like this:
-                //
-                // _____ⵃ⁐⁐⁐⁐X⁐⁐⁐⁐Ⲗ____
-                //   0       1      2      0
+                // First point.
+                points.push(to_screen.pos(*time as f64, bit2 as f32));
+            }
 
-                // When we get a split we can start a second line, and stop
-                // one of them when we have a join.
+            prev_bit4 = Some(bit4);
+        }
 
-                // Line 0: _____/⎺⎺⎺⎺\____/
-                // Line 1:      \____/⎺⎺⎺⎺\___
+        // TODO: Draw to the end time.
 
-                // We also draw the actual number inside the space (but for
-                // the previous one because then we know how much space we have).
+        shapes.push(epaint::Shape::line(points, stroke));
 
-                let mut line_bottom: Vec<Pos2> = Vec::new();
-                let mut line_top: Vec<Pos2> = Vec::new();
+        // Cover the edges in the bands.
+        let bottom = to_screen.pos(time_range.start, 0.0).y;
+        let top = to_screen.pos(time_range.start, 1.0).y;
+        for band in bands {
+            shapes.push(epaint::Shape::rect_filled(
+                Rect::from_x_y_ranges(band.start..=band.end, top.min(bottom)..=top.max(bottom)),
+                0.0,
+                stroke.color,
+            ));
+        }
+    } else {
+        // Multiple bits get drawn like this, with the hexagons
+        // filled (in a different colour if there are X's or Z's, or for
+        // reals, NaNs):
+        //
+        //        ____ ____
+        // ______/    X    \____
+        //       \____X____/
+        //   0      1    2     0
 
-                let mut prev_value = None;
-                let mut prev_is_zero = true;
+        // The width of the transition chevrons.
+        const CHEVRON_WIDTH: f32 = 2.0;
 
-                let thickness = 1.0;
+        let fill = stroke.color.linear_multiply(0.2);
+        let x_fill = x_colour.linear_multiply(0.4);
 
-                for (time, value) in wave.iter() {
-                    // TODO: Have to do custom Eq here.
-                    if Some(value) == prev_value {
-                        continue;
-                    }
+        // Only the changes, not repeats of the same value.
+        let mut changes: Vec<(f64, &Value)> = Vec::new();
+        for (time, value) in wave.iter() {
+            if changes.last().map(|(_, prev)| *prev) != Some(value) {
+                changes.push((*time as f64, value));
+            }
+        }
 
-                    let is_zero = value.0.iter().all(|b| *b == 0);
+        for (i, (start, value)) in changes.iter().enumerate() {
+            // The last value lasts until the end of the view.
+            let end = changes
+                .get(i + 1)
+                .map_or(time_range.end.max(*start), |(end, _)| *end);
+            if end < time_range.start || *start > time_range.end {
+                continue;
+            }
 
-                    match (prev_is_zero, is_zero) {
-                        (true, true) => {
-                            // _
-                            line_bottom.push(to_screen * pos2(*time as f32, 0.0));
-                        }
-                        (true, false) => {
-                            // ⵃ
-                            line_bottom.push(to_screen * pos2(*time as f32, 0.0));
-                            line_bottom.push(to_screen * pos2(*time as f32, 1.0) + vec2(2.0, 0.0));
-                            line_top.push(to_screen * pos2(*time as f32, 0.5) + vec2(1.0, 0.0));
-                            line_top.push(to_screen * pos2(*time as f32, 0.0) + vec2(2.0, 0.0));
-                            // Ensure line_bottom is still the bottom.
-                            std::mem::swap(&mut line_top, &mut line_bottom);
-                        }
-                        (false, true) => {
-                            // Ⲗ
-                            line_top.push(to_screen * pos2(*time as f32, 1.0));
-                            line_top.push(to_screen * pos2(*time as f32, 0.0) + vec2(2.0, 0.0));
-                            line_bottom.push(to_screen * pos2(*time as f32, 0.0));
-                            line_bottom.push(to_screen * pos2(*time as f32, 0.5) + vec2(1.0, 0.0));
-                            // Ensure line_bottom is still the bottom.
-                            std::mem::swap(&mut line_top, &mut line_bottom);
-
-                            // The bottom (now top) line is finished.
-                            shapes.push(epaint::Shape::line(
-                                std::mem::take(&mut line_top),
-                                Stroke::new(thickness, wave_colour),
-                            ));
-                        }
-                        (false, false) => {
-                            // X
-                            line_bottom.push(to_screen * pos2(*time as f32, 0.0));
-                            line_bottom.push(to_screen * pos2(*time as f32, 1.0) + vec2(2.0, 0.0));
-                            line_top.push(to_screen * pos2(*time as f32, 1.0));
-                            line_top.push(to_screen * pos2(*time as f32, 0.0) + vec2(2.0, 0.0));
-                            // Ensure line_bottom is still the bottom.
-                            std::mem::swap(&mut line_top, &mut line_bottom);
-                        }
-                    }
+            let left = to_screen.pos(*start, 0.0);
+            let right = to_screen.pos(end, 0.0);
 
-                    prev_value = Some(value);
-                    prev_is_zero = is_zero;
-                }
+            let (is_zero, has_xz) = match value {
+                // The high bit of each 2-bit code is set for X and Z.
+                Value::Logic(bytes) => (
+                    bytes.iter().all(|b| *b == 0),
+                    bytes.iter().any(|b| b & 0b1010_1010 != 0),
+                ),
+                Value::Real(x) => (*x == 0.0, x.is_nan()),
+            };
+            if is_zero {
+                shapes.push(epaint::Shape::line_segment([left, right], stroke));
+                continue;
+            }
 
-                // TODO: Draw to the end time.
+            let chevron = CHEVRON_WIDTH.min((right.x - left.x) / 2.0);
+            let middle = to_screen.pos(*start, 0.5);
+            let other = to_screen.pos(*start, 1.0);
+            let points = vec![
+                pos2(left.x, middle.y),
+                pos2(left.x + chevron, left.y),
+                pos2(right.x - chevron, left.y),
+                pos2(right.x, middle.y),
+                pos2(right.x - chevron, other.y),
+                pos2(left.x + chevron, other.y),
+            ];
+            shapes.push(epaint::Shape::convex_polygon(
+                points,
+                if has_xz { x_fill } else { fill },
+                stroke,
+            ));
+        }
+    }
+    shapes
+}
 
-                if !line_bottom.is_empty() {
-                    shapes.push(epaint::Shape::line(
-                        line_bottom,
-                        Stroke::new(thickness, wave_colour),
-                    ));
-                }
-                if !line_top.is_empty() {
-                    shapes.push(epaint::Shape::line(
-                        line_top,
-                        Stroke::new(thickness, wave_colour),
-                    ));
+/// The range of a real's values in `time_range`, to scale it to fit its row.
+/// NaNs are ignored, and flat waves are put in the middle of the row.
+pub fn auto_y_range(wave: &ValAndTimeVec, time_range: Range<u64>) -> (f64, f64) {
+    let stats = wave_stats(wave, VarLength::Real, false, time_range);
+    let (Some(min), Some(max)) = (
+        stats.min.and_then(|v| v.to_f64()),
+        stats.max.and_then(|v| v.to_f64()),
+    ) else {
+        return (0.0, 1.0);
+    };
+    if min < max {
+        (min, max)
+    } else {
+        (min - 1.0, max + 1.0)
+    }
+}
+
+/// Draw a real as a line graph, with the bottom and top of its row at
+/// `y_range`. Values outside it are clamped, and NaNs leave gaps.
+fn build_analog_shapes(
+    wave: &ValAndTimeVec,
+    to_screen: WaveTransform,
+    y_range: (f64, f64),
+    stroke: Stroke,
+    // The visible time range.
+    time_range: Range<f64>,
+) -> Vec<Shape> {
+    let (min, max) = y_range;
+    // Bigger values are higher up the screen.
+    let to_y = |x: f64| (1.0 - ((x - min) / (max - min)).clamp(0.0, 1.0)) as f32;
+
+    // From the value that is held at the start of the view to the last one
+    // that starts in it.
+    let first = wave
+        .partition_point(|(time, _)| *time as f64 <= time_range.start)
+        .saturating_sub(1);
+    let last = wave.partition_point(|(time, _)| *time as f64 <= time_range.end);
+
+    let mut shapes = Vec::new();
+    let mut points: Vec<Pos2> = Vec::new();
+    for (i, (time, value)) in wave.iter().enumerate().take(last).skip(first) {
+        // The last value lasts until the end of the view.
+        let end = wave
+            .get(i + 1)
+            .map_or(time_range.end.max(*time as f64), |(end, _)| *end as f64);
+        match value.to_f64().filter(|x| !x.is_nan()) {
+            Some(x) => {
+                points.push(to_screen.pos(*time as f64, to_y(x)));
+                points.push(to_screen.pos(end, to_y(x)));
+            }
+            None => {
+                if points.len() > 1 {
+                    shapes.push(Shape::line(std::mem::take(&mut points), stroke));
                 }
+                points.clear();
             }
         }
-        VarLength::Real => {
-            // TODO
-        }
     }
+    if points.len() > 1 {
+        shapes.push(Shape::line(points, stroke));
+    }
+    shapes
 }
-trait TransformTransform {
-    fn translated(&self, v: Vec2) -> Self;
+
+/// The window for setting the range of an analog real, if one is open. See
+/// `ViewState::y_ranges`.
+pub fn show_y_range_window(ctx: &Context, view: &mut ViewState) {
+    let Some((varid, min, max)) = &mut view.editing_y_range else {
+        return;
+    };
+    let mut open = true;
+    let mut done = false;
+    egui::Window::new("Set Y range")
+        .open(&mut open)
+        .collapsible(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Min");
+                ui.add(egui::DragValue::new(min).speed(0.1));
+                ui.label("Max");
+                ui.add(egui::DragValue::new(max).speed(0.1));
+            });
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(*min < *max, egui::Button::new("Apply"))
+                    .clicked()
+                {
+                    view.y_ranges.insert(*varid, (*min, *max));
+                    done = true;
+                }
+                // Go back to fitting the visible values.
+                if ui.button("Auto").clicked() {
+                    view.y_ranges.remove(varid);
+                    done = true;
+                }
+            });
+        });
+    if done || !open {
+        view.editing_y_range = None;
+    }
 }
 
-impl TransformTransform for RectTransform {
-    fn translated(&self, v: Vec2) -> Self {
-        Self::from_to(self.from().translate(v), *self.to())
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fst::valvec::Value;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(10, -9), "10ns");
+        assert_eq!(format_duration(2500, -9), "2.5us");
+        assert_eq!(format_duration(1, 0), "1s");
+        assert_eq!(format_duration(100, -12), "100ps");
+        assert_eq!(format_duration(0, -9), "0fs");
+    }
+
+    /// Times 0..100 and values 0..1 map to a 100x10 pixel row.
+    fn test_transform() -> WaveTransform {
+        WaveTransform::new(
+            0.0..100.0,
+            0.0..=1.0,
+            Rect::from_x_y_ranges(0.0..=100.0, 0.0..=10.0),
+        )
+    }
+
+    fn test_wave(changes: &[(u64, &str)]) -> ValAndTimeVec {
+        changes
+            .iter()
+            .map(|(time, bits)| (*time, Value::from_bits_str(bits).unwrap()))
+            .collect()
+    }
+
+    /// The transform isn't exact so round the points to whole pixels.
+    fn rounded(points: &[Pos2]) -> Vec<Pos2> {
+        points.iter().map(|point| point.round()).collect()
+    }
+
+    #[test]
+    fn test_build_wave_shapes_bit() {
+        let stroke = Stroke::new(1.0, Color32::GREEN);
+        let wave = test_wave(&[(0, "0"), (10, "1"), (20, "1"), (30, "0")]);
+        let shapes = build_wave_shapes(
+            VarLength::Bits(1),
+            &wave,
+            test_transform(),
+            stroke,
+            Color32::RED,
+            0.0..100.0,
+        );
+
+        // Repeated values don't add points.
+        let [Shape::Path(line)] = shapes.as_slice() else {
+            panic!("Expected a single line, got {shapes:?}");
+        };
+        assert_eq!(
+            rounded(&line.points),
+            [
+                pos2(0.0, 0.0),
+                pos2(10.0, 0.0),
+                pos2(10.0, 10.0),
+                pos2(30.0, 10.0),
+                pos2(30.0, 0.0),
+            ]
+        );
+        assert!(!line.closed);
+        assert_eq!(line.stroke, stroke);
+    }
+
+    /// f32 can't represent every time past 2^24, e.g. 1e8 + 15, so times are
+    /// drawn relative to the start of the view.
+    #[test]
+    fn test_build_wave_shapes_large_times() {
+        const START: u64 = 100_000_000;
+        let wave = test_wave(&[(START + 10, "0"), (START + 15, "1"), (START + 30, "0")]);
+        let time_range = START as f64..(START + 100) as f64;
+        let shapes = build_wave_shapes(
+            VarLength::Bits(1),
+            &wave,
+            WaveTransform::new(
+                time_range.clone(),
+                0.0..=1.0,
+                Rect::from_x_y_ranges(0.0..=100.0, 0.0..=10.0),
+            ),
+            Stroke::new(1.0, Color32::GREEN),
+            Color32::RED,
+            time_range,
+        );
+        let [Shape::Path(line)] = shapes.as_slice() else {
+            panic!("Expected a single line, got {shapes:?}");
+        };
+        assert_eq!(
+            rounded(&line.points),
+            [
+                pos2(10.0, 0.0),
+                pos2(15.0, 0.0),
+                pos2(15.0, 10.0),
+                pos2(30.0, 10.0),
+                pos2(30.0, 0.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_wave_shapes_dense() {
+        // Edges 1 pixel apart are covered by a band.
+        let wave = test_wave(&[(0, "0"), (50, "1"), (51, "0"), (52, "1")]);
+        let shapes = build_wave_shapes(
+            VarLength::Bits(1),
+            &wave,
+            test_transform(),
+            Stroke::new(1.0, Color32::GREEN),
+            Color32::RED,
+            48.0..54.0,
+        );
+        let [Shape::Path(_), Shape::Rect(band)] = shapes.as_slice() else {
+            panic!("Expected a line and a band, got {shapes:?}");
+        };
+        assert_eq!(
+            rounded(&[band.rect.min, band.rect.max]),
+            [pos2(50.0, 0.0), pos2(52.0, 10.0)]
+        );
+    }
+
+    #[test]
+    fn test_build_wave_shapes_bus() {
+        let stroke = Stroke::new(1.0, Color32::GREEN);
+        let wave = test_wave(&[(0, "00"), (10, "01"), (30, "1x")]);
+        let shapes = build_wave_shapes(
+            VarLength::Bits(2),
+            &wave,
+            test_transform(),
+            stroke,
+            Color32::RED,
+            0.0..50.0,
+        );
+
+        let [Shape::LineSegment { points: zero, .. }, Shape::Path(one), Shape::Path(x)] =
+            shapes.as_slice()
+        else {
+            panic!("Expected a line and two hexagons, got {shapes:?}");
+        };
+        // All zero values are a flat line.
+        assert_eq!(rounded(zero), [pos2(0.0, 0.0), pos2(10.0, 0.0)]);
+        assert_eq!(
+            rounded(&one.points),
+            [
+                pos2(10.0, 5.0),
+                pos2(12.0, 0.0),
+                pos2(28.0, 0.0),
+                pos2(30.0, 5.0),
+                pos2(28.0, 10.0),
+                pos2(12.0, 10.0),
+            ]
+        );
+        assert_eq!(one.fill, Color32::GREEN.linear_multiply(0.2));
+        // The last value lasts until the end of the view, and X's are filled
+        // in a different colour.
+        assert_eq!(x.points[3].round(), pos2(50.0, 5.0));
+        assert_eq!(x.fill, Color32::RED.linear_multiply(0.4));
+    }
+
+    #[test]
+    fn test_build_wave_shapes_real() {
+        let wave = vec![
+            (0, Value::Real(0.0)),
+            (10, Value::Real(2.5)),
+            (30, Value::Real(f64::NAN)),
+        ];
+        let shapes = build_wave_shapes(
+            VarLength::Real,
+            &wave,
+            test_transform(),
+            Stroke::new(1.0, Color32::GREEN),
+            Color32::RED,
+            0.0..50.0,
+        );
+
+        // Reals are drawn like buses, with NaN as X.
+        let [Shape::LineSegment { .. }, Shape::Path(value), Shape::Path(nan)] = shapes.as_slice()
+        else {
+            panic!("Expected a line and two hexagons, got {shapes:?}");
+        };
+        assert_eq!(value.fill, Color32::GREEN.linear_multiply(0.2));
+        assert_eq!(nan.fill, Color32::RED.linear_multiply(0.4));
+    }
+
+    #[test]
+    fn test_build_analog_shapes() {
+        let wave = vec![
+            (0, Value::Real(0.0)),
+            (10, Value::Real(2.0)),
+            (30, Value::Real(f64::NAN)),
+            (40, Value::Real(1.0)),
+        ];
+        let shapes = build_analog_shapes(
+            &wave,
+            test_transform(),
+            (0.0, 2.0),
+            Stroke::new(1.0, Color32::GREEN),
+            0.0..50.0,
+        );
+
+        // The NaN splits it into two lines, with 2.0 at the top of the row.
+        let [Shape::Path(before), Shape::Path(after)] = shapes.as_slice() else {
+            panic!("Expected two lines, got {shapes:?}");
+        };
+        assert_eq!(
+            rounded(&before.points),
+            vec![
+                pos2(0.0, 10.0),
+                pos2(10.0, 10.0),
+                pos2(10.0, 0.0),
+                pos2(30.0, 0.0)
+            ]
+        );
+        assert_eq!(
+            rounded(&after.points),
+            vec![pos2(40.0, 5.0), pos2(50.0, 5.0)]
+        );
+    }
+
+    #[test]
+    fn test_auto_y_range() {
+        let wave = vec![
+            (0, Value::Real(-1.0)),
+            (10, Value::Real(2.0)),
+            (30, Value::Real(f64::NAN)),
+            (40, Value::Real(1.0)),
+        ];
+        assert_eq!(auto_y_range(&wave, 0..50), (-1.0, 2.0));
+        // Only the visible values count.
+        assert_eq!(auto_y_range(&wave, 15..50), (1.0, 2.0));
+        assert_eq!(auto_y_range(&wave, 45..50), (0.0, 2.0));
+        assert_eq!(auto_y_range(&wave, 32..38), (0.0, 1.0));
+    }
+
+    #[test]
+    fn test_layout_rows() {
+        let shown: HashMap<VarId, ValAndTimeVec> = (0..5).map(|v| (VarId(v), Vec::new())).collect();
+        let mut view = ViewState::default();
+        view.pinned.insert(VarId(4));
+        view.expanded.insert(VarId(1), vec![(Vec::new(), None); 2]);
+        view.groups = vec![
+            Group {
+                name: "a".to_string(),
+                // 7 isn't shown.
+                vars: vec![3, 7, 1],
+                ..Default::default()
+            },
+            Group {
+                name: "b".to_string(),
+                vars: vec![2],
+                collapsed: true,
+                ..Default::default()
+            },
+        ];
+
+        let (rows, num_pinned) = layout_rows(&shown, &view);
+        assert_eq!(num_pinned, 1);
+        assert_eq!(
+            rows,
+            [
+                Row::Wave(VarId(4), None),
+                Row::Wave(VarId(0), None),
+                Row::Group(0),
+                Row::Wave(VarId(3), None),
+                Row::Wave(VarId(1), None),
+                Row::Wave(VarId(1), Some(0)),
+                Row::Wave(VarId(1), Some(1)),
+                Row::Group(1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_layout_rows_radices() {
+        let shown: HashMap<VarId, ValAndTimeVec> = (0..2).map(|v| (VarId(v), Vec::new())).collect();
+        let mut view = ViewState::default();
+        view.radices
+            .insert(VarId(0), vec![Radix::Dec, Radix::Hex, Radix::Bin]);
+        view.radices.insert(VarId(1), vec![Radix::Oct]);
+        view.expanded.insert(VarId(0), vec![(Vec::new(), None)]);
+
+        let (rows, _) = layout_rows(&shown, &view);
+        assert_eq!(
+            rows,
+            [
+                Row::Wave(VarId(0), None),
+                Row::Radix(VarId(0), 1),
+                Row::Radix(VarId(0), 2),
+                Row::Wave(VarId(0), Some(0)),
+                Row::Wave(VarId(1), None),
+            ]
+        );
+        assert_eq!(view.radix(VarId(0), 0), Radix::Dec);
+        assert_eq!(view.radix(VarId(0), 2), Radix::Bin);
+        assert_eq!(view.radix(VarId(1), 0), Radix::Oct);
+        assert_eq!(view.radix(VarId(2), 0), Radix::Hex);
+
+        // Decimal is labelled signed for signed buses, the others aren't.
+        view.signed.insert(VarId(0));
+        assert_eq!(view.label_radix(VarId(0), 0), Radix::SDec);
+        assert_eq!(view.label_radix(VarId(0), 1), Radix::Hex);
+        assert_eq!(view.radix(VarId(0), 0), Radix::Dec);
+    }
+
+    /// Only the first and last of a huge number of vars are shown. Rows are
+    /// laid out by how many are shown, not by the vars' IDs.
+    #[test]
+    fn test_many_vars() {
+        let last = VarId(199_999);
+        let shown: HashMap<VarId, ValAndTimeVec> = [
+            (VarId(0), test_wave(&[(0, "0")])),
+            (last, test_wave(&[(0, "0"), (50, "1")])),
+        ]
+        .into_iter()
+        .collect();
+        let (rows, num_pinned) = layout_rows(&shown, &ViewState::default());
+        assert_eq!(num_pinned, 0);
+        assert_eq!(rows, [Row::Wave(VarId(0), None), Row::Wave(last, None)]);
+
+        // The last var's wave is drawn exactly in the second row.
+        let region = Rect::from_x_y_ranges(0.0..=100.0, 0.0..=1000.0);
+        let row = row_rect(region, 1);
+        assert_eq!(row, Rect::from_x_y_ranges(0.0..=100.0, 24.0..=48.0));
+        let margin = (LINE_SPACING - 1.0) / 2.0;
+        let shapes = build_wave_shapes(
+            VarLength::Bits(1),
+            &shown[&last],
+            WaveTransform::new(0.0..100.0, -margin..=(1.0 + margin), row),
+            Stroke::new(1.0, Color32::GREEN),
+            Color32::RED,
+            0.0..100.0,
+        );
+        let [Shape::Path(line)] = shapes.as_slice() else {
+            panic!("Expected a single line, got {shapes:?}");
+        };
+        for point in &line.points {
+            assert!(
+                row.expand(0.01).contains(*point),
+                "{point:?} is outside {row:?}"
+            );
+        }
+    }
+
+    /// Reload a file where the design changed, so the vars' IDs shifted.
+    #[test]
+    fn test_saved_view() {
+        let load_vcd = |vars: &[&str]| {
+            let mut vcd = String::from("$scope module top $end\n");
+            for (i, var) in vars.iter().enumerate() {
+                vcd += &format!("$var wire 1 v{i} {var} $end\n");
+            }
+            vcd += "$upscope $end\n$enddefinitions $end\n#0\n";
+            for i in 0..vars.len() {
+                vcd += &format!("0v{i}\n");
+            }
+            let file = tempfile::NamedTempFile::new().unwrap();
+            std::fs::write(file.path(), vcd).unwrap();
+            fst::vcd::load(file.path()).unwrap()
+        };
+
+        let old = load_vcd(&["a", "b", "c"]);
+        let mut cache = WaveCache::default();
+        let mut view = ViewState::default();
+        for varid in [VarId(0), VarId(1), VarId(2)] {
+            cache.show(varid, || old.read_wave(varid)).unwrap();
+        }
+        view.selected = Some(VarId(2));
+        view.groups = vec![Group {
+            name: "g".to_string(),
+            vars: vec![2, 1],
+            ..Default::default()
+        }];
+        let saved = SavedView::save(&old, &cache, &view, &(0.0..1.0));
+
+        let new = load_vcd(&["x", "c", "a", "b"]);
+        cache.clear();
+        view.forget_vars();
+        let mut timespan = 0.0..1.0;
+        saved.restore(&new, &mut cache, &mut view, &mut timespan);
+        assert_eq!(view.selected, Some(VarId(1)));
+        assert_eq!(view.groups[0].vars, [1, 3]);
+        let (rows, _) = layout_rows(&cache.shown, &view);
+        assert_eq!(
+            rows,
+            [
+                Row::Wave(VarId(2), None),
+                Row::Group(0),
+                Row::Wave(VarId(1), None),
+                Row::Wave(VarId(3), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expand_bits() {
+        let wave = test_wave(&[(0, "00"), (10, "01"), (20, "x1"), (30, "10")]);
+        assert_eq!(
+            expand_bits(&wave, 2),
+            [
+                (test_wave(&[(0, "0"), (10, "1"), (30, "0")]), None),
+                (test_wave(&[(0, "0"), (20, "x"), (30, "1")]), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_activity_buckets() {
+        let clk = test_wave(&[(0, "0"), (10, "1"), (20, "0"), (30, "1")]);
+        let data = test_wave(&[(0, "00"), (35, "01"), (39, "10")]);
+        assert_eq!(
+            activity_buckets([&clk, &data].into_iter(), 0.0..40.0, 4),
+            [2, 1, 1, 3]
+        );
+        // Changes outside the range aren't counted.
+        assert_eq!(activity_buckets([&clk].into_iter(), 5.0..25.0, 2), [1, 1]);
+    }
+
+    #[test]
+    fn test_x_to_time() {
+        let space = Rect::from_x_y_ranges(100.0..=300.0, 0.0..=50.0);
+        assert_eq!(x_to_time(100.0, 10.0..30.0, space), 10.0);
+        assert_eq!(x_to_time(250.0, 10.0..30.0, space), 25.0);
+        assert_eq!(x_to_time(300.0, 10.0..30.0, space), 30.0);
+
+        for x in [100.0, 175.0, 300.0] {
+            assert_eq!(
+                time_to_x(x_to_time(x, 10.0..30.0, space), 10.0..30.0, space),
+                x
+            );
+        }
+    }
+
+    #[test]
+    fn test_cursor_delta_label() {
+        assert_eq!(cursor_delta_label(10, 35, -9), "|Δt| = 25ns");
+        assert_eq!(cursor_delta_label(3500, 1000, -12), "|Δt| = 2.5ns");
+        assert_eq!(cursor_delta_label(7, 7, -9), "|Δt| = 0fs");
+    }
+
+    #[test]
+    fn test_clamp_timespan() {
+        // A file with a single timestamp.
+        assert_eq!(clamp_timespan(5.0..5.0, 5.0..5.0), 4.5..5.5);
+        assert_eq!(clamp_timespan(5.0..5.25, 0.0..100.0), 4.625..5.625);
+        assert_eq!(clamp_timespan(-10.0..20.0, -100.0..100.0), -10.0..20.0);
+
+        // It is moved back inside the file.
+        assert_eq!(clamp_timespan(-10.0..20.0, 0.0..100.0), 0.0..30.0);
+        assert_eq!(clamp_timespan(90.0..120.0, 0.0..100.0), 70.0..100.0);
+        assert_eq!(clamp_timespan(99.8..100.2, 0.0..100.0), 99.0..100.0);
+        // And shrunk if it's wider.
+        assert_eq!(clamp_timespan(-50.0..150.0, 0.0..100.0), 0.0..100.0);
+        // Before a file is loaded.
+        assert_eq!(clamp_timespan(0.0..0.0, 0.0..0.0), -0.5..0.5);
+
+        // A single timestamp too big for 1 to make a difference, e.g. 1e17 fs.
+        let timespan = clamp_timespan(1e17..1e17, 1e17..1e17);
+        assert!(timespan.start < 1e17 && timespan.end > 1e17, "{timespan:?}");
+        let timespan = clamp_timespan(timespan, 1e17..1e17);
+        assert!(timespan.start < 1e17 && timespan.end > 1e17, "{timespan:?}");
     }
 }